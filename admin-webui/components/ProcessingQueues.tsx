@@ -9,7 +9,9 @@ interface QueueItem {
   total_chunks: number
   percentage: number
   details: string | null
-  status: string
+  status: string // 'initializing' | 'queued' | 'processing' | 'completed' | 'failed'
+  error: string | null
+  attempts: number
   video_name: string | null
   created_at: number // Unix timestamp in milliseconds for queue ordering
 }
@@ -79,13 +81,7 @@ export default function ProcessingQueues() {
     return () => clearInterval(interval)
   }, [fetchQueues, queues?.active_count])
 
-  const isCancellable = (item: QueueItem) => {
-    const cancellableStages = ['Initializing upload', 'Queued for processing', 'Receiving chunks']
-    return (
-      item.status === 'initializing' ||
-      (item.status === 'processing' && cancellableStages.includes(item.stage))
-    )
-  }
+  const isCancellable = (item: QueueItem) => item.status !== 'completed' && item.status !== 'failed'
 
   const handleCancel = async (uploadId: string) => {
     setCancellingIds((prev) => new Set(prev).add(uploadId))
@@ -209,6 +205,8 @@ export default function ProcessingQueues() {
         return <span className='badge badge-primary badge-sm'>Processing</span>
       case 'initializing':
         return <span className='badge badge-info badge-sm'>Initializing</span>
+      case 'queued':
+        return <span className='badge badge-ghost badge-sm'>Queued</span>
       case 'completed':
         return <span className='badge badge-success badge-sm'>Completed</span>
       case 'failed':
@@ -218,7 +216,7 @@ export default function ProcessingQueues() {
     }
   }
 
-  const activeItems = queues?.items.filter((i) => i.status === 'processing' || i.status === 'initializing') || []
+  const activeItems = queues?.items.filter((i) => ['initializing', 'queued', 'processing'].includes(i.status)) || []
   const completedItems = queues?.items.filter((i) => i.status === 'completed') || []
   const failedItems = queues?.items.filter((i) => i.status === 'failed') || []
   const activeCount = queues?.active_count ?? 0
@@ -371,7 +369,7 @@ export default function ProcessingQueues() {
                               className='btn btn-ghost btn-xs text-error'
                               onClick={() => handleCancel(item.upload_id)}
                               disabled={cancellingIds.has(item.upload_id)}
-                              title='Cancel this upload'
+                              title={item.status === 'processing' ? 'Stop processing and discard this video' : 'Cancel this upload'}
                             >
                               {cancellingIds.has(item.upload_id) ? (
                                 <span className='loading loading-spinner loading-xs'></span>
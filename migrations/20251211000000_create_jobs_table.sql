@@ -0,0 +1,22 @@
+-- Durable processing queue so uploads survive server restarts
+CREATE TABLE IF NOT EXISTS jobs (
+    id TEXT PRIMARY KEY NOT NULL,         -- Upload ID supplied by the client (X-Upload-ID)
+    video_id TEXT,                        -- Output video ID, assigned when the job is queued
+    video_name TEXT,
+    tags TEXT NOT NULL DEFAULT '[]',
+    source_path TEXT,                     -- Assembled source file on local disk
+    stage TEXT NOT NULL,
+    status TEXT NOT NULL,                 -- 'initializing', 'queued', 'processing', 'completed', 'failed'
+    current_chunk INTEGER NOT NULL DEFAULT 0,
+    total_chunks INTEGER NOT NULL DEFAULT 1,
+    percentage INTEGER NOT NULL DEFAULT 0,
+    details TEXT,
+    error TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    player_url TEXT,
+    created_at INTEGER NOT NULL,          -- Unix timestamp in milliseconds
+    updated_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_jobs_status ON jobs(status);
+CREATE INDEX IF NOT EXISTS idx_jobs_created_at ON jobs(created_at);
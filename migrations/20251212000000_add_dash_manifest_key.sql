@@ -0,0 +1,3 @@
+-- DASH manifest (manifest.mpd) sharing the CMAF segments of the HLS renditions.
+-- NULL for videos encoded with MPEG-TS segments, which have no DASH output.
+ALTER TABLE videos ADD COLUMN dash_manifest_key TEXT;
@@ -0,0 +1,3 @@
+-- Per-title ladder: measured source complexity and the rungs (JSON) the video was encoded with
+ALTER TABLE videos ADD COLUMN complexity REAL;
+ALTER TABLE videos ADD COLUMN ladder TEXT;
@@ -0,0 +1,2 @@
+-- Audio renditions (JSON) encoded for each track, e.g. stereo AAC plus 5.1 AAC/E-AC-3
+ALTER TABLE audio_tracks ADD COLUMN renditions TEXT;
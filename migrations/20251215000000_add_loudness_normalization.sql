@@ -0,0 +1,3 @@
+-- Per-upload processing options (JSON) and the loudness normalization result of each audio track
+ALTER TABLE jobs ADD COLUMN options TEXT NOT NULL DEFAULT '{}';
+ALTER TABLE audio_tracks ADD COLUMN loudness TEXT;
@@ -0,0 +1,2 @@
+-- R2 key of the main (stereo) HLS playlist of each audio track
+ALTER TABLE audio_tracks ADD COLUMN playlist_key TEXT;
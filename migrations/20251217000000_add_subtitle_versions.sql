@@ -0,0 +1,2 @@
+-- Previous file of a subtitle track, kept after a retime or replace so it can be reverted
+ALTER TABLE subtitles ADD COLUMN previous_storage_key TEXT;
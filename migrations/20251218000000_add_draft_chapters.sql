@@ -0,0 +1,2 @@
+-- Chapters proposed by scene/silence detection stay drafts until an admin accepts them
+ALTER TABLE chapters ADD COLUMN is_draft INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Sprite sheet layout of the storyboard (JSON StoryboardLayout); NULL for videos encoded with
+-- the single 10x10 sprite
+ALTER TABLE videos ADD COLUMN storyboard TEXT;
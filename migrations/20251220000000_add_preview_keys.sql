@@ -0,0 +1,3 @@
+-- Hover preview clips (animated WebP and MP4); NULL for older videos or failed generation
+ALTER TABLE videos ADD COLUMN preview_webp_key TEXT;
+ALTER TABLE videos ADD COLUMN preview_mp4_key TEXT;
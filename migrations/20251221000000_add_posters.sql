@@ -0,0 +1,2 @@
+-- Multi-size poster set (JSON list of PosterImage: width and key, format from the extension)
+ALTER TABLE videos ADD COLUMN posters TEXT;
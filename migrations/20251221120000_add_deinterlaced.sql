@@ -0,0 +1,2 @@
+-- Field order the source was deinterlaced with ("tff" or "bff"); NULL for progressive sources
+ALTER TABLE videos ADD COLUMN deinterlaced TEXT;
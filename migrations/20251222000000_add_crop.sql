@@ -0,0 +1,2 @@
+-- Black bar crop applied before scaling (JSON CropRect: width, height, x, y in source pixels)
+ALTER TABLE videos ADD COLUMN crop TEXT;
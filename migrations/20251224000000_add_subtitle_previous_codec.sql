@@ -0,0 +1,3 @@
+-- Codec and VobSub index of the previous subtitle file, restored together with it on a revert
+ALTER TABLE subtitles ADD COLUMN previous_codec TEXT;
+ALTER TABLE subtitles ADD COLUMN previous_idx_storage_key TEXT;
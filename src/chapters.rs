@@ -0,0 +1,240 @@
+use crate::config::AutoChaptersConfig;
+use crate::types::ChapterInfo;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Scene detection runs on a downscaled copy; the scene score barely depends on resolution
+const ANALYSIS_WIDTH: u32 = 320;
+/// A scene change this close to a silence counts as falling into it
+const SILENCE_MATCH_SECONDS: f64 = 1.5;
+/// Weight of a scene change that coincides with a silence, on top of its scene score
+const SILENCE_BONUS: f64 = 1.0;
+/// Weight of a long silence without a scene change (screen recordings often only pause)
+const SILENCE_ONLY_WEIGHT: f64 = 0.5;
+
+/// A stretch of silence reported by `silencedetect`
+#[derive(Clone, Copy, Debug)]
+struct Silence {
+    start: f64,
+    end: f64,
+}
+
+/// Propose draft chapters for a source without embedded chapters: scene changes are scored,
+/// boosted where they fall into a silence, and the best boundaries at least
+/// `min_chapter_seconds` apart are kept. Returns no chapters when fewer than two would result.
+pub async fn detect_chapters(
+    input: &Path,
+    duration: u32,
+    has_audio: bool,
+    config: &AutoChaptersConfig,
+    cancel: &CancellationToken,
+) -> Result<Vec<ChapterInfo>> {
+    let duration = duration as f64;
+    let min_length = config.min_chapter_seconds as f64;
+    if duration < min_length * 2.0 {
+        return Ok(Vec::new());
+    }
+
+    let scenes = tokio::select! {
+        scenes = detect_scenes(input, config.scene_threshold) => scenes?,
+        _ = cancel.cancelled() => anyhow::bail!("Chapter detection cancelled"),
+    };
+    let silences = if has_audio {
+        tokio::select! {
+            silences = detect_silences(input, config) => silences?,
+            _ = cancel.cancelled() => anyhow::bail!("Chapter detection cancelled"),
+        }
+    } else {
+        Vec::new()
+    };
+
+    let boundaries = pick_boundaries(&scenes, &silences, duration, config);
+
+    info!(
+        "Chapter detection: {} scene changes, {} silences, {} chapter boundaries",
+        scenes.len(),
+        silences.len(),
+        boundaries.len()
+    );
+
+    if boundaries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let starts = std::iter::once(0.0).chain(boundaries.iter().copied());
+    let ends = boundaries.iter().copied().chain(std::iter::once(duration));
+
+    Ok(starts
+        .zip(ends)
+        .enumerate()
+        .map(|(index, (start_time, end_time))| ChapterInfo {
+            start_time,
+            end_time,
+            title: format!("Chapter {}", index + 1),
+            is_draft: true,
+        })
+        .collect())
+}
+
+/// (time, scene score) of every frame whose scene score exceeds `threshold`
+async fn detect_scenes(input: &Path, threshold: f64) -> Result<Vec<(f64, f64)>> {
+    let output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg("0:v:0")
+        .arg("-an")
+        .arg("-sn")
+        .arg("-vf")
+        .arg(format!(
+            "scale={}:-2,select='gt(scene,{})',metadata=print:file=-",
+            ANALYSIS_WIDTH, threshold
+        ))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await
+        .context("failed to run ffmpeg scene detection")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg scene detection failed: {}", stderr);
+    }
+
+    // metadata=print writes "frame:N pts:P pts_time:T" followed by "lavfi.scene_score=S"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut scenes = Vec::new();
+    let mut time = None;
+    for line in stdout.lines() {
+        if let Some(pts_time) = line
+            .split_whitespace()
+            .find_map(|f| f.strip_prefix("pts_time:"))
+        {
+            time = pts_time.parse::<f64>().ok();
+        } else if let Some(score) = line.trim().strip_prefix("lavfi.scene_score=")
+            && let (Some(time), Ok(score)) = (time.take(), score.parse::<f64>())
+        {
+            scenes.push((time, score));
+        }
+    }
+
+    Ok(scenes)
+}
+
+/// Silences of the first audio track, from `silencedetect` on stderr
+async fn detect_silences(input: &Path, config: &AutoChaptersConfig) -> Result<Vec<Silence>> {
+    let output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg("0:a:0")
+        .arg("-vn")
+        .arg("-sn")
+        .arg("-af")
+        .arg(format!(
+            "silencedetect=noise={}dB:d={}",
+            config.silence_noise_db, config.silence_min_seconds
+        ))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await
+        .context("failed to run ffmpeg silence detection")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg silence detection failed: {}", stderr);
+    }
+
+    // "silence_start: 12.3" ... "silence_end: 15.1 | silence_duration: 2.8"
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut silences = Vec::new();
+    let mut start = None;
+    for line in stderr.lines() {
+        if let Some(value) = field_after(line, "silence_start:") {
+            start = Some(value.max(0.0));
+        } else if let Some(end) = field_after(line, "silence_end:")
+            && let Some(start) = start.take()
+        {
+            silences.push(Silence { start, end });
+        }
+    }
+
+    Ok(silences)
+}
+
+fn field_after(line: &str, key: &str) -> Option<f64> {
+    let rest = &line[line.find(key)? + key.len()..];
+    rest.split_whitespace().next()?.parse().ok()
+}
+
+/// Greedily keep the highest weighted candidates that leave every chapter at least
+/// `min_chapter_seconds` long, up to `max_chapters` chapters
+fn pick_boundaries(
+    scenes: &[(f64, f64)],
+    silences: &[Silence],
+    duration: f64,
+    config: &AutoChaptersConfig,
+) -> Vec<f64> {
+    let min_length = config.min_chapter_seconds as f64;
+    let in_silence = |time: f64, silence: &Silence| {
+        time >= silence.start - SILENCE_MATCH_SECONDS && time <= silence.end + SILENCE_MATCH_SECONDS
+    };
+
+    let mut candidates: Vec<(f64, f64)> = scenes
+        .iter()
+        .map(|&(time, score)| {
+            if silences.iter().any(|s| in_silence(time, s)) {
+                (time, score + SILENCE_BONUS)
+            } else {
+                (time, score)
+            }
+        })
+        .collect();
+    // A silence without a cut still marks a pause; the chapter starts when the sound resumes
+    for silence in silences {
+        if !scenes.iter().any(|&(time, _)| in_silence(time, silence)) {
+            let length_bonus = ((silence.end - silence.start) / 10.0).min(0.5);
+            candidates.push((silence.end, SILENCE_ONLY_WEIGHT + length_bonus));
+        }
+    }
+
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let max_boundaries = config.max_chapters.saturating_sub(1) as usize;
+    let mut boundaries: Vec<f64> = Vec::new();
+    for (time, _) in candidates {
+        if boundaries.len() >= max_boundaries {
+            break;
+        }
+        let fits = time >= min_length
+            && duration - time >= min_length
+            && boundaries.iter().all(|b| (b - time).abs() >= min_length);
+        if fits {
+            boundaries.push(time);
+        }
+    }
+
+    boundaries.sort_by(|a, b| a.total_cmp(b));
+    // Whole milliseconds keep the stored times tidy
+    boundaries
+        .into_iter()
+        .map(|time| (time * 1000.0).round() / 1000.0)
+        .collect()
+}
@@ -1,5 +1,7 @@
+use crate::video::VideoCodec;
 use anyhow::{Context, Result};
 use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use tokio::fs;
 
@@ -34,6 +36,275 @@ pub struct R2Config {
 #[derive(Clone, Debug, Deserialize)]
 pub struct VideoConfig {
     pub encoder: String,
+    /// Extra encoders (e.g. "libx265", "hevc_nvenc", "libsvtav1", "av1_nvenc") whose ladders are
+    /// produced alongside the H.264 ladder from `encoder`
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    /// HLS segment packaging for the H.264 ladder and audio
+    #[serde(default)]
+    pub segment_format: SegmentFormat,
+    /// Size the ladder from a complexity analysis of each upload instead of the fixed BPP table.
+    /// Ignored when `ladder` is set, so configured rungs keep their own rates.
+    #[serde(default)]
+    pub per_title_ladder: bool,
+    /// Decode the source once and encode every rung from one ffmpeg process (`split` filter graph)
+    /// instead of one process per rung. Faster for heavy sources, but uses more memory at once.
+    #[serde(default)]
+    pub single_decode: bool,
+    /// For HDR (PQ/HLG) sources, keep a 10-bit HEVC HDR rendition of the top rung next to the
+    /// tone-mapped SDR ladder
+    #[serde(default)]
+    pub hdr_rendition: bool,
+    /// Bitrate ladder, lowest rung first. Empty means the built-in 480p-2160p ladder.
+    #[serde(default)]
+    pub ladder: Vec<LadderRung>,
+    /// HLS segment duration in seconds
+    #[serde(default = "default_segment_duration")]
+    pub segment_duration: u32,
+    /// Keyframe interval in seconds; must divide `segment_duration`
+    #[serde(default = "default_gop_seconds")]
+    pub gop_seconds: u32,
+    /// Preset per FFmpeg encoder name (e.g. `libx264: "medium"`, `h264_nvenc: "p5"`)
+    #[serde(default)]
+    pub presets: HashMap<String, String>,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    #[serde(default)]
+    pub auto_chapters: AutoChaptersConfig,
+    #[serde(default)]
+    pub auto_crop: AutoCropConfig,
+}
+
+fn default_segment_duration() -> u32 {
+    4
+}
+
+fn default_gop_seconds() -> u32 {
+    2
+}
+
+/// One rung of a configured ladder
+#[derive(Clone, Debug, Deserialize)]
+pub struct LadderRung {
+    /// Rendition name, also used as its output directory (e.g. "720p")
+    pub label: String,
+    /// Short edge of the output in pixels (the height for landscape video)
+    pub height: u32,
+    /// Target bitrate in kbps; derived from the resolution when omitted
+    #[serde(default)]
+    pub bitrate: Option<u32>,
+    /// Constant quality (x264 CRF scale) instead of a target bitrate, capped at `maxrate`
+    #[serde(default)]
+    pub crf: Option<u32>,
+    /// Peak bitrate in kbps; 1.5x the bitrate when omitted
+    #[serde(default)]
+    pub maxrate: Option<u32>,
+    /// H.264 profile ("baseline", "main" or "high")
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// H.264 level (e.g. "3.1", "4.1")
+    #[serde(default)]
+    pub level: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AudioConfig {
+    /// AAC bitrate in kbps
+    #[serde(default = "default_audio_bitrate")]
+    pub bitrate: u32,
+    /// Output sample rate in Hz; the source rate is kept when omitted
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    /// Add a 5.1 rendition next to the stereo one for multichannel sources
+    #[serde(default = "default_surround")]
+    pub surround: bool,
+    #[serde(default)]
+    pub surround_codec: SurroundCodec,
+    /// Bitrate of the 5.1 rendition in kbps
+    #[serde(default = "default_surround_bitrate")]
+    pub surround_bitrate: u32,
+    #[serde(default)]
+    pub loudnorm: LoudnormConfig,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: default_audio_bitrate(),
+            sample_rate: None,
+            surround: default_surround(),
+            surround_codec: SurroundCodec::default(),
+            surround_bitrate: default_surround_bitrate(),
+            loudnorm: LoudnormConfig::default(),
+        }
+    }
+}
+
+/// Two-pass EBU R128 loudness normalization (ffmpeg `loudnorm`)
+#[derive(Clone, Debug, Deserialize)]
+pub struct LoudnormConfig {
+    /// Normalize uploads unless the upload turns it off (or on, when this is false)
+    #[serde(default)]
+    pub enabled: bool,
+    /// Integrated loudness target in LUFS
+    #[serde(default = "default_loudness_target")]
+    pub target: f64,
+    /// Maximum true peak in dBTP
+    #[serde(default = "default_true_peak")]
+    pub true_peak: f64,
+    /// Loudness range target in LU
+    #[serde(default = "default_loudness_range")]
+    pub loudness_range: f64,
+}
+
+impl Default for LoudnormConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            target: default_loudness_target(),
+            true_peak: default_true_peak(),
+            loudness_range: default_loudness_range(),
+        }
+    }
+}
+
+fn default_loudness_target() -> f64 {
+    -16.0
+}
+
+fn default_true_peak() -> f64 {
+    -1.5
+}
+
+fn default_loudness_range() -> f64 {
+    11.0
+}
+
+fn default_audio_bitrate() -> u32 {
+    128
+}
+
+fn default_surround() -> bool {
+    true
+}
+
+fn default_surround_bitrate() -> u32 {
+    384
+}
+
+/// Draft chapters proposed from scene changes and silences for sources without chapters
+#[derive(Clone, Debug, Deserialize)]
+pub struct AutoChaptersConfig {
+    /// Analyze uploads unless the upload turns it off (or on, when this is false)
+    #[serde(default)]
+    pub enabled: bool,
+    /// ffmpeg scene score (0-1) above which a frame counts as a scene change
+    #[serde(default = "default_scene_threshold")]
+    pub scene_threshold: f64,
+    /// Audio below this level in dB counts as silence
+    #[serde(default = "default_silence_noise_db")]
+    pub silence_noise_db: f64,
+    /// Shortest silence in seconds worth considering
+    #[serde(default = "default_silence_min_seconds")]
+    pub silence_min_seconds: f64,
+    /// Shortest chapter in seconds
+    #[serde(default = "default_min_chapter_seconds")]
+    pub min_chapter_seconds: u32,
+    #[serde(default = "default_max_chapters")]
+    pub max_chapters: u32,
+}
+
+impl Default for AutoChaptersConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            scene_threshold: default_scene_threshold(),
+            silence_noise_db: default_silence_noise_db(),
+            silence_min_seconds: default_silence_min_seconds(),
+            min_chapter_seconds: default_min_chapter_seconds(),
+            max_chapters: default_max_chapters(),
+        }
+    }
+}
+
+fn default_scene_threshold() -> f64 {
+    0.3
+}
+
+fn default_silence_noise_db() -> f64 {
+    -35.0
+}
+
+fn default_silence_min_seconds() -> f64 {
+    1.0
+}
+
+fn default_min_chapter_seconds() -> u32 {
+    60
+}
+
+fn default_max_chapters() -> u32 {
+    20
+}
+
+/// Black bar (letterbox/pillarbox) removal with ffmpeg `cropdetect`
+#[derive(Clone, Debug, Deserialize)]
+pub struct AutoCropConfig {
+    /// Detect and crop bars unless the upload turns it off (or on, when this is false)
+    #[serde(default)]
+    pub enabled: bool,
+    /// Luma (0-255, scaled for 10-bit sources) up to which a pixel counts as black
+    #[serde(default = "default_crop_limit")]
+    pub limit: u32,
+    /// Number of positions sampled across the video
+    #[serde(default = "default_crop_samples")]
+    pub samples: u32,
+    /// Bars thinner than this many pixels (per axis, both sides together) are left in place
+    #[serde(default = "default_crop_min_bar")]
+    pub min_bar: u32,
+}
+
+impl Default for AutoCropConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            limit: default_crop_limit(),
+            samples: default_crop_samples(),
+            min_bar: default_crop_min_bar(),
+        }
+    }
+}
+
+fn default_crop_limit() -> u32 {
+    24
+}
+
+fn default_crop_samples() -> u32 {
+    10
+}
+
+fn default_crop_min_bar() -> u32 {
+    8
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SurroundCodec {
+    /// 5.1 AAC-LC, decodable wherever stereo AAC is
+    #[default]
+    Aac,
+    /// Dolby Digital Plus, preferred by Apple TV and smart TVs
+    Eac3,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SegmentFormat {
+    /// MPEG-TS `.ts` segments
+    #[default]
+    Mpegts,
+    /// fMP4 (CMAF) `init.mp4` + `.m4s` segments
+    Fmp4,
 }
 
 impl Config {
@@ -43,6 +314,161 @@ impl Config {
             .context("Failed to read config file")?;
         let config: Config =
             serde_yaml::from_str(&content).context("Failed to parse config file")?;
+        config.validate()?;
         Ok(config)
     }
+
+    /// Reject settings that would only fail later, in the middle of an encode
+    fn validate(&self) -> Result<()> {
+        for encoder in &self.video.codecs {
+            if VideoCodec::from_encoder(encoder).is_none() {
+                anyhow::bail!(
+                    "video.codecs: unsupported encoder '{}' (expected an H.264, HEVC or AV1 encoder)",
+                    encoder
+                );
+            }
+        }
+
+        self.validate_ladder()?;
+
+        let video = &self.video;
+        if !(1..=30).contains(&video.segment_duration) {
+            anyhow::bail!("video.segment_duration must be between 1 and 30 seconds");
+        }
+        if video.gop_seconds == 0 || !video.segment_duration.is_multiple_of(video.gop_seconds) {
+            anyhow::bail!(
+                "video.gop_seconds ({}) must divide video.segment_duration ({}) so segments start on a keyframe",
+                video.gop_seconds,
+                video.segment_duration
+            );
+        }
+
+        for (encoder, preset) in &video.presets {
+            if VideoCodec::from_encoder(encoder).is_none() {
+                anyhow::bail!("video.presets: unknown encoder '{}'", encoder);
+            }
+            if preset.trim().is_empty() {
+                anyhow::bail!("video.presets: empty preset for '{}'", encoder);
+            }
+        }
+
+        if !(32..=512).contains(&video.audio.bitrate) {
+            anyhow::bail!("video.audio.bitrate must be between 32 and 512 kbps");
+        }
+        if !(96..=1024).contains(&video.audio.surround_bitrate) {
+            anyhow::bail!("video.audio.surround_bitrate must be between 96 and 1024 kbps");
+        }
+        let loudnorm = &video.audio.loudnorm;
+        if !(-70.0..=-5.0).contains(&loudnorm.target) {
+            anyhow::bail!("video.audio.loudnorm.target must be between -70 and -5 LUFS");
+        }
+        if !(-9.0..=0.0).contains(&loudnorm.true_peak) {
+            anyhow::bail!("video.audio.loudnorm.true_peak must be between -9 and 0 dBTP");
+        }
+        if !(1.0..=50.0).contains(&loudnorm.loudness_range) {
+            anyhow::bail!("video.audio.loudnorm.loudness_range must be between 1 and 50 LU");
+        }
+        let auto_chapters = &video.auto_chapters;
+        if !(0.05..=1.0).contains(&auto_chapters.scene_threshold) {
+            anyhow::bail!("video.auto_chapters.scene_threshold must be between 0.05 and 1");
+        }
+        if !(-90.0..=-10.0).contains(&auto_chapters.silence_noise_db) {
+            anyhow::bail!("video.auto_chapters.silence_noise_db must be between -90 and -10 dB");
+        }
+        if !(0.1..=30.0).contains(&auto_chapters.silence_min_seconds) {
+            anyhow::bail!("video.auto_chapters.silence_min_seconds must be between 0.1 and 30");
+        }
+        if !(10..=3600).contains(&auto_chapters.min_chapter_seconds) {
+            anyhow::bail!("video.auto_chapters.min_chapter_seconds must be between 10 and 3600");
+        }
+        if !(2..=100).contains(&auto_chapters.max_chapters) {
+            anyhow::bail!("video.auto_chapters.max_chapters must be between 2 and 100");
+        }
+        let auto_crop = &video.auto_crop;
+        if !(1..=128).contains(&auto_crop.limit) {
+            anyhow::bail!("video.auto_crop.limit must be between 1 and 128");
+        }
+        if !(1..=50).contains(&auto_crop.samples) {
+            anyhow::bail!("video.auto_crop.samples must be between 1 and 50");
+        }
+        if auto_crop.min_bar > 256 {
+            anyhow::bail!("video.auto_crop.min_bar must be at most 256 pixels");
+        }
+        if let Some(rate) = video.audio.sample_rate
+            && ![22050, 32000, 44100, 48000].contains(&rate)
+        {
+            anyhow::bail!(
+                "video.audio.sample_rate {} is not supported (22050, 32000, 44100 or 48000)",
+                rate
+            );
+        }
+
+        Ok(())
+    }
+
+    fn validate_ladder(&self) -> Result<()> {
+        let mut labels = HashSet::new();
+        let mut previous_height = 0;
+
+        for rung in &self.video.ladder {
+            let name = format!("video.ladder '{}'", rung.label);
+
+            let valid_label = !rung.label.is_empty()
+                && !rung.label.starts_with("audio_")
+                && !rung.label.starts_with("subs_")
+                && rung
+                    .label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            if !valid_label {
+                anyhow::bail!(
+                    "{}: labels must be non-empty [A-Za-z0-9_-] and not start with 'audio_' or 'subs_'",
+                    name
+                );
+            }
+            if !labels.insert(rung.label.as_str()) {
+                anyhow::bail!("{}: duplicate label", name);
+            }
+
+            if !(144..=4320).contains(&rung.height) || !rung.height.is_multiple_of(2) {
+                anyhow::bail!("{}: height must be an even number between 144 and 4320", name);
+            }
+            if rung.height <= previous_height {
+                anyhow::bail!("{}: rungs must be listed from the lowest height up", name);
+            }
+            previous_height = rung.height;
+
+            if rung.bitrate.is_some() && rung.crf.is_some() {
+                anyhow::bail!("{}: set either bitrate or crf, not both", name);
+            }
+            if rung.bitrate == Some(0) || rung.maxrate == Some(0) {
+                anyhow::bail!("{}: bitrates must be greater than zero", name);
+            }
+            if let Some(crf) = rung.crf
+                && crf > 51
+            {
+                anyhow::bail!("{}: crf must be between 0 and 51", name);
+            }
+            if let (Some(bitrate), Some(maxrate)) = (rung.bitrate, rung.maxrate)
+                && maxrate < bitrate
+            {
+                anyhow::bail!("{}: maxrate must not be lower than bitrate", name);
+            }
+            if let Some(profile) = &rung.profile
+                && !matches!(profile.as_str(), "baseline" | "main" | "high")
+            {
+                anyhow::bail!("{}: profile must be baseline, main or high", name);
+            }
+            if let Some(level) = &rung.level {
+                let valid = level
+                    .parse::<f64>()
+                    .is_ok_and(|l| (1.0..=6.2).contains(&l));
+                if !valid {
+                    anyhow::bail!("{}: level must be an H.264 level such as 3.1 or 4.1", name);
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
@@ -0,0 +1,229 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+use tokio::fs;
+
+/// Timescale of the generated SegmentTimeline (milliseconds)
+const TIMESCALE: u64 = 1000;
+
+/// A video rendition that already exists on disk as fMP4 HLS output
+pub struct DashVideo {
+    /// Directory (relative to the video root) holding init.mp4 and the .m4s segments
+    pub dir: String,
+    /// Groups renditions of the same codec into one AdaptationSet (e.g. "avc1", "hvc1");
+    /// HDR renditions use a family of their own
+    pub codec_family: &'static str,
+    pub codecs: String,
+    pub bandwidth: u32,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+    /// CICP TransferCharacteristics of HDR renditions (16 = PQ, 18 = HLG)
+    pub transfer_characteristics: Option<u8>,
+}
+
+/// An audio rendition that already exists on disk as fMP4 HLS output
+pub struct DashAudio {
+    pub dir: String,
+    pub language: String,
+    pub codecs: String,
+    pub bandwidth: u32,
+    pub channels: u32,
+    pub is_default: bool,
+}
+
+/// Write `manifest.mpd` into `out_dir`, referencing the same CMAF segments as the HLS playlists.
+/// Segment durations are taken from each rendition's `index.m3u8`.
+pub async fn write_dash_manifest(
+    out_dir: &Path,
+    duration: u32,
+    videos: &[DashVideo],
+    audios: &[DashAudio],
+) -> Result<()> {
+    let mut mpd = String::new();
+    mpd.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        mpd,
+        "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-live:2011\" \
+         type=\"static\" mediaPresentationDuration=\"PT{}S\" minBufferTime=\"PT4S\">",
+        duration
+    );
+    mpd.push_str("  <Period id=\"0\" start=\"PT0S\">\n");
+
+    let mut set_id = 0;
+
+    // One AdaptationSet per video codec so players switch bitrates within a codec only
+    let mut families: Vec<&str> = Vec::new();
+    for video in videos {
+        if !families.contains(&video.codec_family) {
+            families.push(video.codec_family);
+        }
+    }
+
+    for family in families {
+        let _ = writeln!(
+            mpd,
+            "    <AdaptationSet id=\"{}\" contentType=\"video\" mimeType=\"video/mp4\" segmentAlignment=\"true\" startWithSAP=\"1\">",
+            set_id
+        );
+        set_id += 1;
+
+        if let Some(transfer) = videos
+            .iter()
+            .find(|v| v.codec_family == family)
+            .and_then(|v| v.transfer_characteristics)
+        {
+            let _ = writeln!(
+                mpd,
+                "      <SupplementalProperty schemeIdUri=\"urn:mpeg:mpegB:cicp:TransferCharacteristics\" value=\"{}\"/>",
+                transfer
+            );
+        }
+
+        for video in videos.iter().filter(|v| v.codec_family == family) {
+            let _ = writeln!(
+                mpd,
+                "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\" width=\"{}\" height=\"{}\" frameRate=\"{}/1000\" sar=\"1:1\">",
+                video.dir,
+                video.codecs,
+                video.bandwidth,
+                video.width,
+                video.height,
+                (video.frame_rate * 1000.0).round() as u64
+            );
+            push_segment_template(&mut mpd, out_dir, &video.dir).await?;
+            mpd.push_str("      </Representation>\n");
+        }
+
+        mpd.push_str("    </AdaptationSet>\n");
+    }
+
+    for audio in audios {
+        let _ = writeln!(
+            mpd,
+            "    <AdaptationSet id=\"{}\" contentType=\"audio\" mimeType=\"audio/mp4\" lang=\"{}\" segmentAlignment=\"true\">",
+            set_id, audio.language
+        );
+        set_id += 1;
+
+        if audio.is_default {
+            mpd.push_str("      <Role schemeIdUri=\"urn:mpeg:dash:role:2011\" value=\"main\"/>\n");
+        }
+        let _ = writeln!(
+            mpd,
+            "      <Representation id=\"{}\" codecs=\"{}\" bandwidth=\"{}\">",
+            audio.dir, audio.codecs, audio.bandwidth
+        );
+        let _ = writeln!(
+            mpd,
+            "        <AudioChannelConfiguration schemeIdUri=\"urn:mpeg:dash:23003:3:audio_channel_configuration:2011\" value=\"{}\"/>",
+            audio.channels
+        );
+        push_segment_template(&mut mpd, out_dir, &audio.dir).await?;
+        mpd.push_str("      </Representation>\n");
+        mpd.push_str("    </AdaptationSet>\n");
+    }
+
+    mpd.push_str("  </Period>\n</MPD>\n");
+
+    fs::write(out_dir.join("manifest.mpd"), mpd)
+        .await
+        .context("failed to write DASH manifest")?;
+
+    Ok(())
+}
+
+async fn push_segment_template(mpd: &mut String, out_dir: &Path, dir: &str) -> Result<()> {
+    let playlist = fs::read_to_string(out_dir.join(dir).join("index.m3u8"))
+        .await
+        .with_context(|| format!("failed to read playlist for {}", dir))?;
+
+    let _ = writeln!(
+        mpd,
+        "        <SegmentTemplate timescale=\"{}\" initialization=\"{}/init.mp4\" media=\"{}/segment_$Number%03d$.m4s\" startNumber=\"0\">",
+        TIMESCALE, dir, dir
+    );
+    mpd.push_str("          <SegmentTimeline>\n");
+    for (duration, repeat) in segment_timeline(&playlist) {
+        if repeat > 0 {
+            let _ = writeln!(mpd, "            <S d=\"{}\" r=\"{}\"/>", duration, repeat);
+        } else {
+            let _ = writeln!(mpd, "            <S d=\"{}\"/>", duration);
+        }
+    }
+    mpd.push_str("          </SegmentTimeline>\n");
+    mpd.push_str("        </SegmentTemplate>\n");
+
+    Ok(())
+}
+
+/// Run-length encode the #EXTINF durations of a media playlist as (duration, repeat) pairs.
+/// Timestamps are accumulated before rounding so the timeline doesn't drift.
+fn segment_timeline(playlist: &str) -> Vec<(u64, u32)> {
+    let mut timeline: Vec<(u64, u32)> = Vec::new();
+    let mut elapsed = 0.0;
+    let mut last_end = 0;
+
+    for line in playlist.lines() {
+        let Some(value) = line.strip_prefix("#EXTINF:") else {
+            continue;
+        };
+        let Ok(seconds) = value.split(',').next().unwrap_or_default().trim().parse::<f64>() else {
+            continue;
+        };
+
+        elapsed += seconds;
+        let end = (elapsed * TIMESCALE as f64).round() as u64;
+        let duration = end - last_end;
+        last_end = end;
+
+        match timeline.last_mut() {
+            Some((d, repeat)) if *d == duration => *repeat += 1,
+            _ => timeline.push((duration, 0)),
+        }
+    }
+
+    timeline
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(durations: &[&str]) -> String {
+        let mut playlist = String::from("#EXTM3U\n#EXT-X-TARGETDURATION:4\n");
+        for (index, duration) in durations.iter().enumerate() {
+            let _ = writeln!(playlist, "#EXTINF:{},\nsegment_{:03}.m4s", duration, index);
+        }
+        playlist.push_str("#EXT-X-ENDLIST\n");
+        playlist
+    }
+
+    #[test]
+    fn test_segment_timeline_folds_equal_runs() {
+        let timeline = segment_timeline(&playlist(&["4.000", "4.000", "4.000", "4.000", "2.500"]));
+        assert_eq!(timeline, vec![(4000, 3), (2500, 0)]);
+    }
+
+    #[test]
+    fn test_segment_timeline_keeps_unequal_runs_apart() {
+        let timeline = segment_timeline(&playlist(&["4.004", "3.996", "3.996", "4.004"]));
+        assert_eq!(timeline, vec![(4004, 0), (3996, 1), (4004, 0)]);
+    }
+
+    #[test]
+    fn test_segment_timeline_rounds_without_drift() {
+        let timeline = segment_timeline(&playlist(&["3.3333", "3.3333", "3.3333"]));
+        assert_eq!(timeline, vec![(3333, 0), (3334, 0), (3333, 0)]);
+        let total: u64 = timeline.iter().map(|(d, r)| d * (*r as u64 + 1)).sum();
+        assert_eq!(total, 10000);
+    }
+
+    #[test]
+    fn test_segment_timeline_ignores_other_lines() {
+        let playlist =
+            "#EXTM3U\n#EXTINF:4.000,title\nsegment_000.m4s\n#EXTINF:bogus,\n#EXT-X-ENDLIST\n";
+        assert_eq!(segment_timeline(playlist), vec![(4000, 0)]);
+        assert!(segment_timeline("#EXTM3U\n").is_empty());
+    }
+}
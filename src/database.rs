@@ -1,6 +1,14 @@
-use crate::types::{Attachment, AudioTrack, Chapter, SubtitleTrack, VideoDto, VideoQuery};
+use crate::handlers::common::now_millis;
+use crate::storyboard::{STORYBOARD_DIR, STORYBOARD_TRACK};
+use crate::thumbnail::poster_mime;
+use crate::types::{
+    Attachment, AudioLoudness, AudioRendition, AudioTrack, Chapter, ChapterInfo, CropRect, FieldOrder, Job,
+    PosterImage,
+    PreviewUrls, ProgressUpdate, StoryboardLayout, SubtitleTrack, SubtitleVersion, UploadOptions, VideoDto, VideoQuery, VideoVariant,
+};
 use anyhow::{Context, Result};
-use sqlx::{Sqlite, SqlitePool, migrate::MigrateDatabase};
+use sqlx::{Sqlite, SqliteExecutor, SqlitePool, migrate::MigrateDatabase};
+use std::collections::BTreeMap;
 use tracing::info;
 
 pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
@@ -26,23 +34,37 @@ pub async fn initialize_database(database_url: &str) -> Result<SqlitePool> {
     Ok(db_pool)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn save_video(
-    db_pool: &SqlitePool,
+    db: impl SqliteExecutor<'_>,
     video_id: &str,
     video_name: &str,
     tags: &[String],
     available_resolutions: &[String],
     duration: u32,
     thumbnail_key: &str,
-    sprites_key: &str,
+    sprites_key: Option<&str>,
     entrypoint: &str,
+    dash_manifest_key: Option<&str>,
+    complexity: Option<f64>,
+    ladder: &[VideoVariant],
+    storyboard: Option<&StoryboardLayout>,
+    preview_webp_key: Option<&str>,
+    preview_mp4_key: Option<&str>,
+    posters: &[PosterImage],
+    crop: Option<&CropRect>,
+    deinterlaced: Option<FieldOrder>,
 ) -> Result<()> {
     let tags_json = serde_json::to_string(tags)?;
     let resolutions_json = serde_json::to_string(available_resolutions)?;
+    let ladder_json = serde_json::to_string(ladder)?;
+    let storyboard_json = storyboard.map(serde_json::to_string).transpose()?;
+    let posters_json = serde_json::to_string(posters)?;
+    let crop_json = crop.map(serde_json::to_string).transpose()?;
 
     sqlx
          ::query(
-             "INSERT INTO videos (id, name, tags, available_resolutions, duration, thumbnail_key, sprites_key, entrypoint) VALUES (?, ?, ?, ?, ?, ?, ?, ?)"
+             "INSERT INTO videos (id, name, tags, available_resolutions, duration, thumbnail_key, sprites_key, entrypoint, dash_manifest_key, complexity, ladder, storyboard, preview_webp_key, preview_mp4_key, posters, crop, deinterlaced) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
          )
          .bind(video_id)
          .bind(video_name)
@@ -52,7 +74,16 @@ pub async fn save_video(
          .bind(thumbnail_key)
          .bind(sprites_key)
          .bind(entrypoint)
-         .execute(db_pool).await?;
+         .bind(dash_manifest_key)
+         .bind(complexity)
+         .bind(&ladder_json)
+         .bind(&storyboard_json)
+         .bind(preview_webp_key)
+         .bind(preview_mp4_key)
+         .bind(&posters_json)
+         .bind(&crop_json)
+         .bind(deinterlaced.map(|order| order.parity()))
+         .execute(db).await?;
 
     info!(
         "Video saved to database: id={}, name={}",
@@ -73,6 +104,19 @@ pub struct VideoRow {
     pub thumbnail_key: String,
     pub sprites_key: Option<String>,
     pub entrypoint: String,
+    pub dash_manifest_key: Option<String>,
+    pub complexity: Option<f64>,
+    pub ladder: Option<String>,
+    /// JSON `StoryboardLayout`
+    pub storyboard: Option<String>,
+    pub preview_webp_key: Option<String>,
+    pub preview_mp4_key: Option<String>,
+    /// JSON list of `PosterImage`
+    pub posters: Option<String>,
+    /// JSON `CropRect`
+    pub crop: Option<String>,
+    /// FFmpeg parity ("tff" or "bff") the source was deinterlaced with
+    pub deinterlaced: Option<String>,
     pub created_at: String,
     pub is_public: i64,
 }
@@ -142,7 +186,7 @@ pub async fn list_videos(
     let rows: Vec<VideoRow> = match (name.as_ref(), tag) {
          (None, None) => {
              sqlx::query_as::<_, VideoRow>(
-                 "SELECT id, name, tags, available_resolutions, duration, thumbnail_key, sprites_key, entrypoint, created_at, is_public \
+                 "SELECT id, name, tags, available_resolutions, duration, thumbnail_key, sprites_key, entrypoint, dash_manifest_key, complexity, ladder, storyboard, preview_webp_key, preview_mp4_key, posters, crop, deinterlaced, created_at, is_public \
                   FROM videos \
                   ORDER BY datetime(created_at) DESC \
                   LIMIT ? OFFSET ?",
@@ -156,7 +200,7 @@ pub async fn list_videos(
              let safe_name = name.replace("\"", "");
              let pattern = format!("name:\"{}\"*", safe_name);
              sqlx::query_as::<_, VideoRow>(
-                 "SELECT v.id, v.name, v.tags, v.available_resolutions, v.duration, v.thumbnail_key, v.sprites_key, v.entrypoint, v.created_at, v.is_public \
+                 "SELECT v.id, v.name, v.tags, v.available_resolutions, v.duration, v.thumbnail_key, v.sprites_key, v.entrypoint, v.dash_manifest_key, v.complexity, v.ladder, v.storyboard, v.preview_webp_key, v.preview_mp4_key, v.posters, v.crop, v.deinterlaced, v.created_at, v.is_public \
                   FROM videos v \
                   JOIN videos_fts f ON v.id = f.id \
                   WHERE f.videos_fts MATCH ? \
@@ -173,7 +217,7 @@ pub async fn list_videos(
              let safe_tag = tag.replace("\"", "");
              let pattern = format!("tags:\"{}\"", safe_tag);
              sqlx::query_as::<_, VideoRow>(
-                 "SELECT v.id, v.name, v.tags, v.available_resolutions, v.duration, v.thumbnail_key, v.sprites_key, v.entrypoint, v.created_at, v.is_public \
+                 "SELECT v.id, v.name, v.tags, v.available_resolutions, v.duration, v.thumbnail_key, v.sprites_key, v.entrypoint, v.dash_manifest_key, v.complexity, v.ladder, v.storyboard, v.preview_webp_key, v.preview_mp4_key, v.posters, v.crop, v.deinterlaced, v.created_at, v.is_public \
                   FROM videos v \
                   JOIN videos_fts f ON v.id = f.id \
                   WHERE f.videos_fts MATCH ? \
@@ -191,7 +235,7 @@ pub async fn list_videos(
              let safe_tag = tag.replace("\"", "");
              let pattern = format!("name:\"{}\"* AND tags:\"{}\"", safe_name, safe_tag);
              sqlx::query_as::<_, VideoRow>(
-                 "SELECT v.id, v.name, v.tags, v.available_resolutions, v.duration, v.thumbnail_key, v.sprites_key, v.entrypoint, v.created_at, v.is_public \
+                 "SELECT v.id, v.name, v.tags, v.available_resolutions, v.duration, v.thumbnail_key, v.sprites_key, v.entrypoint, v.dash_manifest_key, v.complexity, v.ladder, v.storyboard, v.preview_webp_key, v.preview_mp4_key, v.posters, v.crop, v.deinterlaced, v.created_at, v.is_public \
                   FROM videos v \
                   JOIN videos_fts f ON v.id = f.id \
                   WHERE f.videos_fts MATCH ? \
@@ -215,14 +259,42 @@ pub async fn list_videos(
 
         let base = public_base_url.trim_end_matches('/');
         let thumbnail_url = format!("{}/{}", base, row.thumbnail_key);
-        let sprites_key = row
+        let sprites_url = row
             .sprites_key
             .as_deref()
-            .map(|key| key.to_string())
-            .unwrap_or_else(|| row.thumbnail_key.clone());
-        let sprites_url = format!("{}/{}", base, sprites_key);
+            .map(|key| format!("{}/{}", base, key));
         // Return player URL instead of direct HLS URL
         let player_url = format!("/player/{}", row.id);
+        // Videos encoded before per-title ladders have no stored ladder
+        let ladder: Vec<VideoVariant> = row
+            .ladder
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default();
+        // Videos encoded before storyboards only have the single 10x10 sprite
+        let storyboard: Option<StoryboardLayout> = row
+            .storyboard
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok());
+        let storyboard_url = storyboard
+            .as_ref()
+            .map(|_| format!("{}/{}/{}/{}", base, row.id, STORYBOARD_DIR, STORYBOARD_TRACK));
+        let preview_url = (row.preview_webp_key.is_some() || row.preview_mp4_key.is_some()).then(|| {
+            PreviewUrls {
+                webp: row.preview_webp_key.as_ref().map(|key| format!("{}/{}", base, key)),
+                mp4: row.preview_mp4_key.as_ref().map(|key| format!("{}/{}", base, key)),
+            }
+        });
+        let posters: Vec<PosterImage> = row
+            .posters
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .unwrap_or_default();
+        let thumbnail_srcset = poster_srcset(&posters, base);
+        let crop: Option<CropRect> = row
+            .crop
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok());
 
         result.push(VideoDto {
             id: row.id,
@@ -231,16 +303,47 @@ pub async fn list_videos(
             available_resolutions: resolutions,
             duration: row.duration as u32,
             thumbnail_url,
-            sprites_url: Some(sprites_url),
+            thumbnail_srcset,
+            sprites_url,
+            storyboard_url,
+            storyboard,
+            preview_url,
             player_url,
             created_at: row.created_at,
             is_public: row.is_public != 0,
+            complexity: row.complexity,
+            ladder,
+            crop,
+            deinterlaced: row.deinterlaced,
         });
     }
 
     Ok(result)
 }
 
+/// `srcset` strings of a poster set by MIME type, smallest width first
+fn poster_srcset(posters: &[PosterImage], base: &str) -> BTreeMap<String, String> {
+    let mut by_mime: BTreeMap<String, Vec<&PosterImage>> = BTreeMap::new();
+    for poster in posters {
+        if let Some(mime) = poster_mime(&poster.key) {
+            by_mime.entry(mime.to_string()).or_default().push(poster);
+        }
+    }
+
+    by_mime
+        .into_iter()
+        .map(|(mime, mut images)| {
+            images.sort_by_key(|image| image.width);
+            let srcset = images
+                .iter()
+                .map(|image| format!("{}/{} {}w", base, image.key, image.width))
+                .collect::<Vec<_>>()
+                .join(", ");
+            (mime, srcset)
+        })
+        .collect()
+}
+
 pub async fn update_video(
     db_pool: &SqlitePool,
     video_id: &str,
@@ -268,7 +371,7 @@ pub async fn update_video(
 
 pub async fn get_video(db_pool: &SqlitePool, video_id: &str) -> Result<VideoRow> {
     let row = sqlx::query_as::<_, VideoRow>(
-        "SELECT id, name, tags, available_resolutions, duration, thumbnail_key, sprites_key, entrypoint, created_at, is_public \
+        "SELECT id, name, tags, available_resolutions, duration, thumbnail_key, sprites_key, entrypoint, dash_manifest_key, complexity, ladder, storyboard, preview_webp_key, preview_mp4_key, posters, crop, deinterlaced, created_at, is_public \
          FROM videos \
          WHERE id = ?",
     )
@@ -300,6 +403,37 @@ pub async fn update_video_visibility(
     Ok(())
 }
 
+pub async fn set_thumbnail_key(db_pool: &SqlitePool, video_id: &str, thumbnail_key: &str) -> Result<()> {
+    let rows_affected = sqlx::query("UPDATE videos SET thumbnail_key = ? WHERE id = ?")
+        .bind(thumbnail_key)
+        .bind(video_id)
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+
+    if rows_affected == 0 {
+        anyhow::bail!("Video not found");
+    }
+
+    info!("Video thumbnail updated: id={}, key={}", video_id, thumbnail_key);
+
+    Ok(())
+}
+
+pub async fn set_posters(db_pool: &SqlitePool, video_id: &str, posters: &[PosterImage]) -> Result<()> {
+    let posters_json = serde_json::to_string(posters)?;
+
+    sqlx::query("UPDATE videos SET posters = ? WHERE id = ?")
+        .bind(&posters_json)
+        .bind(video_id)
+        .execute(db_pool)
+        .await?;
+
+    info!("Video posters updated: id={}, count={}", video_id, posters.len());
+
+    Ok(())
+}
+
 pub async fn delete_videos(db_pool: &SqlitePool, video_ids: &[String]) -> Result<u64> {
     if video_ids.is_empty() {
         return Ok(0);
@@ -361,12 +495,17 @@ struct SubtitleRow {
     title: Option<String>,
     codec: String,
     storage_key: String,
+    idx_storage_key: Option<String>,
+    previous_storage_key: Option<String>,
+    previous_codec: Option<String>,
+    previous_idx_storage_key: Option<String>,
     is_default: i32,
     is_forced: i32,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn save_subtitle(
-    db_pool: &SqlitePool,
+    db: impl SqliteExecutor<'_>,
     video_id: &str,
     track_index: i32,
     language: Option<&str>,
@@ -377,9 +516,19 @@ pub async fn save_subtitle(
     is_default: bool,
     is_forced: bool,
 ) -> Result<i64> {
-    let result = sqlx::query(
+    // Saving an existing track (sidecar replace/edit) overwrites it in place
+    let id: i64 = sqlx::query_scalar(
         "INSERT INTO subtitles (video_id, track_index, language, title, codec, storage_key, idx_storage_key, is_default, is_forced) 
-         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(video_id, track_index) DO UPDATE SET
+            language = excluded.language,
+            title = excluded.title,
+            codec = excluded.codec,
+            storage_key = excluded.storage_key,
+            idx_storage_key = excluded.idx_storage_key,
+            is_default = excluded.is_default,
+            is_forced = excluded.is_forced
+         RETURNING id"
     )
     .bind(video_id)
     .bind(track_index)
@@ -390,15 +539,116 @@ pub async fn save_subtitle(
     .bind(idx_storage_key)
     .bind(is_default as i32)
     .bind(is_forced as i32)
+    .fetch_one(db)
+    .await?;
+
+    info!(
+        "Subtitle saved to database: video_id={}, track_index={}, codec={}",
+        video_id, track_index, codec
+    );
+
+    Ok(id)
+}
+
+/// Point a subtitle track at a new file version, remembering the one it replaces for a revert
+pub async fn set_subtitle_version(
+    db_pool: &SqlitePool,
+    video_id: &str,
+    track_index: i32,
+    current: SubtitleVersion<'_>,
+    previous: Option<SubtitleVersion<'_>>,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE subtitles SET codec = ?, storage_key = ?, idx_storage_key = ?, \
+             previous_codec = ?, previous_storage_key = ?, previous_idx_storage_key = ? \
+         WHERE video_id = ? AND track_index = ?",
+    )
+    .bind(current.codec)
+    .bind(current.storage_key)
+    .bind(current.idx_storage_key)
+    .bind(previous.map(|previous| previous.codec))
+    .bind(previous.map(|previous| previous.storage_key))
+    .bind(previous.and_then(|previous| previous.idx_storage_key))
+    .bind(video_id)
+    .bind(track_index)
     .execute(db_pool)
     .await?;
 
+    info!(
+        "Subtitle version updated: video_id={}, track_index={}, codec={}, storage_key={}",
+        video_id, track_index, current.codec, current.storage_key
+    );
+
+    Ok(())
+}
+
+/// Add a sidecar subtitle as a new track one past the highest existing track, returning its
+/// index. The index is picked inside the INSERT, so concurrent uploads can't claim the same one.
+#[allow(clippy::too_many_arguments)]
+pub async fn insert_subtitle(
+    db_pool: &SqlitePool,
+    video_id: &str,
+    language: Option<&str>,
+    title: Option<&str>,
+    codec: &str,
+    storage_key: &str,
+    is_default: bool,
+    is_forced: bool,
+) -> Result<i32> {
+    let track_index: i32 = sqlx::query_scalar(
+        "INSERT INTO subtitles (video_id, track_index, language, title, codec, storage_key, is_default, is_forced) \
+         SELECT ?, COALESCE(MAX(track_index) + 1, 0), ?, ?, ?, ?, ?, ? FROM subtitles WHERE video_id = ? \
+         RETURNING track_index",
+    )
+    .bind(video_id)
+    .bind(language)
+    .bind(title)
+    .bind(codec)
+    .bind(storage_key)
+    .bind(is_default as i32)
+    .bind(is_forced as i32)
+    .bind(video_id)
+    .fetch_one(db_pool)
+    .await?;
+
     info!(
         "Subtitle saved to database: video_id={}, track_index={}, codec={}",
         video_id, track_index, codec
     );
 
-    Ok(result.last_insert_rowid())
+    Ok(track_index)
+}
+
+/// Clear the default flag of every subtitle track of a video except `keep_track`
+pub async fn clear_default_subtitles(
+    db_pool: &SqlitePool,
+    video_id: &str,
+    keep_track: i32,
+) -> Result<()> {
+    sqlx::query("UPDATE subtitles SET is_default = 0 WHERE video_id = ? AND track_index != ?")
+        .bind(video_id)
+        .bind(keep_track)
+        .execute(db_pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn delete_subtitle(db_pool: &SqlitePool, video_id: &str, track_index: i32) -> Result<bool> {
+    let result = sqlx::query("DELETE FROM subtitles WHERE video_id = ? AND track_index = ?")
+        .bind(video_id)
+        .bind(track_index)
+        .execute(db_pool)
+        .await?;
+
+    if result.rows_affected() > 0 {
+        info!(
+            "Subtitle deleted from database: video_id={}, track_index={}",
+            video_id, track_index
+        );
+    }
+
+    Ok(result.rows_affected() > 0)
 }
 
 pub async fn get_subtitles_for_video(
@@ -406,7 +656,7 @@ pub async fn get_subtitles_for_video(
     video_id: &str,
 ) -> Result<Vec<SubtitleTrack>> {
     let rows: Vec<SubtitleRow> = sqlx::query_as(
-        "SELECT id, video_id, track_index, language, title, codec, storage_key, is_default, is_forced 
+        "SELECT id, video_id, track_index, language, title, codec, storage_key, idx_storage_key, previous_storage_key, previous_codec, previous_idx_storage_key, is_default, is_forced 
          FROM subtitles WHERE video_id = ? ORDER BY track_index"
     )
     .bind(video_id)
@@ -423,7 +673,10 @@ pub async fn get_subtitles_for_video(
             title: r.title,
             codec: r.codec,
             storage_key: r.storage_key,
-            idx_storage_key: None, // Not stored in DB yet
+            idx_storage_key: r.idx_storage_key,
+            previous_storage_key: r.previous_storage_key,
+            previous_codec: r.previous_codec,
+            previous_idx_storage_key: r.previous_idx_storage_key,
             is_default: r.is_default != 0,
             is_forced: r.is_forced != 0,
         })
@@ -436,7 +689,7 @@ pub async fn get_subtitle_by_track(
     track_index: i32,
 ) -> Result<Option<SubtitleTrack>> {
     let row: Option<SubtitleRow> = sqlx::query_as(
-        "SELECT id, video_id, track_index, language, title, codec, storage_key, is_default, is_forced 
+        "SELECT id, video_id, track_index, language, title, codec, storage_key, idx_storage_key, previous_storage_key, previous_codec, previous_idx_storage_key, is_default, is_forced 
          FROM subtitles WHERE video_id = ? AND track_index = ?"
     )
     .bind(video_id)
@@ -452,7 +705,10 @@ pub async fn get_subtitle_by_track(
         title: r.title,
         codec: r.codec,
         storage_key: r.storage_key,
-        idx_storage_key: None, // Not stored in DB yet
+        idx_storage_key: r.idx_storage_key,
+        previous_storage_key: r.previous_storage_key,
+        previous_codec: r.previous_codec,
+        previous_idx_storage_key: r.previous_idx_storage_key,
         is_default: r.is_default != 0,
         is_forced: r.is_forced != 0,
     }))
@@ -470,7 +726,7 @@ struct AttachmentRow {
 }
 
 pub async fn save_attachment(
-    db_pool: &SqlitePool,
+    db: impl SqliteExecutor<'_>,
     video_id: &str,
     filename: &str,
     mimetype: &str,
@@ -483,7 +739,7 @@ pub async fn save_attachment(
     .bind(filename)
     .bind(mimetype)
     .bind(storage_key)
-    .execute(db_pool)
+    .execute(db)
     .await?;
 
     info!(
@@ -553,11 +809,14 @@ struct AudioTrackRow {
     sample_rate: Option<i32>,
     bit_rate: Option<i64>,
     is_default: i32,
+    renditions: Option<String>,
+    loudness: Option<String>,
+    playlist_key: Option<String>,
 }
 
-#[allow(dead_code)]
+#[allow(clippy::too_many_arguments)]
 pub async fn save_audio_track(
-    db_pool: &SqlitePool,
+    db: impl SqliteExecutor<'_>,
     video_id: &str,
     track_index: i32,
     language: Option<&str>,
@@ -567,10 +826,16 @@ pub async fn save_audio_track(
     sample_rate: Option<i32>,
     bit_rate: Option<i64>,
     is_default: bool,
+    playlist_key: Option<&str>,
+    renditions: &[AudioRendition],
+    loudness: Option<&AudioLoudness>,
 ) -> Result<i64> {
+    let renditions_json = serde_json::to_string(renditions)?;
+    let loudness_json = loudness.map(serde_json::to_string).transpose()?;
+
     let result = sqlx::query(
-        "INSERT INTO audio_tracks (video_id, track_index, language, title, codec, channels, sample_rate, bit_rate, is_default) 
-         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+        "INSERT INTO audio_tracks (video_id, track_index, language, title, codec, channels, sample_rate, bit_rate, is_default, playlist_key, renditions, loudness) 
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"
     )
     .bind(video_id)
     .bind(track_index)
@@ -581,7 +846,10 @@ pub async fn save_audio_track(
     .bind(sample_rate)
     .bind(bit_rate)
     .bind(is_default as i32)
-    .execute(db_pool)
+    .bind(playlist_key)
+    .bind(renditions_json)
+    .bind(loudness_json)
+    .execute(db)
     .await?;
 
     info!(
@@ -597,7 +865,7 @@ pub async fn get_audio_tracks_for_video(
     video_id: &str,
 ) -> Result<Vec<AudioTrack>> {
     let rows: Vec<AudioTrackRow> = sqlx::query_as(
-        "SELECT id, video_id, track_index, language, title, codec, channels, sample_rate, bit_rate, is_default 
+        "SELECT id, video_id, track_index, language, title, codec, channels, sample_rate, bit_rate, is_default, playlist_key, renditions, loudness 
          FROM audio_tracks WHERE video_id = ? ORDER BY track_index ASC"
     )
     .bind(video_id)
@@ -617,6 +885,15 @@ pub async fn get_audio_tracks_for_video(
             sample_rate: r.sample_rate,
             bit_rate: r.bit_rate,
             is_default: r.is_default != 0,
+            playlist_key: r.playlist_key,
+            uri: None,
+            renditions: r
+                .renditions
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .unwrap_or_default(),
+            loudness: r
+                .loudness
+                .and_then(|json| serde_json::from_str(&json).ok()),
         })
         .collect())
 }
@@ -631,25 +908,28 @@ struct ChapterRow {
     start_time: f64,
     end_time: f64,
     title: String,
+    is_draft: i32,
 }
 
 pub async fn save_chapter(
-    db_pool: &SqlitePool,
+    db: impl SqliteExecutor<'_>,
     video_id: &str,
     chapter_index: i32,
     start_time: f64,
     end_time: f64,
     title: &str,
+    is_draft: bool,
 ) -> Result<i64> {
     let result = sqlx::query(
-        "INSERT INTO chapters (video_id, chapter_index, start_time, end_time, title) VALUES (?, ?, ?, ?, ?)",
+        "INSERT INTO chapters (video_id, chapter_index, start_time, end_time, title, is_draft) VALUES (?, ?, ?, ?, ?, ?)",
     )
     .bind(video_id)
     .bind(chapter_index)
     .bind(start_time)
     .bind(end_time)
     .bind(title)
-    .execute(db_pool)
+    .bind(is_draft as i32)
+    .execute(db)
     .await?;
 
     info!(
@@ -660,12 +940,56 @@ pub async fn save_chapter(
     Ok(result.last_insert_rowid())
 }
 
-pub async fn get_chapters_for_video(db_pool: &SqlitePool, video_id: &str) -> Result<Vec<Chapter>> {
+/// Replace every chapter of a video in one transaction; indices follow the slice order
+pub async fn replace_chapters(
+    db_pool: &SqlitePool,
+    video_id: &str,
+    chapters: &[ChapterInfo],
+) -> Result<()> {
+    let mut tx = db_pool.begin().await?;
+
+    sqlx::query("DELETE FROM chapters WHERE video_id = ?")
+        .bind(video_id)
+        .execute(&mut *tx)
+        .await?;
+
+    for (index, chapter) in chapters.iter().enumerate() {
+        sqlx::query(
+            "INSERT INTO chapters (video_id, chapter_index, start_time, end_time, title, is_draft) VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(video_id)
+        .bind(index as i32)
+        .bind(chapter.start_time)
+        .bind(chapter.end_time)
+        .bind(&chapter.title)
+        .bind(chapter.is_draft as i32)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    info!(
+        "Chapters replaced in database: video_id={}, count={}",
+        video_id,
+        chapters.len()
+    );
+
+    Ok(())
+}
+
+/// Chapters of a video in order; drafts are only included for admin views
+pub async fn get_chapters_for_video(
+    db_pool: &SqlitePool,
+    video_id: &str,
+    include_drafts: bool,
+) -> Result<Vec<Chapter>> {
     let rows: Vec<ChapterRow> = sqlx::query_as(
-        "SELECT id, video_id, chapter_index, start_time, end_time, title 
-         FROM chapters WHERE video_id = ? ORDER BY chapter_index",
+        "SELECT id, video_id, chapter_index, start_time, end_time, title, is_draft 
+         FROM chapters WHERE video_id = ? AND (? OR is_draft = 0) ORDER BY chapter_index",
     )
     .bind(video_id)
+    .bind(include_drafts)
     .fetch_all(db_pool)
     .await?;
 
@@ -678,6 +1002,299 @@ pub async fn get_chapters_for_video(db_pool: &SqlitePool, video_id: &str) -> Res
             start_time: r.start_time,
             end_time: r.end_time,
             title: r.title,
+            is_draft: r.is_draft != 0,
         })
         .collect())
 }
+
+/// Publish every draft chapter of a video. Returns the number of accepted chapters.
+pub async fn accept_draft_chapters(db_pool: &SqlitePool, video_id: &str) -> Result<u64> {
+    let accepted = sqlx::query("UPDATE chapters SET is_draft = 0 WHERE video_id = ? AND is_draft = 1")
+        .bind(video_id)
+        .execute(db_pool)
+        .await?
+        .rows_affected();
+
+    info!(
+        "Draft chapters accepted: video_id={}, count={}",
+        video_id, accepted
+    );
+
+    Ok(accepted)
+}
+
+// Job queue operations
+
+#[derive(sqlx::FromRow)]
+struct JobRow {
+    id: String,
+    video_id: Option<String>,
+    video_name: Option<String>,
+    tags: String,
+    options: String,
+    source_path: Option<String>,
+    stage: String,
+    status: String,
+    current_chunk: i64,
+    total_chunks: i64,
+    percentage: i64,
+    details: Option<String>,
+    error: Option<String>,
+    attempts: i64,
+    player_url: Option<String>,
+    created_at: i64,
+    updated_at: i64,
+}
+
+impl From<JobRow> for Job {
+    fn from(r: JobRow) -> Self {
+        Job {
+            id: r.id,
+            video_id: r.video_id,
+            video_name: r.video_name,
+            tags: serde_json::from_str(&r.tags).unwrap_or_default(),
+            options: serde_json::from_str(&r.options).unwrap_or_default(),
+            source_path: r.source_path,
+            stage: r.stage,
+            status: r.status,
+            current_chunk: r.current_chunk as u32,
+            total_chunks: r.total_chunks as u32,
+            percentage: r.percentage as u32,
+            details: r.details,
+            error: r.error,
+            attempts: r.attempts as u32,
+            player_url: r.player_url,
+            created_at: r.created_at as u64,
+            updated_at: r.updated_at as u64,
+        }
+    }
+}
+
+const JOB_COLUMNS: &str = "id, video_id, video_name, tags, options, source_path, stage, status, current_chunk, total_chunks, \
+     percentage, details, error, attempts, player_url, created_at, updated_at";
+
+/// Create (or reset) the job row for an upload that has just started arriving. Only jobs that are
+/// still initializing or have failed are reset; returns `false` when the id belongs to a queued,
+/// processing or completed job.
+pub async fn create_job(db_pool: &SqlitePool, job_id: &str, progress: &ProgressUpdate) -> Result<bool> {
+    let now = now_millis() as i64;
+
+    let rows_affected = sqlx::query(
+        "INSERT INTO jobs (id, video_name, stage, status, current_chunk, total_chunks, percentage, details, created_at, updated_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+         ON CONFLICT(id) DO UPDATE SET \
+             video_id = NULL, video_name = excluded.video_name, tags = '[]', options = '{}', source_path = NULL, \
+             stage = excluded.stage, status = excluded.status, current_chunk = excluded.current_chunk, \
+             total_chunks = excluded.total_chunks, percentage = excluded.percentage, details = excluded.details, \
+             error = NULL, attempts = 0, player_url = NULL, created_at = excluded.created_at, \
+             updated_at = excluded.updated_at \
+         WHERE jobs.status IN ('initializing', 'failed')",
+    )
+    .bind(job_id)
+    .bind(progress.video_name.as_deref())
+    .bind(&progress.stage)
+    .bind(&progress.status)
+    .bind(progress.current_chunk as i64)
+    .bind(progress.total_chunks as i64)
+    .bind(progress.percentage as i64)
+    .bind(progress.details.as_deref())
+    .bind(now)
+    .bind(now)
+    .execute(db_pool)
+    .await?
+    .rows_affected();
+
+    Ok(rows_affected > 0)
+}
+
+/// Hand a fully received upload over to the worker. Returns `false` when the job no longer
+/// exists or was cancelled while the upload was still arriving.
+pub async fn enqueue_job(
+    db_pool: &SqlitePool,
+    job_id: &str,
+    video_id: &str,
+    video_name: &str,
+    tags: &[String],
+    options: &UploadOptions,
+    source_path: &str,
+) -> Result<bool> {
+    let tags_json = serde_json::to_string(tags)?;
+    let options_json = serde_json::to_string(options)?;
+
+    let rows_affected = sqlx::query(
+        "UPDATE jobs SET video_id = ?, video_name = ?, tags = ?, options = ?, source_path = ?, \
+             stage = 'Queued for processing', status = 'queued', current_chunk = 0, total_chunks = 1, \
+             percentage = 0, details = NULL, error = NULL, updated_at = ? \
+         WHERE id = ? AND status = 'initializing'",
+    )
+    .bind(video_id)
+    .bind(video_name)
+    .bind(&tags_json)
+    .bind(&options_json)
+    .bind(source_path)
+    .bind(now_millis() as i64)
+    .bind(job_id)
+    .execute(db_pool)
+    .await?
+    .rows_affected();
+
+    if rows_affected == 0 {
+        return Ok(false);
+    }
+
+    info!("Job queued: id={}, video_id={}", job_id, video_id);
+
+    Ok(true)
+}
+
+/// Record a progress update for a job. `video_name` is only overwritten when provided.
+/// Finished jobs are left untouched so a late update cannot revive a cancelled or failed job.
+pub async fn update_job_progress(
+    db_pool: &SqlitePool,
+    job_id: &str,
+    progress: &ProgressUpdate,
+) -> Result<()> {
+    sqlx::query(
+        "UPDATE jobs SET stage = ?, status = ?, current_chunk = ?, total_chunks = ?, percentage = ?, \
+             details = ?, error = ?, player_url = COALESCE(?, player_url), \
+             video_name = COALESCE(?, video_name), updated_at = ? \
+         WHERE id = ? AND status NOT IN ('completed', 'failed')",
+    )
+    .bind(&progress.stage)
+    .bind(&progress.status)
+    .bind(progress.current_chunk as i64)
+    .bind(progress.total_chunks as i64)
+    .bind(progress.percentage as i64)
+    .bind(progress.details.as_deref())
+    .bind(progress.error.as_deref())
+    .bind(progress.result.as_ref().map(|r| r.player_url.as_str()))
+    .bind(progress.video_name.as_deref())
+    .bind(now_millis() as i64)
+    .bind(job_id)
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Atomically move the oldest queued job into the processing state
+pub async fn claim_next_job(db_pool: &SqlitePool) -> Result<Option<Job>> {
+    let query = format!(
+        "UPDATE jobs SET status = 'processing', stage = 'Starting', attempts = attempts + 1, updated_at = ? \
+         WHERE id = (SELECT id FROM jobs WHERE status = 'queued' ORDER BY created_at LIMIT 1) \
+         RETURNING {}",
+        JOB_COLUMNS
+    );
+
+    let row: Option<JobRow> = sqlx::query_as(&query)
+        .bind(now_millis() as i64)
+        .fetch_optional(db_pool)
+        .await?;
+
+    Ok(row.map(Job::from))
+}
+
+/// Cancel a job that has not started processing yet. Returns the cancelled job, or `None`
+/// if it no longer exists or is already past the point where it can be cancelled.
+pub async fn cancel_pending_job(db_pool: &SqlitePool, job_id: &str) -> Result<Option<Job>> {
+    let query = format!(
+        "UPDATE jobs SET stage = 'Cancelled', status = 'failed', current_chunk = 0, percentage = 0, \
+             details = 'Cancelled by user', error = 'Cancelled by user', updated_at = ? \
+         WHERE id = ? AND status IN ('initializing', 'queued') \
+         RETURNING {}",
+        JOB_COLUMNS
+    );
+
+    let row: Option<JobRow> = sqlx::query_as(&query)
+        .bind(now_millis() as i64)
+        .bind(job_id)
+        .fetch_optional(db_pool)
+        .await?;
+
+    if row.is_some() {
+        info!("Job cancelled: id={}", job_id);
+    }
+
+    Ok(row.map(Job::from))
+}
+
+/// Put an interrupted job back at its original place in the queue
+pub async fn requeue_job(db_pool: &SqlitePool, job_id: &str) -> Result<()> {
+    sqlx::query(
+        "UPDATE jobs SET stage = 'Queued for processing', status = 'queued', current_chunk = 0, \
+             total_chunks = 1, percentage = 0, details = 'Resuming after server restart', updated_at = ? \
+         WHERE id = ?",
+    )
+    .bind(now_millis() as i64)
+    .bind(job_id)
+    .execute(db_pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_job(db_pool: &SqlitePool, job_id: &str) -> Result<Option<Job>> {
+    let query = format!("SELECT {} FROM jobs WHERE id = ?", JOB_COLUMNS);
+    let row: Option<JobRow> = sqlx::query_as(&query)
+        .bind(job_id)
+        .fetch_optional(db_pool)
+        .await?;
+
+    Ok(row.map(Job::from))
+}
+
+/// List jobs for the queue view: everything unfinished or failed, plus jobs completed after `completed_since`
+pub async fn list_jobs(db_pool: &SqlitePool, completed_since: u64) -> Result<Vec<Job>> {
+    let query = format!(
+        "SELECT {} FROM jobs WHERE status != 'completed' OR updated_at >= ? ORDER BY created_at",
+        JOB_COLUMNS
+    );
+    let rows: Vec<JobRow> = sqlx::query_as(&query)
+        .bind(completed_since as i64)
+        .fetch_all(db_pool)
+        .await?;
+
+    Ok(rows.into_iter().map(Job::from).collect())
+}
+
+/// Delete completed and failed jobs last updated before `before` (milliseconds). Returns the
+/// number of deleted jobs.
+pub async fn prune_finished_jobs(db_pool: &SqlitePool, before: u64) -> Result<u64> {
+    let result =
+        sqlx::query("DELETE FROM jobs WHERE status IN ('completed', 'failed') AND updated_at < ?")
+            .bind(before as i64)
+            .execute(db_pool)
+            .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Jobs that were interrupted by a shutdown (not queued and not finished)
+pub async fn get_interrupted_jobs(db_pool: &SqlitePool) -> Result<Vec<Job>> {
+    let query = format!(
+        "SELECT {} FROM jobs WHERE status IN ('initializing', 'processing') ORDER BY created_at",
+        JOB_COLUMNS
+    );
+    let rows: Vec<JobRow> = sqlx::query_as(&query).fetch_all(db_pool).await?;
+
+    Ok(rows.into_iter().map(Job::from).collect())
+}
+
+/// Delete finished jobs. When `job_id` is `None` every failed job is removed.
+pub async fn delete_finished_jobs(db_pool: &SqlitePool, job_id: Option<&str>) -> Result<u64> {
+    let result = match job_id {
+        Some(id) => {
+            sqlx::query("DELETE FROM jobs WHERE id = ? AND status IN ('completed', 'failed')")
+                .bind(id)
+                .execute(db_pool)
+                .await?
+        }
+        None => {
+            sqlx::query("DELETE FROM jobs WHERE status = 'failed'")
+                .execute(db_pool)
+                .await?
+        }
+    };
+
+    Ok(result.rows_affected())
+}
@@ -240,8 +240,7 @@ fn minify_pass_one(code: &str) -> String {
                 let curr_is_ident_start = is_ident_start(c);
 
                 // Space needed between identifiers
-                let needs_separator = (last_is_ident && curr_is_ident)
-                    || (last_is_ident && c == '/') // "return /regex/"
+                let needs_separator = (last_is_ident && (curr_is_ident || c == '/')) // "return /regex/"
                     || (last == '/' && curr_is_ident_start) // division followed by identifier
                     || (last == ')' && curr_is_ident_start) // ") function" or ") if"
                     || (last == ']' && curr_is_ident_start); // "] in" patterns
@@ -988,18 +987,18 @@ fn rename_variables(code: &str) -> String {
                     j += 1;
                 }
 
-                if valid && j < tokens.len() {
-                    if let JsToken::Operator(op) = &tokens[j] {
-                        if op == "=>" {
-                            for name in param_candidates {
-                                record_declared_name(
-                                    &name,
-                                    &mut used_names,
-                                    &mut declared_set,
-                                    &mut declared_order,
-                                );
-                            }
-                        }
+                if valid
+                    && j < tokens.len()
+                    && let JsToken::Operator(op) = &tokens[j]
+                    && op == "=>"
+                {
+                    for name in param_candidates {
+                        record_declared_name(
+                            &name,
+                            &mut used_names,
+                            &mut declared_set,
+                            &mut declared_order,
+                        );
                     }
                 }
             }
@@ -1335,6 +1334,24 @@ pub fn verify_token(video_id: &str, token: &str, secret: &str, ip: &str, user_ag
     }
 }
 
+pub async fn get_config_info(State(state): State<AppState>) -> Json<ConfigInfo> {
+    // Extract bucket name from endpoint URL
+    // Format: https://{account-id}.r2.cloudflarestorage.com/{bucket-name}
+    let bucket_name = state
+        .config
+        .r2
+        .endpoint
+        .split('/')
+        .next_back()
+        .unwrap_or(&state.config.r2.bucket)
+        .to_string();
+
+    Json(ConfigInfo {
+        bucket: bucket_name,
+        encoder: state.config.video.encoder.clone(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1630,21 +1647,3 @@ mod tests {
     }
 
 }
-
-pub async fn get_config_info(State(state): State<AppState>) -> Json<ConfigInfo> {
-    // Extract bucket name from endpoint URL
-    // Format: https://{account-id}.r2.cloudflarestorage.com/{bucket-name}
-    let bucket_name = state
-        .config
-        .r2
-        .endpoint
-        .split('/')
-        .last()
-        .unwrap_or(&state.config.r2.bucket)
-        .to_string();
-
-    Json(ConfigInfo {
-        bucket: bucket_name,
-        encoder: state.config.video.encoder.clone(),
-    })
-}
@@ -1,6 +1,6 @@
 use crate::database::{
     get_attachment_by_filename, get_attachments_for_video, get_audio_tracks_for_video,
-    get_chapters_for_video, get_subtitle_by_track, get_subtitles_for_video,
+    get_chapters_for_video, get_subtitle_by_track, get_subtitles_for_video, get_video,
 };
 use crate::handlers::common::{internal_err, verify_token};
 use crate::types::{
@@ -140,10 +140,34 @@ pub async fn get_video_audio_tracks(
         ));
     }
 
-    let items = get_audio_tracks_for_video(&state.db_pool, &video_id)
+    let video = get_video(&state.db_pool, &video_id)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Video not found".to_string()))?;
+
+    let mut items = get_audio_tracks_for_video(&state.db_pool, &video_id)
         .await
         .map_err(internal_err)?;
 
+    // Public playlists are fetched straight from the CDN; private ones go through the backend
+    // playlist endpoint, which rewrites segment URLs and needs the token
+    let cdn_base = state.config.r2.public_base_url.trim_end_matches('/');
+    let prefix = format!("{}/", video_id);
+    let playlist_uri = |key: &str| {
+        if video.is_public != 0 {
+            format!("{}/{}", cdn_base, key)
+        } else {
+            let file = key.strip_prefix(&prefix).unwrap_or(key);
+            format!("/hls/{}/{}?token={}", video_id, file, token)
+        }
+    };
+
+    for track in &mut items {
+        track.uri = track.playlist_key.as_deref().map(playlist_uri);
+        for rendition in &mut track.renditions {
+            rendition.uri = rendition.playlist_key.as_deref().map(playlist_uri);
+        }
+    }
+
     Ok(Json(AudioTrackListResponse { items }))
 }
 
@@ -216,12 +240,23 @@ pub async fn get_subtitle_file(
         .map_err(internal_err)?
         .ok_or_else(|| (StatusCode::NOT_FOUND, "Subtitle not found".to_string()))?;
 
+    // VobSub tracks are a .sub bitmap file plus an .idx index, requested as "{track}.idx"
+    let wants_idx = track_with_ext.ends_with(".idx");
+    let key = if wants_idx {
+        subtitle
+            .idx_storage_key
+            .as_deref()
+            .ok_or_else(|| (StatusCode::NOT_FOUND, "Subtitle index not found".to_string()))?
+    } else {
+        subtitle.storage_key.as_str()
+    };
+
     // Fetch from R2
     let content = state
         .s3
         .get_object()
         .bucket(&state.config.r2.bucket)
-        .key(&subtitle.storage_key)
+        .key(key)
         .send()
         .await
         .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
@@ -233,8 +268,10 @@ pub async fn get_subtitle_file(
 
     // Determine content type based on codec
     let content_type = match subtitle.codec.as_str() {
+        _ if wants_idx => "text/plain",
         "ass" | "ssa" => "text/x-ssa",
         "subrip" | "srt" => "text/plain",
+        "hdmv_pgs_subtitle" | "pgssub" | "dvd_subtitle" | "dvdsub" => "application/octet-stream",
         _ => "text/plain",
     };
 
@@ -459,7 +496,7 @@ pub async fn get_video_chapters(
         ));
     }
 
-    let chapters = get_chapters_for_video(&state.db_pool, &video_id)
+    let chapters = get_chapters_for_video(&state.db_pool, &video_id, false)
         .await
         .map_err(internal_err)?;
 
@@ -1,6 +1,8 @@
 pub mod common;
 pub mod content;
 pub mod player;
+pub mod subtitle;
+pub mod thumbnail;
 pub mod upload;
 pub mod video;
 
@@ -11,11 +13,19 @@ pub use content::{
     get_attachment_file, get_jassub_worker, get_libbitsub_worker, get_subtitle_file,
     get_video_attachments, get_video_audio_tracks, get_video_chapters, get_video_subtitles,
 };
-pub use player::{get_hls_file, get_player};
+pub use player::{get_dash_file, get_hls_file, get_player};
+pub use subtitle::{
+    delete_subtitle, replace_subtitle, retime_subtitle_track, revert_subtitle, update_subtitle,
+    upload_subtitle,
+};
+pub use thumbnail::{pick_thumbnail_frame, upload_thumbnail};
 
 #[allow(unused)]
 pub use upload::{
-    CancelQueueResponse, CleanupResponse, cancel_queue, finalize_chunked_upload, get_progress, list_queues,
-    upload_chunk, upload_video, cleanup_uploads,
+    CancelQueueResponse, CleanupResponse, cancel_queue, clear_failed_queues, finalize_chunked_upload,
+    get_progress, list_queues, remove_queue, upload_chunk, upload_video, cleanup_uploads,
+};
+pub use video::{
+    accept_chapters, add_chapter, delete_chapter, delete_videos, list_all_chapters, list_videos,
+    replace_video_chapters, update_chapter, update_video, update_video_visibility,
 };
-pub use video::{delete_videos, list_videos, update_video, update_video_visibility};
@@ -1,6 +1,8 @@
 use crate::database::{get_attachments_for_video, get_chapters_for_video, get_subtitles_for_video};
 use crate::handlers::common::{generate_token, internal_err, minify_js, verify_token};
-use crate::types::AppState;
+use crate::storyboard::STORYBOARD_DIR;
+use crate::thumbnail::poster_mime;
+use crate::types::{AppState, PosterImage, StoryboardLayout};
 
 use axum::{
     extract::{ConnectInfo, Path, Query, State},
@@ -58,7 +60,7 @@ pub async fn get_player(
     let attachments = get_attachments_for_video(&state.db_pool, &id)
         .await
         .unwrap_or_default();
-    let chapters = get_chapters_for_video(&state.db_pool, &id)
+    let chapters = get_chapters_for_video(&state.db_pool, &id, false)
         .await
         .unwrap_or_default();
 
@@ -132,8 +134,73 @@ pub async fn get_player(
         "const chapters = [];".to_string()
     };
 
-    let thumbnail_url = format!("{}/{}/thumbnail.jpg", cdn_base, id);
-    let sprite_url = format!("{}/{}/sprites.jpg", cdn_base, id);
+    let thumbnail_url = format!("{}/{}", cdn_base, video.thumbnail_key);
+
+    // Poster set: the player picks a WebP (or JPEG) width for its size, link previews get a
+    // JPEG around 1280px since not every crawler reads WebP
+    let posters: Vec<PosterImage> = video
+        .posters
+        .as_deref()
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default();
+    let posters_of = |mime: &str| -> Vec<&PosterImage> {
+        let mut images: Vec<&PosterImage> = posters
+            .iter()
+            .filter(|poster| poster_mime(&poster.key) == Some(mime))
+            .collect();
+        images.sort_by_key(|poster| poster.width);
+        images
+    };
+    let jpeg_posters = posters_of("image/jpeg");
+    let webp_posters = posters_of("image/webp");
+    let player_posters = if webp_posters.is_empty() { &jpeg_posters } else { &webp_posters };
+    let posters_js = format!(
+        "const posters = [{}];",
+        player_posters
+            .iter()
+            .map(|poster| format!(
+                "{{ width: {}, url: '{}/{}' }}",
+                poster.width, cdn_base, poster.key
+            ))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let og_image = jpeg_posters
+        .iter()
+        .rev()
+        .find(|poster| poster.width <= 1280)
+        .or(jpeg_posters.first())
+        .map(|poster| format!("{}/{}", cdn_base, poster.key))
+        .unwrap_or_else(|| thumbnail_url.clone());
+    // Storyboard layout for the scrub preview; older videos only have the single 10x10 sprite,
+    // and videos whose sprites failed to generate get no scrub preview at all
+    let (storyboard, storyboard_base) = match video
+        .storyboard
+        .as_deref()
+        .and_then(|json| serde_json::from_str::<StoryboardLayout>(json).ok())
+    {
+        Some(storyboard) => (
+            Some(storyboard),
+            format!("{}/{}/{}/", cdn_base, id, STORYBOARD_DIR),
+        ),
+        None if video.sprites_key.is_some() => (
+            Some(StoryboardLayout {
+                interval: (video.duration as f64 / 100.0).max(0.1),
+                tile_width: 160,
+                tile_height: 90,
+                columns: 10,
+                rows: 10,
+                frames: 100,
+                sheets: vec!["sprites.jpg".to_string()],
+            }),
+            format!("{}/{}/", cdn_base, id),
+        ),
+        None => (None, String::new()),
+    };
+    let storyboard_js = format!(
+        "const storyboard = {};",
+        serde_json::to_string(&storyboard).unwrap_or_else(|_| "null".to_string())
+    );
 
     let js_code = format!(
         r#"
@@ -144,12 +211,9 @@ pub async fn get_player(
         {fonts_js}
         {chapters_js}
         const thumbnailUrl = '{thumbnail_url}';
-        const spriteUrl = '{sprite_url}';
-        const spriteColumns = 10;
-        const spriteRows = 10;
-        const spriteWidth = 160;
-        const spriteHeight = 90;
-        let spriteInterval = 10;
+        {posters_js}
+        {storyboard_js}
+        const storyboardBase = '{storyboard_base}';
 
         let player = null;
         let video = null;
@@ -204,9 +268,18 @@ pub async fn get_player(
             const previewImage = document.getElementById('previewImage');
             const previewTime = document.getElementById('previewTime');
 
-            if (thumbnailUrl && video && container) {{
-                video.poster = thumbnailUrl;
-                container.style.backgroundImage = `url(${{thumbnailUrl}})`;
+            // Smallest poster covering the player at the device pixel ratio
+            const pickPoster = () => {{
+                if (!posters.length) return thumbnailUrl;
+                const needed = (container ? container.clientWidth : window.innerWidth) * (window.devicePixelRatio || 1);
+                const match = posters.find((p) => p.width >= needed);
+                return (match || posters[posters.length - 1]).url;
+            }};
+            const posterUrl = pickPoster();
+
+            if (posterUrl && video && container) {{
+                video.poster = posterUrl;
+                container.style.backgroundImage = `url(${{posterUrl}})`;
                 container.classList.add('has-thumb');
             }}
 
@@ -226,30 +299,27 @@ pub async fn get_player(
                 if (video) video.poster = '';
             }};
 
-            const updateSpriteInterval = () => {{
-                if (!video || !isFinite(video.duration) || video.duration <= 0) return;
-                const frames = spriteColumns * spriteRows;
-                spriteInterval = Math.max(video.duration / frames, 0.1);
-            }};
-
             const hidePreview = () => {{
                 if (preview) preview.classList.remove('show');
             }};
 
             const showPreview = (clientX) => {{
-                if (!spriteUrl || !preview || !previewImage || !previewTime || !progress || !video || !isFinite(video.duration)) return;
+                if (!storyboard || !preview || !previewImage || !previewTime || !progress || !video || !isFinite(video.duration)) return;
                 const rect = progress.getBoundingClientRect();
                 const pct = Math.min(1, Math.max(0, (clientX - rect.left) / rect.width));
                 const time = pct * (video.duration || 0);
-                const frames = spriteColumns * spriteRows;
-                const interval = spriteInterval > 0 ? spriteInterval : Math.max((video.duration || 0) / frames, 0.1);
-                const idx = Math.min(frames - 1, Math.floor(time / interval));
-                const col = idx % spriteColumns;
-                const row = Math.floor(idx / spriteColumns);
-
-                previewImage.style.backgroundImage = 'url(' + spriteUrl + ')';
-                previewImage.style.backgroundSize = (spriteColumns * spriteWidth) + 'px ' + (spriteRows * spriteHeight) + 'px';
-                previewImage.style.backgroundPosition = '-' + (col * spriteWidth) + 'px -' + (row * spriteHeight) + 'px';
+                const perSheet = storyboard.columns * storyboard.rows;
+                const idx = Math.min(storyboard.frames - 1, Math.floor(time / storyboard.interval));
+                const sheet = storyboard.sheets[Math.floor(idx / perSheet)];
+                const tile = idx % perSheet;
+                const col = tile % storyboard.columns;
+                const row = Math.floor(tile / storyboard.columns);
+
+                previewImage.style.width = storyboard.tile_width + 'px';
+                previewImage.style.height = storyboard.tile_height + 'px';
+                previewImage.style.backgroundImage = 'url(' + storyboardBase + sheet + ')';
+                previewImage.style.backgroundSize = (storyboard.columns * storyboard.tile_width) + 'px ' + (storyboard.rows * storyboard.tile_height) + 'px';
+                previewImage.style.backgroundPosition = '-' + (col * storyboard.tile_width) + 'px -' + (row * storyboard.tile_height) + 'px';
                 previewTime.textContent = formatTime(time);
 
                 const minX = 60;
@@ -299,6 +369,9 @@ pub async fn get_player(
             player = new shaka.Player();
             await player.attach(video);
             player.configure({{
+                // Subtitles are drawn by the libass/libbitsub overlay; the HLS WebVTT
+                // renditions are for native and third-party players
+                autoShowText: shaka.config.AutoShowText.NEVER,
                 streaming: {{
                     bufferingGoal: 30,
                     rebufferingGoal: 2,
@@ -356,7 +429,6 @@ pub async fn get_player(
 
             video.onloadedmetadata = () => {{
                 updateOrientation();
-                updateSpriteInterval();
             }};
             window.addEventListener('resize', updateOrientation);
             document.onfullscreenchange = () => {{
@@ -448,7 +520,6 @@ pub async fn get_player(
             video.ondurationchange = () => {{
                 durationEl.textContent = formatTime(video.duration);
                 updateBufferedBar();
-                updateSpriteInterval();
             }};
             video.onprogress = updateBufferedBar;
             progress.onmousemove = (e) => showPreview(e.clientX);
@@ -574,7 +645,9 @@ pub async fn get_player(
         function buildQualityMenu() {{
             const menu = document.getElementById('qualityMenu');
             const tracks = player.getVariantTracks();
-            const heights = [...new Set(tracks.map(t => t.height))].sort((a,b) => b - a);
+            // Label rungs by their short edge so vertical videos read 1080p rather than 1920p
+            const edge = t => t.width ? Math.min(t.width, t.height) : t.height;
+            const heights = [...new Set(tracks.map(edge))].sort((a,b) => b - a);
             
             menu.innerHTML = '<div class="menu-item" data-value="-1">Auto</div>';
             heights.forEach(h => {{
@@ -589,7 +662,7 @@ pub async fn get_player(
                         player.configure({{ abr: {{ enabled: true }} }});
                     }} else {{
                         player.configure({{ abr: {{ enabled: false }} }});
-                        const track = tracks.find(t => t.height === val);
+                        const track = tracks.find(t => edge(t) === val);
                         if (track) player.selectVariantTrack(track, true);
                     }}
                     menu.querySelectorAll('.menu-item').forEach(i => i.classList.remove('active'));
@@ -738,7 +811,9 @@ pub async fn get_player(
         fonts_js = fonts_js,
         chapters_js = chapters_js,
         thumbnail_url = thumbnail_url,
-        sprite_url = sprite_url,
+        posters_js = posters_js,
+        storyboard_js = storyboard_js,
+        storyboard_base = storyboard_base,
     );
 
     // Minify JS
@@ -774,7 +849,11 @@ pub async fn get_player(
 <head>
     <meta charset="UTF-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>Video Player</title>
+    <title>{title}</title>
+    <meta property="og:type" content="video.other">
+    <meta property="og:title" content="{title}">
+    <meta property="og:image" content="{og_image}">
+    <meta name="twitter:card" content="summary_large_image">
     <style>
         * {{ margin: 0; padding: 0; box-sizing: border-box; }}
         body {{ background: #000; font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif; }}
@@ -874,6 +953,8 @@ pub async fn get_player(
     <script>{minified_js}</script>
 </body>
 </html>"#,
+        title = escape_html(&video.name),
+        og_image = escape_html(&og_image),
         scripts_html = scripts_html,
         subtitle_controls = subtitle_controls,
         minified_js = minified_js,
@@ -908,31 +989,130 @@ fn rewrite_playlist_urls(
     video_id: &str,
     current_path: &str,
 ) -> String {
+    let resolve = |path: &str| {
+        if path.contains('/') {
+            // Absolute path in playlist (rare)
+            format!("{}/{}/{}", base_url, video_id, path)
+        } else if current_path.is_empty() {
+            // Master playlist level - relative path
+            format!("{}/{}/{}", base_url, video_id, path)
+        } else {
+            // Variant playlist level - relative path
+            format!("{}/{}/{}/{}", base_url, video_id, current_path, path)
+        }
+    };
+
     let mut lines = Vec::new();
 
     for line in playlist_content.lines() {
-        if line.starts_with('#') || line.trim().is_empty() {
+        if let Some(attrs) = line.strip_prefix("#EXT-X-MAP:") {
+            // fMP4 init segment - lives next to the media segments on the CDN
+            lines.push(format!(
+                "#EXT-X-MAP:{}",
+                rewrite_uri_attribute(attrs, &resolve)
+            ));
+        } else if let Some(attrs) = line.strip_prefix("#EXT-X-MEDIA:") {
+            // Audio and subtitle renditions of the master playlist
+            lines.push(format!(
+                "#EXT-X-MEDIA:{}",
+                rewrite_uri_attribute(attrs, &resolve)
+            ));
+        } else if line.starts_with('#') || line.trim().is_empty() {
             // Keep HLS tags and empty lines as-is
             lines.push(line.to_string());
         } else {
             // This is a resource path - rewrite it
-            let full_url = if line.contains('/') {
-                // Absolute path in playlist (rare)
-                format!("{}/{}/{}", base_url, video_id, line)
-            } else if current_path.is_empty() {
-                // Master playlist level - relative path
-                format!("{}/{}/{}", base_url, video_id, line)
-            } else {
-                // Variant playlist level - relative path
-                format!("{}/{}/{}/{}", base_url, video_id, current_path, line)
-            };
-            lines.push(full_url);
+            lines.push(resolve(line));
         }
     }
 
     lines.join("\n")
 }
 
+/// Rewrite the quoted URI="..." value inside an HLS tag attribute list
+fn rewrite_uri_attribute(attrs: &str, resolve: &impl Fn(&str) -> String) -> String {
+    let Some(start) = attrs.find("URI=\"") else {
+        return attrs.to_string();
+    };
+    let value_start = start + "URI=\"".len();
+    let Some(value_len) = attrs[value_start..].find('"') else {
+        return attrs.to_string();
+    };
+    let value_end = value_start + value_len;
+
+    format!(
+        "{}{}{}",
+        &attrs[..value_start],
+        resolve(&attrs[value_start..value_end]),
+        &attrs[value_end..]
+    )
+}
+
+/// Token check for private videos: the token comes from the query string or the `token` cookie
+fn authorize_private_video(
+    state: &AppState,
+    addr: SocketAddr,
+    headers: &HeaderMap,
+    query_token: Option<String>,
+    id: &str,
+) -> Result<(), (StatusCode, String)> {
+    let mut token = query_token.unwrap_or_default();
+    if token.is_empty() {
+        let cookie_header = headers
+            .get(header::COOKIE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        for cookie in cookie_header.split(';') {
+            let cookie = cookie.trim();
+            if let Some(val) = cookie.strip_prefix("token=") {
+                token = val.to_string();
+                break;
+            }
+        }
+    }
+
+    let ip = headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|xff| xff.split(',').next().map(|s| s.trim().to_string()))
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| addr.ip().to_string());
+
+    let user_agent = headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+
+    if !verify_token(id, &token, &state.config.server.secret_key, &ip, user_agent) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            "Access denied: Invalid or expired token".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fetch a text object (playlist or manifest) from R2
+async fn fetch_text_from_r2(state: &AppState, key: &str) -> Result<String, (StatusCode, String)> {
+    let content = state
+        .s3
+        .get_object()
+        .bucket(&state.config.r2.bucket)
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
+
+    let bytes = content.body.collect().await
+        .map_err(|e| internal_err(anyhow::anyhow!(e)))?
+        .into_bytes();
+
+    String::from_utf8(bytes.to_vec())
+        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Invalid UTF-8".to_string()))
+}
+
 pub async fn get_hls_file(
     State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -958,59 +1138,12 @@ pub async fn get_hls_file(
 
     // For private videos: Verify token for playlist access
     if video.is_public == 0 {
-        let mut token = query.token.unwrap_or_default();
-        if token.is_empty() {
-            let cookie_header = headers
-                .get(header::COOKIE)
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or("");
-
-            for cookie in cookie_header.split(';') {
-                let cookie = cookie.trim();
-                if let Some(val) = cookie.strip_prefix("token=") {
-                    token = val.to_string();
-                    break;
-                }
-            }
-        }
-
-        let ip = headers
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-            .and_then(|xff| xff.split(',').next().map(|s| s.trim().to_string()))
-            .filter(|s| !s.is_empty())
-            .unwrap_or_else(|| addr.ip().to_string());
-
-        let user_agent = headers
-            .get(header::USER_AGENT)
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("");
-
-        if !verify_token(&id, &token, &state.config.server.secret_key, &ip, user_agent) {
-            return Err((
-                StatusCode::FORBIDDEN,
-                "Access denied: Invalid or expired token".to_string(),
-            ));
-        }
+        authorize_private_video(&state, addr, &headers, query.token, &id)?;
     }
     // For public videos, no token verification needed!
 
     // Fetch playlist from R2 (both public and private)
-    let content = state
-        .s3
-        .get_object()
-        .bucket(&state.config.r2.bucket)
-        .key(&key)
-        .send()
-        .await
-        .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
-
-    let bytes = content.body.collect().await
-        .map_err(|e| internal_err(anyhow::anyhow!(e)))?
-        .into_bytes();
-
-    let playlist_text = String::from_utf8(bytes.to_vec())
-        .map_err(|_| (StatusCode::INTERNAL_SERVER_ERROR, "Invalid UTF-8".to_string()))?;
+    let playlist_text = fetch_text_from_r2(&state, &key).await?;
 
     // Determine current path (for relative URL resolution)
     let current_path = if let Some(slash_pos) = file.rfind('/') {
@@ -1024,7 +1157,7 @@ pub async fn get_hls_file(
 
     let rewritten = rewrite_playlist_urls(
         &playlist_text,
-        &base_url,
+        base_url,
         &id,
         current_path,
     );
@@ -1034,3 +1167,67 @@ pub async fn get_hls_file(
         rewritten
     ).into_response())
 }
+
+/// Point the relative segment URLs of a DASH manifest at the public CDN
+fn add_dash_base_url(manifest: &str, base_url: &str, video_id: &str) -> String {
+    let base = format!("  <BaseURL>{}/{}/</BaseURL>\n", base_url, video_id);
+    match manifest.find("  <Period") {
+        Some(pos) => format!("{}{}{}", &manifest[..pos], base, &manifest[pos..]),
+        None => manifest.to_string(),
+    }
+}
+
+pub async fn get_dash_file(
+    State(state): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Query(query): Query<HlsTokenQuery>,
+    Path((id, file)): Path<(String, String)>,
+) -> Result<Response, (StatusCode, String)> {
+    // Only the manifest goes through the backend - segments come from the CDN
+    if !file.ends_with(".mpd") {
+        return Err((
+            StatusCode::GONE,
+            "Video segments are now served via CDN".to_string()
+        ));
+    }
+
+    let video = crate::database::get_video(&state.db_pool, &id)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Video not found".to_string()))?;
+
+    let manifest_key = video.dash_manifest_key.ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            "No DASH manifest for this video".to_string(),
+        )
+    })?;
+
+    if manifest_key != format!("{}/{}", id, file) {
+        return Err((StatusCode::NOT_FOUND, "Manifest not found".to_string()));
+    }
+
+    // For private videos: Verify token for manifest access
+    if video.is_public == 0 {
+        authorize_private_video(&state, addr, &headers, query.token, &id)?;
+    }
+
+    let manifest = fetch_text_from_r2(&state, &manifest_key).await?;
+
+    let base_url = state.config.r2.public_base_url.trim_end_matches('/');
+    let rewritten = add_dash_base_url(&manifest, base_url, &id);
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/dash+xml")],
+        rewritten
+    ).into_response())
+}
+
+/// Escape text for HTML content and attribute values
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
@@ -0,0 +1,513 @@
+use crate::database::{
+    clear_default_subtitles, delete_subtitle as db_delete_subtitle, get_subtitle_by_track,
+    get_video, insert_subtitle, save_subtitle, set_subtitle_version,
+};
+use crate::handlers::common::{internal_err, now_millis};
+use crate::storage::{bulk_delete_from_r2, download_bytes_from_r2, upload_bytes_to_r2};
+use crate::subtitles::{Retime, SubtitleFormat, normalize_subtitle, retime_subtitle};
+use crate::types::{AppState, SubtitleTrack, SubtitleVersion};
+
+use axum::{
+    Json,
+    extract::{Multipart, Path, State, multipart::Field},
+    http::StatusCode,
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Longest accepted track title
+const MAX_TITLE_CHARS: usize = 200;
+
+#[derive(serde::Deserialize)]
+pub struct UpdateSubtitleRequest {
+    /// Empty string clears the language
+    pub language: Option<String>,
+    /// Empty string clears the title
+    pub title: Option<String>,
+    pub is_default: Option<bool>,
+    pub is_forced: Option<bool>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct RetimeSubtitleRequest {
+    /// Shift applied after the rate correction, in milliseconds (negative = earlier)
+    #[serde(default)]
+    pub offset_ms: i64,
+    /// Multiplier for every timestamp
+    pub rate: Option<f64>,
+    /// Alternative to `rate`: the frame rate the file was timed against and the video's frame
+    /// rate (e.g. 25 -> 23.976), giving a rate of `from_fps / to_fps`
+    pub from_fps: Option<f64>,
+    pub to_fps: Option<f64>,
+}
+
+/// Multipart fields shared by sidecar upload and replace
+#[derive(Default)]
+struct SubtitleForm {
+    file: Option<(String, Vec<u8>)>,
+    language: Option<String>,
+    title: Option<String>,
+    is_default: Option<bool>,
+    is_forced: Option<bool>,
+}
+
+/// Upload a sidecar subtitle file (`file`, optional `language`, `title`, `is_default`,
+/// `is_forced`) as a new track of an existing video
+pub async fn upload_subtitle(
+    State(state): State<AppState>,
+    Path(video_id): Path<String>,
+    multipart: Multipart,
+) -> Result<(StatusCode, Json<SubtitleTrack>), (StatusCode, String)> {
+    get_video(&state.db_pool, &video_id)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Video not found".to_string()))?;
+
+    let form = read_subtitle_form(multipart).await?;
+    let (file_name, bytes) = form
+        .file
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing subtitle file".to_string()))?;
+    let language = normalize_language(form.language.as_deref())?;
+    let title = normalize_title(form.title.as_deref())?;
+    let is_default = form.is_default.unwrap_or(false);
+
+    // The track index is only known once the row is in, so the file is named independently
+    let file_stem = format!("sidecar_{}", Uuid::new_v4());
+    let (codec, storage_key) =
+        store_subtitle_file(&state, &video_id, &file_stem, &file_name, &bytes).await?;
+
+    let track_index = insert_subtitle(
+        &state.db_pool,
+        &video_id,
+        language.as_deref(),
+        title.as_deref(),
+        codec,
+        &storage_key,
+        is_default,
+        form.is_forced.unwrap_or(false),
+    )
+    .await
+    .map_err(internal_err)?;
+    if is_default {
+        clear_default_subtitles(&state.db_pool, &video_id, track_index)
+            .await
+            .map_err(internal_err)?;
+    }
+
+    info!(
+        "Sidecar subtitle {} uploaded as track {} of video {}",
+        file_name, track_index, video_id
+    );
+
+    let track = load_track(&state, &video_id, track_index).await?;
+    Ok((StatusCode::CREATED, Json(track)))
+}
+
+/// Replace the file of a subtitle track, keeping its metadata unless the form overrides it
+pub async fn replace_subtitle(
+    State(state): State<AppState>,
+    Path((video_id, track)): Path<(String, String)>,
+    multipart: Multipart,
+) -> Result<Json<SubtitleTrack>, (StatusCode, String)> {
+    let track_index = parse_track_index(&track)?;
+    let existing = load_track(&state, &video_id, track_index).await?;
+
+    let form = read_subtitle_form(multipart).await?;
+    let (file_name, bytes) = form
+        .file
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing subtitle file".to_string()))?;
+    let language = match form.language.as_deref() {
+        Some(language) => normalize_language(Some(language))?,
+        None => existing.language.clone(),
+    };
+    let title = match form.title.as_deref() {
+        Some(title) => normalize_title(Some(title))?,
+        None => existing.title.clone(),
+    };
+    let is_default = form.is_default.unwrap_or(existing.is_default);
+
+    let file_stem = format!("track_{}_{}", track_index, now_millis());
+    let (codec, storage_key) =
+        store_subtitle_file(&state, &video_id, &file_stem, &file_name, &bytes).await?;
+
+    if is_default {
+        clear_default_subtitles(&state.db_pool, &video_id, track_index)
+            .await
+            .map_err(internal_err)?;
+    }
+    save_subtitle(
+        &state.db_pool,
+        &video_id,
+        track_index,
+        language.as_deref(),
+        title.as_deref(),
+        codec,
+        &storage_key,
+        None,
+        is_default,
+        form.is_forced.unwrap_or(existing.is_forced),
+    )
+    .await
+    .map_err(internal_err)?;
+
+    // The replaced file is kept for a revert, together with its codec and VobSub index
+    let current = SubtitleVersion {
+        codec,
+        storage_key: &storage_key,
+        idx_storage_key: None,
+    };
+    set_subtitle_version(
+        &state.db_pool,
+        &video_id,
+        track_index,
+        current,
+        Some(existing.current_version()),
+    )
+    .await
+    .map_err(internal_err)?;
+    delete_keys(&state, &existing, previous_keys(&existing)).await;
+
+    info!(
+        "Subtitle track {} of video {} replaced with {}",
+        track_index, video_id, file_name
+    );
+
+    Ok(Json(load_track(&state, &video_id, track_index).await?))
+}
+
+/// Edit the language, title, default and forced flags of a subtitle track
+pub async fn update_subtitle(
+    State(state): State<AppState>,
+    Path((video_id, track)): Path<(String, String)>,
+    Json(body): Json<UpdateSubtitleRequest>,
+) -> Result<Json<SubtitleTrack>, (StatusCode, String)> {
+    let track_index = parse_track_index(&track)?;
+    let existing = load_track(&state, &video_id, track_index).await?;
+
+    let language = match body.language.as_deref() {
+        Some(language) => normalize_language(Some(language))?,
+        None => existing.language.clone(),
+    };
+    let title = match body.title.as_deref() {
+        Some(title) => normalize_title(Some(title))?,
+        None => existing.title.clone(),
+    };
+    let is_default = body.is_default.unwrap_or(existing.is_default);
+
+    if is_default {
+        clear_default_subtitles(&state.db_pool, &video_id, track_index)
+            .await
+            .map_err(internal_err)?;
+    }
+    save_subtitle(
+        &state.db_pool,
+        &video_id,
+        track_index,
+        language.as_deref(),
+        title.as_deref(),
+        &existing.codec,
+        &existing.storage_key,
+        existing.idx_storage_key.as_deref(),
+        is_default,
+        body.is_forced.unwrap_or(existing.is_forced),
+    )
+    .await
+    .map_err(internal_err)?;
+
+    Ok(Json(load_track(&state, &video_id, track_index).await?))
+}
+
+/// Shift and/or rate-correct the timestamps of a text subtitle track. The result is stored as a
+/// new file; the current one is kept as the previous version.
+pub async fn retime_subtitle_track(
+    State(state): State<AppState>,
+    Path((video_id, track)): Path<(String, String)>,
+    Json(body): Json<RetimeSubtitleRequest>,
+) -> Result<Json<SubtitleTrack>, (StatusCode, String)> {
+    let track_index = parse_track_index(&track)?;
+    let retime = parse_retime(&body)?;
+    let existing = load_track(&state, &video_id, track_index).await?;
+
+    let format = SubtitleFormat::from_key(&existing.storage_key).ok_or_else(|| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Only SRT, WebVTT and ASS/SSA tracks can be retimed".to_string(),
+        )
+    })?;
+
+    let bytes = download_bytes_from_r2(&state, &existing.storage_key)
+        .await
+        .map_err(internal_err)?;
+    let content = String::from_utf8_lossy(&bytes);
+    let retimed = retime_subtitle(&content, format, retime)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("Cannot retime subtitle: {}", e)))?;
+
+    let extension = existing
+        .storage_key
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .unwrap_or_default();
+    let storage_key = format!(
+        "{}/subtitles/track_{}_{}.{}",
+        video_id,
+        track_index,
+        now_millis(),
+        extension
+    );
+    upload_bytes_to_r2(&state, &storage_key, retimed.into_bytes(), format.content_type())
+        .await
+        .map_err(internal_err)?;
+
+    let current = SubtitleVersion {
+        codec: &existing.codec,
+        storage_key: &storage_key,
+        idx_storage_key: None,
+    };
+    set_subtitle_version(
+        &state.db_pool,
+        &video_id,
+        track_index,
+        current,
+        Some(existing.current_version()),
+    )
+    .await
+    .map_err(internal_err)?;
+    // Only one previous version is kept
+    delete_keys(&state, &existing, previous_keys(&existing)).await;
+
+    info!(
+        "Subtitle track {} of video {} retimed (offset {}ms, rate {})",
+        track_index, video_id, retime.offset_ms, retime.rate
+    );
+
+    Ok(Json(load_track(&state, &video_id, track_index).await?))
+}
+
+/// Swap a subtitle track back to its previous file; reverting again restores the newer one
+pub async fn revert_subtitle(
+    State(state): State<AppState>,
+    Path((video_id, track)): Path<(String, String)>,
+) -> Result<Json<SubtitleTrack>, (StatusCode, String)> {
+    let track_index = parse_track_index(&track)?;
+    let existing = load_track(&state, &video_id, track_index).await?;
+
+    let previous = existing.previous_version().ok_or_else(|| {
+        (
+            StatusCode::CONFLICT,
+            "Subtitle has no previous version".to_string(),
+        )
+    })?;
+
+    set_subtitle_version(
+        &state.db_pool,
+        &video_id,
+        track_index,
+        previous,
+        Some(existing.current_version()),
+    )
+    .await
+    .map_err(internal_err)?;
+
+    info!(
+        "Subtitle track {} of video {} reverted to {}",
+        track_index, video_id, previous.storage_key
+    );
+
+    Ok(Json(load_track(&state, &video_id, track_index).await?))
+}
+
+/// Delete a subtitle track and its files
+pub async fn delete_subtitle(
+    State(state): State<AppState>,
+    Path((video_id, track)): Path<(String, String)>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let track_index = parse_track_index(&track)?;
+    let existing = load_track(&state, &video_id, track_index).await?;
+
+    db_delete_subtitle(&state.db_pool, &video_id, track_index)
+        .await
+        .map_err(internal_err)?;
+    delete_track_files(&state, &existing).await;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn read_subtitle_form(mut multipart: Multipart) -> Result<SubtitleForm, (StatusCode, String)> {
+    let mut form = SubtitleForm::default();
+
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid multipart body: {}", e)))?
+    {
+        let field_name = field.name().map(|s| s.to_string());
+
+        match field_name.as_deref() {
+            Some("file") => {
+                let file_name = field.file_name().unwrap_or("subtitle").to_string();
+                let bytes = field
+                    .bytes()
+                    .await
+                    .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid file field: {}", e)))?;
+                form.file = Some((file_name, bytes.to_vec()));
+            }
+            Some("language") => form.language = Some(field_text(field).await?),
+            Some("title") => form.title = Some(field_text(field).await?),
+            Some("is_default") => form.is_default = Some(parse_flag(&field_text(field).await?)),
+            Some("is_forced") => form.is_forced = Some(parse_flag(&field_text(field).await?)),
+            _ => continue,
+        }
+    }
+
+    Ok(form)
+}
+
+async fn field_text(field: Field<'_>) -> Result<String, (StatusCode, String)> {
+    field
+        .text()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid form field: {}", e)))
+}
+
+fn parse_flag(text: &str) -> bool {
+    matches!(text.trim(), "true" | "1" | "on")
+}
+
+/// Validate and normalize an uploaded file, then store it as `{id}/subtitles/{file_stem}.*`.
+/// Callers pass a fresh stem for every upload so CDN caches never serve a replaced file.
+/// Returns (codec, storage key).
+async fn store_subtitle_file(
+    state: &AppState,
+    video_id: &str,
+    file_stem: &str,
+    file_name: &str,
+    bytes: &[u8],
+) -> Result<(&'static str, String), (StatusCode, String)> {
+    let subtitle = normalize_subtitle(bytes, file_name)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, format!("Invalid subtitle file: {}", e)))?;
+
+    let storage_key = format!("{}/subtitles/{}.{}", video_id, file_stem, subtitle.extension);
+    let content_type = match subtitle.extension {
+        "ass" => "text/x-ssa",
+        _ => "text/srt",
+    };
+
+    upload_bytes_to_r2(state, &storage_key, subtitle.content.into_bytes(), content_type)
+        .await
+        .map_err(internal_err)?;
+
+    Ok((subtitle.codec, storage_key))
+}
+
+fn parse_retime(body: &RetimeSubtitleRequest) -> Result<Retime, (StatusCode, String)> {
+    let bad_request = |message: &str| Err((StatusCode::BAD_REQUEST, message.to_string()));
+
+    let rate = match (body.rate, body.from_fps, body.to_fps) {
+        (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+            return bad_request("Use either rate or from_fps/to_fps, not both");
+        }
+        (Some(rate), None, None) => rate,
+        (None, Some(from), Some(to)) if from > 0.0 && to > 0.0 => from / to,
+        (None, None, None) => 1.0,
+        _ => return bad_request("from_fps and to_fps must both be positive"),
+    };
+
+    if !(0.5..=2.0).contains(&rate) {
+        return bad_request("rate must be between 0.5 and 2.0");
+    }
+    // A day is far beyond any plausible subtitle offset
+    if body.offset_ms.abs() > 86_400_000 {
+        return bad_request("offset_ms must be within 24 hours");
+    }
+    if body.offset_ms == 0 && rate == 1.0 {
+        return bad_request("Nothing to change: set offset_ms and/or a rate");
+    }
+
+    Ok(Retime {
+        offset_ms: body.offset_ms,
+        rate,
+    })
+}
+
+async fn delete_track_files(state: &AppState, track: &SubtitleTrack) {
+    let keys: Vec<String> = std::iter::once(track.storage_key.clone())
+        .chain(track.idx_storage_key.clone())
+        .chain(previous_keys(track))
+        .collect();
+    delete_keys(state, track, keys).await;
+}
+
+/// Files of the version `track` keeps for a revert
+fn previous_keys(track: &SubtitleTrack) -> Vec<String> {
+    track
+        .previous_storage_key
+        .iter()
+        .chain(&track.previous_idx_storage_key)
+        .cloned()
+        .collect()
+}
+
+/// Best-effort removal of files that are no longer referenced by `track`
+async fn delete_keys(state: &AppState, track: &SubtitleTrack, keys: Vec<String>) {
+    if let Err(e) = bulk_delete_from_r2(state, keys).await {
+        warn!(
+            "Failed to delete files of subtitle track {} of video {}: {}",
+            track.track_index, track.video_id, e
+        );
+    }
+}
+
+async fn load_track(
+    state: &AppState,
+    video_id: &str,
+    track_index: i32,
+) -> Result<SubtitleTrack, (StatusCode, String)> {
+    get_subtitle_by_track(&state.db_pool, video_id, track_index)
+        .await
+        .map_err(internal_err)?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Subtitle not found".to_string()))
+}
+
+/// Track index from "3" or "3.srt", the same form the subtitle file URLs use
+fn parse_track_index(track: &str) -> Result<i32, (StatusCode, String)> {
+    track
+        .split('.')
+        .next()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| (StatusCode::BAD_REQUEST, "Invalid track format".to_string()))
+}
+
+/// Accept ISO 639 codes with optional BCP 47 subtags ("eng", "pt-BR"); empty clears the language
+fn normalize_language(language: Option<&str>) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(language) = language.map(str::trim).filter(|l| !l.is_empty()) else {
+        return Ok(None);
+    };
+
+    let mut parts = language.split('-');
+    let primary = parts.next().unwrap_or_default();
+    let valid = (2..=3).contains(&primary.len())
+        && primary.chars().all(|c| c.is_ascii_alphabetic())
+        && parts.all(|part| (1..=8).contains(&part.len()) && part.chars().all(|c| c.is_ascii_alphanumeric()));
+
+    if !valid {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Invalid language code: {}", language),
+        ));
+    }
+
+    let (primary, rest) = language.split_at(primary.len());
+    Ok(Some(format!("{}{}", primary.to_lowercase(), rest)))
+}
+
+fn normalize_title(title: Option<&str>) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(title) = title.map(str::trim).filter(|t| !t.is_empty()) else {
+        return Ok(None);
+    };
+    if title.chars().count() > MAX_TITLE_CHARS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Title must be at most {} characters", MAX_TITLE_CHARS),
+        ));
+    }
+    Ok(Some(title.to_string()))
+}
@@ -0,0 +1,271 @@
+use crate::database::{VideoRow, get_video, set_posters, set_thumbnail_key};
+use crate::handlers::common::{internal_err, now_millis};
+use crate::storage::{bulk_delete_from_r2, download_bytes_from_r2, upload_bytes_to_r2};
+use crate::thumbnail::{
+    MAX_POSTER_BYTES, POSTERS_DIR, detect_image, find_segment, generate_posters, grab_frame,
+    poster_mime,
+};
+use crate::types::{AppState, PosterImage, VideoVariant};
+
+use axum::{
+    Json,
+    extract::{Multipart, Path, State},
+    http::StatusCode,
+};
+use tokio::fs;
+use tracing::{info, warn};
+
+#[derive(serde::Deserialize)]
+pub struct FrameThumbnailRequest {
+    /// Timestamp of the frame in seconds
+    pub time: f64,
+}
+
+#[derive(serde::Serialize)]
+pub struct ThumbnailResponse {
+    pub thumbnail_key: String,
+    pub thumbnail_url: String,
+}
+
+/// Upload a custom poster (`file`: JPEG, PNG or WebP) as the video's thumbnail
+pub async fn upload_thumbnail(
+    State(state): State<AppState>,
+    Path(video_id): Path<String>,
+    mut multipart: Multipart,
+) -> Result<Json<ThumbnailResponse>, (StatusCode, String)> {
+    let video = load_video(&state, &video_id).await?;
+
+    let mut bytes = None;
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid multipart body: {}", e)))?
+    {
+        if field.name() == Some("file") {
+            let data = field
+                .bytes()
+                .await
+                .map_err(|e| (StatusCode::BAD_REQUEST, format!("Invalid file field: {}", e)))?;
+            bytes = Some(data.to_vec());
+        }
+    }
+
+    let bytes = bytes.ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing image file".to_string()))?;
+    if bytes.len() > MAX_POSTER_BYTES {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            format!("Image is larger than {} MB", MAX_POSTER_BYTES / 1024 / 1024),
+        ));
+    }
+    let (extension, content_type) = detect_image(&bytes).ok_or_else(|| {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Image must be a JPEG, PNG or WebP file".to_string(),
+        )
+    })?;
+
+    // The poster set is rendered from the uploaded image itself
+    let work_dir = std::env::temp_dir().join(format!("thumbnail-{}-{}", video_id, now_millis()));
+    let image_path = work_dir.join(format!("poster.{}", extension));
+    let written = async {
+        fs::create_dir_all(&work_dir).await?;
+        fs::write(&image_path, &bytes).await
+    }
+    .await;
+
+    let response = store_thumbnail(&state, &video, bytes, extension, content_type).await?;
+    match written {
+        Ok(()) => refresh_posters(&state, &video, &image_path, 0.0, None, &work_dir).await,
+        Err(e) => warn!("Failed to stage poster of video {}: {}", video_id, e),
+    }
+    let _ = fs::remove_dir_all(&work_dir).await;
+    info!("Custom poster uploaded for video {}", video_id);
+    Ok(Json(response))
+}
+
+/// Regenerate the thumbnail from the frame at `time`, taken from the highest stored rendition
+/// (the source file is gone once encoding finishes)
+pub async fn pick_thumbnail_frame(
+    State(state): State<AppState>,
+    Path(video_id): Path<String>,
+    Json(body): Json<FrameThumbnailRequest>,
+) -> Result<Json<ThumbnailResponse>, (StatusCode, String)> {
+    let video = load_video(&state, &video_id).await?;
+
+    if !body.time.is_finite() || body.time < 0.0 || body.time > video.duration as f64 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("time must be between 0 and {} seconds", video.duration),
+        ));
+    }
+
+    let (label, width) = highest_rendition(&video).ok_or_else(|| {
+        (
+            StatusCode::CONFLICT,
+            "Video has no stored renditions".to_string(),
+        )
+    })?;
+    let rendition_prefix = format!("{}/{}", video_id, label);
+
+    let playlist = download_bytes_from_r2(&state, &format!("{}/index.m3u8", rendition_prefix))
+        .await
+        .map_err(internal_err)?;
+    let segment = find_segment(&String::from_utf8_lossy(&playlist), body.time).ok_or_else(|| {
+        (
+            StatusCode::CONFLICT,
+            format!("Rendition {} has no segments", label),
+        )
+    })?;
+
+    // fMP4 segments only decode with their init segment in front
+    let mut media = Vec::new();
+    if let Some(init_uri) = &segment.init_uri {
+        let init = download_bytes_from_r2(&state, &format!("{}/{}", rendition_prefix, init_uri))
+            .await
+            .map_err(internal_err)?;
+        media.extend(init);
+    }
+    let data = download_bytes_from_r2(&state, &format!("{}/{}", rendition_prefix, segment.uri))
+        .await
+        .map_err(internal_err)?;
+    media.extend(data);
+
+    let work_dir = std::env::temp_dir().join(format!("thumbnail-{}-{}", video_id, now_millis()));
+    let extension = if segment.init_uri.is_some() { "mp4" } else { "ts" };
+    let media_path = work_dir.join(format!("segment.{}", extension));
+    let frame = async {
+        fs::create_dir_all(&work_dir).await?;
+        let frame_path = work_dir.join("frame.jpg");
+        fs::write(&media_path, media).await?;
+        grab_frame(&media_path, segment.offset, &frame_path).await?;
+        Ok::<_, anyhow::Error>(fs::read(&frame_path).await?)
+    }
+    .await;
+
+    let response = match frame {
+        Ok(frame) => {
+            let response = store_thumbnail(&state, &video, frame, "jpg", "image/jpeg").await;
+            if response.is_ok() {
+                refresh_posters(&state, &video, &media_path, segment.offset, width, &work_dir).await;
+            }
+            response
+        }
+        Err(e) => Err(internal_err(e)),
+    };
+    let _ = fs::remove_dir_all(&work_dir).await;
+    let response = response?;
+    info!(
+        "Thumbnail of video {} regenerated from {} at {:.3}s",
+        video_id, label, body.time
+    );
+    Ok(Json(response))
+}
+
+/// Store the image under a fresh key so CDN caches of the old thumbnail don't matter, point the
+/// video at it and drop the previous one
+async fn store_thumbnail(
+    state: &AppState,
+    video: &VideoRow,
+    bytes: Vec<u8>,
+    extension: &str,
+    content_type: &str,
+) -> Result<ThumbnailResponse, (StatusCode, String)> {
+    let thumbnail_key = format!("{}/thumbnail_{}.{}", video.id, now_millis(), extension);
+
+    upload_bytes_to_r2(state, &thumbnail_key, bytes, content_type)
+        .await
+        .map_err(internal_err)?;
+    set_thumbnail_key(&state.db_pool, &video.id, &thumbnail_key)
+        .await
+        .map_err(internal_err)?;
+
+    // Videos without a storyboard use the thumbnail as their sprite, keep it for them
+    if video.sprites_key.as_deref() != Some(video.thumbnail_key.as_str())
+        && let Err(e) = bulk_delete_from_r2(state, vec![video.thumbnail_key.clone()]).await
+    {
+        warn!(
+            "Failed to delete previous thumbnail {} of video {}: {}",
+            video.thumbnail_key, video.id, e
+        );
+    }
+
+    let base = state.config.r2.public_base_url.trim_end_matches('/');
+    Ok(ThumbnailResponse {
+        thumbnail_url: format!("{}/{}", base, thumbnail_key),
+        thumbnail_key,
+    })
+}
+
+/// Re-render the poster set from `input` under a fresh prefix and replace the stored one. On
+/// failure the set is cleared rather than left showing the previous image.
+async fn refresh_posters(
+    state: &AppState,
+    video: &VideoRow,
+    input: &std::path::Path,
+    seek: f64,
+    source_width: Option<u32>,
+    work_dir: &std::path::Path,
+) {
+    let old_posters: Vec<PosterImage> = video
+        .posters
+        .as_deref()
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default();
+
+    let posters_dir = work_dir.join(POSTERS_DIR);
+    let prefix = format!("{}/{}_{}", video.id, POSTERS_DIR, now_millis());
+    let uploaded = async {
+        let mut posters = Vec::new();
+        for poster in generate_posters(input, seek, source_width, None, "", &posters_dir).await? {
+            let key = format!("{}/{}", prefix, poster.key);
+            let bytes = fs::read(posters_dir.join(&poster.key)).await?;
+            let content_type = poster_mime(&key).unwrap_or("application/octet-stream");
+            upload_bytes_to_r2(state, &key, bytes, content_type).await?;
+            posters.push(PosterImage { key, ..poster });
+        }
+        Ok::<_, anyhow::Error>(posters)
+    }
+    .await;
+
+    let posters = uploaded.unwrap_or_else(|e| {
+        warn!("Failed to regenerate posters of video {}: {}", video.id, e);
+        Vec::new()
+    });
+    if let Err(e) = set_posters(&state.db_pool, &video.id, &posters).await {
+        warn!("Failed to save posters of video {}: {}", video.id, e);
+        return;
+    }
+
+    let old_keys: Vec<String> = old_posters.into_iter().map(|poster| poster.key).collect();
+    if !old_keys.is_empty()
+        && let Err(e) = bulk_delete_from_r2(state, old_keys).await
+    {
+        warn!("Failed to delete previous posters of video {}: {}", video.id, e);
+    }
+}
+
+async fn load_video(state: &AppState, video_id: &str) -> Result<VideoRow, (StatusCode, String)> {
+    get_video(&state.db_pool, video_id)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Video not found".to_string()))
+}
+
+/// Label (and width, when known) of the largest H.264 rendition, from the stored ladder or, for
+/// older videos, the resolution labels
+fn highest_rendition(video: &VideoRow) -> Option<(String, Option<u32>)> {
+    let ladder: Vec<VideoVariant> = video
+        .ladder
+        .as_deref()
+        .and_then(|json| serde_json::from_str(json).ok())
+        .unwrap_or_default();
+    if let Some(variant) = ladder.iter().max_by_key(|v| v.width * v.height) {
+        return Some((variant.label.clone(), Some(variant.width)));
+    }
+
+    let resolutions: Vec<String> =
+        serde_json::from_str(&video.available_resolutions).unwrap_or_default();
+    resolutions
+        .into_iter()
+        .max_by_key(|label| label.trim_end_matches('p').parse::<u32>().unwrap_or(0))
+        .map(|label| (label, None))
+}
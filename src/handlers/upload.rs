@@ -1,14 +1,12 @@
-use crate::database::{save_attachment, save_chapter, save_subtitle, save_video};
+use crate::database::{
+    cancel_pending_job, create_job, delete_finished_jobs, enqueue_job, get_job, list_jobs,
+    update_job_progress,
+};
 use crate::handlers::common::{internal_err, now_millis};
-use crate::storage::upload_hls_to_r2;
+use crate::jobs::{chunked_temp_dir, failed_progress, remove_old_jobs};
 use crate::types::{
-    AppState, ChunkUploadResponse, ChunkedUpload, FinalizeUploadRequest, ProgressMap,
-    ProgressResponse, ProgressUpdate, QueueItem, QueueListResponse, UploadAccepted, UploadResponse,
-};
-use crate::video::{
-    encode_to_hls, extract_all_attachments, extract_subtitle, get_attachments, get_audio_streams,
-    get_chapters, get_subtitle_streams, get_variants_for_height, get_video_duration,
-    get_video_height,
+    AppState, ChunkUploadResponse, ChunkedUpload, CropRect, FinalizeUploadRequest, ProgressResponse,
+    ProgressUpdate, QueueItem, QueueListResponse, UploadAccepted, UploadOptions, UploadResponse,
 };
 
 use axum::{
@@ -18,7 +16,7 @@ use axum::{
     response::sse::{Event, Sse},
 };
 use futures::stream::Stream;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::time::Duration;
 use tokio::{fs, io::AsyncReadExt, io::AsyncWriteExt};
@@ -28,6 +26,12 @@ use uuid::Uuid;
 // Stale upload timeout: 30 minutes of inactivity
 const STALE_UPLOAD_TIMEOUT_MS: u64 = 30 * 60 * 1000;
 
+// Minimum interval between byte-progress writes while a single-request upload streams in
+const UPLOAD_PROGRESS_INTERVAL_MS: u64 = 500;
+
+// Completed jobs stay visible in the queue list for this long
+const COMPLETED_JOB_RETENTION_MS: u64 = 60 * 60 * 1000;
+
 // Supported video file extensions
 const ALLOWED_VIDEO_EXTENSIONS: &[&str] = &[
     "mp4", "m4v",           // MP4 container
@@ -61,6 +65,20 @@ fn validate_video_extension(filename: &str) -> Result<(), String> {
     }
 }
 
+/// Parse the per-upload crop override (`w:h:x:y`); empty means none
+fn parse_crop(value: &str) -> Result<Option<CropRect>, String> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    let crop = CropRect::parse(value)
+        .ok_or_else(|| format!("Invalid crop '{}', expected width:height:x:y", value.trim()))?;
+    // The frame size is only known once the source is probed, but an edge past u32::MAX fits none
+    if !crop.fits(u32::MAX, u32::MAX) {
+        return Err(format!("Invalid crop '{}', it extends past any frame", value.trim()));
+    }
+    Ok(Some(crop))
+}
+
 /// Clean up stale chunked uploads that have been inactive for too long
 async fn cleanup_stale_uploads(state: &AppState) {
     let now = now_millis();
@@ -77,23 +95,61 @@ async fn cleanup_stale_uploads(state: &AppState) {
 
     if !to_remove.is_empty() {
         let mut uploads = state.chunked_uploads.write().await;
-        let mut progress_map = state.progress.write().await;
 
         for (id, temp_dir) in to_remove {
             uploads.remove(&id);
-            progress_map.remove(&id);
             let _ = fs::remove_dir_all(&temp_dir).await;
+            let progress = failed_progress(
+                "Upload timed out",
+                "Upload timed out waiting for more chunks".to_string(),
+            );
+            if let Err(e) = update_job_progress(&state.db_pool, &id, &progress).await {
+                error!("Failed to mark stale upload {} as failed: {}", id, e);
+            }
             warn!("Cleaned up stale chunked upload: {}", id);
         }
     }
 }
 
-async fn update_progress(progress_map: &ProgressMap, upload_id: &str, mut update: ProgressUpdate) {
-    let mut map = progress_map.write().await;
-    if let Some(existing) = map.get(upload_id) {
-        update.created_at = existing.created_at;
+/// Queue a fully received upload for processing and wake the worker
+async fn queue_upload(
+    state: &AppState,
+    upload_id: &str,
+    video_path: &std::path::Path,
+    video_name: &str,
+    tags: &[String],
+    options: &UploadOptions,
+) -> Result<(), (StatusCode, String)> {
+    let video_id = Uuid::new_v4().to_string();
+
+    let queued = enqueue_job(
+        &state.db_pool,
+        upload_id,
+        &video_id,
+        video_name,
+        tags,
+        options,
+        &video_path.to_string_lossy(),
+    )
+    .await;
+
+    match queued {
+        Ok(true) => {}
+        Ok(false) => {
+            let _ = fs::remove_file(video_path).await;
+            return Err((
+                StatusCode::CONFLICT,
+                "Upload was cancelled before processing started".to_string(),
+            ));
+        }
+        Err(e) => {
+            let _ = fs::remove_file(video_path).await;
+            return Err(internal_err(e));
+        }
     }
-    map.insert(upload_id.to_string(), update);
+
+    state.job_notify.notify_one();
+    Ok(())
 }
 
 pub async fn upload_video(
@@ -104,6 +160,7 @@ pub async fn upload_video(
     let mut video_path: Option<PathBuf> = None;
     let mut video_name: Option<String> = None;
     let mut tags: Vec<String> = Vec::new();
+    let mut options = UploadOptions::default();
 
     let upload_id = headers
         .get("X-Upload-ID")
@@ -111,24 +168,25 @@ pub async fn upload_video(
         .map(|s| s.to_string())
         .unwrap_or_else(|| Uuid::new_v4().to_string());
 
+    let initial_progress = ProgressUpdate {
+        stage: "Initializing upload".to_string(),
+        current_chunk: 0,
+        total_chunks: 1,
+        percentage: 0,
+        details: Some("Waiting for file data...".to_string()),
+        status: "initializing".to_string(),
+        result: None,
+        error: None,
+        video_name: None,
+    };
+    if !create_job(&state.db_pool, &upload_id, &initial_progress)
+        .await
+        .map_err(internal_err)?
     {
-        let initial_progress = ProgressUpdate {
-            stage: "Initializing upload".to_string(),
-            current_chunk: 0,
-            total_chunks: 1,
-            percentage: 0,
-            details: Some("Waiting for file data...".to_string()),
-            status: "initializing".to_string(),
-            result: None,
-            error: None,
-            video_name: None,
-            created_at: now_millis(),
-        };
-        state
-            .progress
-            .write()
-            .await
-            .insert(upload_id.clone(), initial_progress);
+        return Err((
+            StatusCode::CONFLICT,
+            "Upload ID belongs to a job that is already queued or done".to_string(),
+        ));
     }
 
     while let Some(mut field) = multipart
@@ -147,6 +205,12 @@ pub async fn upload_video(
 
                 // Validate file extension
                 if let Err(err) = validate_video_extension(&file_name) {
+                    let _ = update_job_progress(
+                        &state.db_pool,
+                        &upload_id,
+                        &failed_progress(&err, err.clone()),
+                    )
+                    .await;
                     return Err((StatusCode::BAD_REQUEST, err));
                 }
 
@@ -158,6 +222,7 @@ pub async fn upload_video(
                     .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
 
                 let mut total_bytes = 0;
+                let mut last_report = 0;
                 while let Some(chunk) = field
                     .chunk()
                     .await
@@ -168,20 +233,26 @@ pub async fn upload_video(
                         .await
                         .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
 
-                    if !upload_id.is_empty() {
+                    // Throttle progress writes so large uploads don't hammer the database
+                    let now = now_millis();
+                    if now.saturating_sub(last_report) >= UPLOAD_PROGRESS_INTERVAL_MS {
+                        last_report = now;
                         let progress_update = ProgressUpdate {
                             stage: "Uploading to server".to_string(),
                             current_chunk: 0,
                             total_chunks: 1,
                             percentage: 0,
                             details: Some(format!("Uploaded {} bytes", total_bytes)),
-                            status: "processing".to_string(),
+                            status: "initializing".to_string(),
                             result: None,
                             error: None,
                             video_name: None,
-                            created_at: 0,
                         };
-                        update_progress(&state.progress, &upload_id, progress_update).await;
+                        if let Err(e) =
+                            update_job_progress(&state.db_pool, &upload_id, &progress_update).await
+                        {
+                            error!("Failed to record upload progress for {}: {}", upload_id, e);
+                        }
                     }
                 }
 
@@ -209,6 +280,46 @@ pub async fn upload_video(
                         .collect();
                 }
             }
+            Some("normalize_audio") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
+                // Accepts JSON booleans as well as HTML checkbox values
+                options.normalize_audio = Some(matches!(text.trim(), "true" | "1" | "on"));
+            }
+            Some("auto_chapters") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
+                options.auto_chapters = Some(matches!(text.trim(), "true" | "1" | "on"));
+            }
+            Some("auto_crop") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
+                options.auto_crop = Some(matches!(text.trim(), "true" | "1" | "on"));
+            }
+            Some("crop") => {
+                let text = field
+                    .text()
+                    .await
+                    .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
+                match parse_crop(&text) {
+                    Ok(crop) => options.crop = crop,
+                    Err(err) => {
+                        let _ = update_job_progress(
+                            &state.db_pool,
+                            &upload_id,
+                            &failed_progress(&err, err.clone()),
+                        )
+                        .await;
+                        return Err((StatusCode::BAD_REQUEST, err));
+                    }
+                }
+            }
             _ => {
                 continue;
             }
@@ -225,270 +336,7 @@ pub async fn upload_video(
     let video_name =
         video_name.ok_or_else(|| (StatusCode::BAD_REQUEST, "missing field 'name'".to_string()))?;
 
-    let initial_progress = ProgressUpdate {
-        stage: "Queued for processing".to_string(),
-        current_chunk: 0,
-        total_chunks: 1,
-        percentage: 0,
-        details: None,
-        status: "processing".to_string(),
-        result: None,
-        error: None,
-        video_name: Some(video_name.clone()),
-        created_at: 0,
-    };
-    update_progress(&state.progress, &upload_id, initial_progress).await;
-
-    let state_clone = state.clone();
-    let upload_id_clone = upload_id.clone();
-    let video_path_clone = video_path.clone();
-    let video_name_clone = video_name.clone();
-    let tags_clone = tags.clone();
-
-    tokio::spawn(async move {
-        let result = async {
-            let output_id = Uuid::new_v4().to_string();
-            let hls_dir = std::env::temp_dir().join(format!("hls-{}", &output_id));
-            fs::create_dir_all(&hls_dir)
-                .await
-                .map_err(|e| anyhow::anyhow!(e))?;
-
-            let (video_duration, original_height) = tokio::join!(
-                get_video_duration(&video_path_clone),
-                get_video_height(&video_path_clone)
-            );
-            let video_duration = video_duration?;
-            let original_height = original_height?;
-            let variants = get_variants_for_height(original_height);
-            let available_resolutions: Vec<String> =
-                variants.iter().map(|v| v.label.clone()).collect();
-
-            let encoding_progress = ProgressUpdate {
-                stage: "FFmpeg processing".to_string(),
-                current_chunk: 0,
-                total_chunks: variants.len() as u32,
-                percentage: 0,
-                details: Some("Starting encoding...".to_string()),
-                status: "processing".to_string(),
-                result: None,
-                error: None,
-                video_name: Some(video_name_clone.clone()),
-                created_at: 0,
-            };
-            update_progress(&state_clone.progress, &upload_id_clone, encoding_progress).await;
-
-            // Get audio streams for multi-audio encoding
-            let audio_streams = get_audio_streams(&video_path_clone)
-                .await
-                .unwrap_or_default();
-
-            encode_to_hls(
-                &video_path_clone,
-                &hls_dir,
-                &state_clone.progress,
-                &upload_id_clone,
-                state_clone.ffmpeg_semaphore.clone(),
-                &state_clone.config.video.encoder,
-                video_duration,
-                &audio_streams,
-            )
-            .await?;
-
-            // Extract subtitles and attachments from the source video
-            let subtitle_streams = get_subtitle_streams(&video_path_clone)
-                .await
-                .unwrap_or_default();
-            let attachment_streams = get_attachments(&video_path_clone).await.unwrap_or_default();
-
-            // Create directories for subtitles and fonts
-            let subtitles_dir = hls_dir.join("subtitles");
-            let fonts_dir = hls_dir.join("fonts");
-
-            if !subtitle_streams.is_empty() {
-                fs::create_dir_all(&subtitles_dir).await?;
-            }
-            if !attachment_streams.is_empty() {
-                fs::create_dir_all(&fonts_dir).await?;
-                // Extract all font attachments
-                extract_all_attachments(&video_path_clone, &fonts_dir).await?;
-            }
-
-            // Extract each subtitle stream
-            for (idx, sub) in subtitle_streams.iter().enumerate() {
-                let ext = match sub.codec_name.as_str() {
-                    "ass" | "ssa" => "ass",
-                    "subrip" | "srt" => "srt",
-                    _ => "ass", // Default to ASS
-                };
-                let sub_filename = format!("track_{}.{}", idx, ext);
-                let sub_path = subtitles_dir.join(&sub_filename);
-
-                // Use enumerate index (idx) as relative subtitle stream index
-                if let Err(e) =
-                    extract_subtitle(&video_path_clone, idx as i32, &sub_path, &sub.codec_name)
-                        .await
-                {
-                    error!(
-                        "Failed to extract subtitle stream {} (track {}): {}",
-                        sub.stream_index, idx, e
-                    );
-                }
-            }
-
-            let upload_progress = ProgressUpdate {
-                stage: "Upload to R2".to_string(),
-                current_chunk: 0,
-                total_chunks: 1,
-                percentage: 0,
-                details: Some("Uploading segments to storage...".to_string()),
-                status: "processing".to_string(),
-                result: None,
-                error: None,
-                video_name: Some(video_name_clone.clone()),
-                created_at: 0,
-            };
-            update_progress(&state_clone.progress, &upload_id_clone, upload_progress).await;
-
-            info!("Starting R2 upload for video: {}", output_id);
-            let prefix = format!("{}/", output_id);
-            let playlist_key =
-                upload_hls_to_r2(&state_clone, &hls_dir, &prefix, Some(&upload_id_clone)).await?;
-            info!("Completed R2 upload. Master playlist key: {}", playlist_key);
-
-            let thumbnail_key = format!("{}/thumbnail.jpg", output_id);
-            let sprites_key = format!("{}/sprites.jpg", output_id);
-            let entrypoint = playlist_key.clone();
-
-            save_video(
-                &state_clone.db_pool,
-                &output_id,
-                &video_name_clone,
-                &tags_clone,
-                &available_resolutions,
-                video_duration,
-                &thumbnail_key,
-                &sprites_key,
-                &entrypoint,
-            )
-            .await?;
-
-            // Save subtitle metadata to database
-            for (idx, sub) in subtitle_streams.iter().enumerate() {
-                let ext = match sub.codec_name.as_str() {
-                    "ass" | "ssa" => "ass",
-                    "subrip" | "srt" => "srt",
-                    _ => "ass",
-                };
-                let storage_key = format!("{}/subtitles/track_{}.{}", output_id, idx, ext);
-
-                if let Err(e) = save_subtitle(
-                    &state_clone.db_pool,
-                    &output_id,
-                    idx as i32,
-                    sub.language.as_deref(),
-                    sub.title.as_deref(),
-                    &sub.codec_name,
-                    &storage_key,
-                    None, // idx_storage_key for VobSub
-                    sub.is_default,
-                    sub.is_forced,
-                )
-                .await
-                {
-                    error!("Failed to save subtitle metadata for track {}: {}", idx, e);
-                }
-            }
-
-            // Save attachment metadata to database
-            for att in &attachment_streams {
-                let storage_key = format!("{}/fonts/{}", output_id, att.filename);
-
-                if let Err(e) = save_attachment(
-                    &state_clone.db_pool,
-                    &output_id,
-                    &att.filename,
-                    &att.mimetype,
-                    &storage_key,
-                )
-                .await
-                {
-                    error!(
-                        "Failed to save attachment metadata for {}: {}",
-                        att.filename, e
-                    );
-                }
-            }
-
-            // Extract and save chapters from video
-            let chapter_streams = get_chapters(&video_path_clone).await.unwrap_or_default();
-            for (idx, chapter) in chapter_streams.iter().enumerate() {
-                if let Err(e) = save_chapter(
-                    &state_clone.db_pool,
-                    &output_id,
-                    idx as i32,
-                    chapter.start_time,
-                    chapter.end_time,
-                    &chapter.title,
-                )
-                .await
-                {
-                    error!("Failed to save chapter metadata for index {}: {}", idx, e);
-                }
-            }
-
-            let _ = fs::remove_file(&video_path_clone).await;
-            let _ = fs::remove_dir_all(&hls_dir).await;
-
-            let player_url = format!("/player/{}", output_id);
-            Ok::<_, anyhow::Error>(UploadResponse {
-                player_url,
-                upload_id: upload_id_clone.clone(),
-            })
-        }
-        .await;
-
-        match result {
-            Ok(response) => {
-                let completion_progress = ProgressUpdate {
-                    stage: "Completed".to_string(),
-                    current_chunk: 1,
-                    total_chunks: 1,
-                    percentage: 100,
-                    details: Some("Upload and processing complete".to_string()),
-                    status: "completed".to_string(),
-                    result: Some(response),
-                    error: None,
-                    video_name: Some(video_name_clone.clone()),
-                    created_at: 0,
-                };
-                update_progress(&state_clone.progress, &upload_id_clone, completion_progress).await;
-            }
-            Err(e) => {
-                error!("Background processing failed: {:?}", e);
-                let error_progress = ProgressUpdate {
-                    stage: "Failed".to_string(),
-                    current_chunk: 0,
-                    total_chunks: 1,
-                    percentage: 0,
-                    details: Some(format!("Processing failed: {}", e)),
-                    status: "failed".to_string(),
-                    result: None,
-                    error: Some(e.to_string()),
-                    video_name: Some(video_name_clone.clone()),
-                    created_at: 0,
-                };
-                update_progress(&state_clone.progress, &upload_id_clone, error_progress).await;
-            }
-        }
-
-        tokio::time::sleep(Duration::from_secs(10)).await;
-        let mut progress_map = state_clone.progress.write().await;
-        if let Some(entry) = progress_map.get(&upload_id_clone)
-            && (entry.status == "completed" || entry.status == "failed")
-        {
-            progress_map.remove(&upload_id_clone);
-        }
-    });
+    queue_upload(&state, &upload_id, &video_path, &video_name, &tags, &options).await?;
 
     Ok(Json(UploadAccepted {
         upload_id,
@@ -587,7 +435,20 @@ pub async fn upload_chunk(
         let mut uploads = state.chunked_uploads.write().await;
 
         if !uploads.contains_key(&upload_id) {
-            let temp_dir = std::env::temp_dir().join(format!("chunked-{}", upload_id));
+            // The upload was cancelled or timed out mid-transfer, or was already finalized and
+            // this is a late or retried chunk; either way the job must not be reset
+            if let Some(job) = get_job(&state.db_pool, &upload_id)
+                .await
+                .map_err(internal_err)?
+                && job.status != "initializing"
+            {
+                return Err((
+                    StatusCode::CONFLICT,
+                    format!("Upload is no longer active (stage: {})", job.stage),
+                ));
+            }
+
+            let temp_dir = chunked_temp_dir(&upload_id);
             fs::create_dir_all(&temp_dir)
                 .await
                 .map_err(|e| internal_err(anyhow::anyhow!(e)))?;
@@ -609,17 +470,23 @@ pub async fn upload_chunk(
                 total_chunks,
                 percentage: 0,
                 details: Some(format!("Receiving chunk 1 of {}", total_chunks)),
-                status: "processing".to_string(),
+                status: "initializing".to_string(),
                 result: None,
                 error: None,
                 video_name: Some(file_name.replace(&['.'][..], "_")),
-                created_at: now_millis(),
             };
-            state
-                .progress
-                .write()
+            // The job may have been finalized since the check above
+            if !create_job(&state.db_pool, &upload_id, &progress)
                 .await
-                .insert(upload_id.clone(), progress);
+                .map_err(internal_err)?
+            {
+                uploads.remove(&upload_id);
+                let _ = fs::remove_dir_all(&temp_dir).await;
+                return Err((
+                    StatusCode::CONFLICT,
+                    "Upload is no longer active".to_string(),
+                ));
+            }
         }
 
         uploads.get(&upload_id).unwrap().temp_dir.clone()
@@ -663,13 +530,14 @@ pub async fn upload_chunk(
             "Received chunk {} of {}",
             received_count, total_chunks
         )),
-        status: "processing".to_string(),
+        status: "initializing".to_string(),
         result: None,
         error: None,
-        video_name: Some(file_name.replace(&['.'][..], "_")),
-        created_at: 0,
+        video_name: None,
     };
-    update_progress(&state.progress, &upload_id, progress).await;
+    if let Err(e) = update_job_progress(&state.db_pool, &upload_id, &progress).await {
+        error!("Failed to record chunk progress for {}: {}", upload_id, e);
+    }
 
     Ok(Json(ChunkUploadResponse {
         upload_id,
@@ -678,7 +546,7 @@ pub async fn upload_chunk(
     }))
 }
 
-// Finalize chunked upload - assembles chunks and starts processing
+// Finalize chunked upload - assembles chunks and queues it for processing
 pub async fn finalize_chunked_upload(
     State(state): State<AppState>,
     headers: HeaderMap,
@@ -694,6 +562,9 @@ pub async fn finalize_chunked_upload(
                 "Missing X-Upload-ID header".to_string(),
             )
         })?;
+    // Reject a bad override before assembling the file
+    let crop = parse_crop(body.crop.as_deref().unwrap_or_default())
+        .map_err(|err| (StatusCode::BAD_REQUEST, err))?;
 
     info!("Finalizing chunked upload: {}", upload_id);
 
@@ -709,10 +580,23 @@ pub async fn finalize_chunked_upload(
 
     // Validate file extension
     if let Err(err) = validate_video_extension(&chunked_upload.file_name) {
+        let _ = fs::remove_dir_all(&chunked_upload.temp_dir).await;
+        let _ = update_job_progress(
+            &state.db_pool,
+            &upload_id,
+            &failed_progress(&err, err.clone()),
+        )
+        .await;
         return Err((StatusCode::BAD_REQUEST, err));
     }
 
     if !chunked_upload.received_chunks.iter().all(|&r| r) {
+        // Put the upload back so the client can send the missing chunks
+        state
+            .chunked_uploads
+            .write()
+            .await
+            .insert(upload_id.clone(), chunked_upload);
         return Err((
             StatusCode::BAD_REQUEST,
             "Not all chunks have been received".to_string(),
@@ -725,13 +609,15 @@ pub async fn finalize_chunked_upload(
         total_chunks: chunked_upload.total_chunks,
         percentage: 100,
         details: Some("Assembling chunks into final file...".to_string()),
-        status: "processing".to_string(),
+        status: "initializing".to_string(),
         result: None,
         error: None,
         video_name: Some(body.name.clone()),
-        created_at: 0,
     };
-    update_progress(&state.progress, &upload_id, progress).await;
+    if let Err(e) = update_job_progress(&state.db_pool, &upload_id, &progress).await {
+        error!("Failed to record progress for {}: {}", upload_id, e);
+    }
+
     let final_path =
         std::env::temp_dir().join(format!("{}-{}", Uuid::new_v4(), chunked_upload.file_name));
     let mut final_file = fs::File::create(&final_path)
@@ -768,271 +654,14 @@ pub async fn finalize_chunked_upload(
         })
         .unwrap_or_default();
 
-    let video_name = body.name;
-
-    let progress = ProgressUpdate {
-        stage: "Queued for processing".to_string(),
-        current_chunk: 0,
-        total_chunks: 1,
-        percentage: 0,
-        details: None,
-        status: "processing".to_string(),
-        result: None,
-        error: None,
-        video_name: Some(video_name.clone()),
-        created_at: 0,
+    let options = UploadOptions {
+        normalize_audio: body.normalize_audio,
+        auto_chapters: body.auto_chapters,
+        auto_crop: body.auto_crop,
+        crop,
     };
-    update_progress(&state.progress, &upload_id, progress).await;
-    let state_clone = state.clone();
-    let upload_id_clone = upload_id.clone();
-    let video_path_clone = final_path.clone();
-    let video_name_clone = video_name.clone();
-    let tags_clone = tags.clone();
-
-    tokio::spawn(async move {
-        let result = async {
-            let output_id = Uuid::new_v4().to_string();
-            let hls_dir = std::env::temp_dir().join(format!("hls-{}", &output_id));
-            fs::create_dir_all(&hls_dir)
-                .await
-                .map_err(|e| anyhow::anyhow!(e))?;
-
-            let (video_duration, original_height) = tokio::join!(
-                get_video_duration(&video_path_clone),
-                get_video_height(&video_path_clone)
-            );
-            let video_duration = video_duration?;
-            let original_height = original_height?;
-            let variants = get_variants_for_height(original_height);
-            let available_resolutions: Vec<String> =
-                variants.iter().map(|v| v.label.clone()).collect();
-
-            let encoding_progress = ProgressUpdate {
-                stage: "FFmpeg processing".to_string(),
-                current_chunk: 0,
-                total_chunks: variants.len() as u32,
-                percentage: 0,
-                details: Some("Starting encoding...".to_string()),
-                status: "processing".to_string(),
-                result: None,
-                error: None,
-                video_name: Some(video_name_clone.clone()),
-                created_at: 0,
-            };
-            update_progress(&state_clone.progress, &upload_id_clone, encoding_progress).await;
 
-            // Get audio streams for multi-audio encoding
-            let audio_streams = get_audio_streams(&video_path_clone)
-                .await
-                .unwrap_or_default();
-
-            encode_to_hls(
-                &video_path_clone,
-                &hls_dir,
-                &state_clone.progress,
-                &upload_id_clone,
-                state_clone.ffmpeg_semaphore.clone(),
-                &state_clone.config.video.encoder,
-                video_duration,
-                &audio_streams,
-            )
-            .await?;
-
-            // Extract subtitles and attachments from the source video
-            let subtitle_streams = get_subtitle_streams(&video_path_clone)
-                .await
-                .unwrap_or_default();
-            let attachment_streams = get_attachments(&video_path_clone).await.unwrap_or_default();
-
-            // Create directories for subtitles and fonts
-            let subtitles_dir = hls_dir.join("subtitles");
-            let fonts_dir = hls_dir.join("fonts");
-
-            if !subtitle_streams.is_empty() {
-                fs::create_dir_all(&subtitles_dir).await?;
-            }
-            if !attachment_streams.is_empty() {
-                fs::create_dir_all(&fonts_dir).await?;
-                // Extract all font attachments
-                extract_all_attachments(&video_path_clone, &fonts_dir).await?;
-            }
-
-            // Extract each subtitle stream
-            for (idx, sub) in subtitle_streams.iter().enumerate() {
-                let ext = match sub.codec_name.as_str() {
-                    "ass" | "ssa" => "ass",
-                    "subrip" | "srt" => "srt",
-                    _ => "ass", // Default to ASS
-                };
-                let sub_filename = format!("track_{}.{}", idx, ext);
-                let sub_path = subtitles_dir.join(&sub_filename);
-
-                // Use enumerate index (idx) as relative subtitle stream index
-                if let Err(e) =
-                    extract_subtitle(&video_path_clone, idx as i32, &sub_path, &sub.codec_name)
-                        .await
-                {
-                    error!(
-                        "Failed to extract subtitle stream {} (track {}): {}",
-                        sub.stream_index, idx, e
-                    );
-                }
-            }
-
-            let upload_progress = ProgressUpdate {
-                stage: "Upload to R2".to_string(),
-                current_chunk: 0,
-                total_chunks: 1,
-                percentage: 0,
-                details: Some("Uploading segments to storage...".to_string()),
-                status: "processing".to_string(),
-                result: None,
-                error: None,
-                video_name: Some(video_name_clone.clone()),
-                created_at: 0,
-            };
-            update_progress(&state_clone.progress, &upload_id_clone, upload_progress).await;
-
-            info!("Starting R2 upload for video: {}", output_id);
-            let prefix = format!("{}/", output_id);
-            let playlist_key =
-                upload_hls_to_r2(&state_clone, &hls_dir, &prefix, Some(&upload_id_clone)).await?;
-            info!("Completed R2 upload. Master playlist key: {}", playlist_key);
-
-            let thumbnail_key = format!("{}/thumbnail.jpg", output_id);
-            let sprites_key = format!("{}/sprites.jpg", output_id);
-            let entrypoint = playlist_key.clone();
-
-            save_video(
-                &state_clone.db_pool,
-                &output_id,
-                &video_name_clone,
-                &tags_clone,
-                &available_resolutions,
-                video_duration,
-                &thumbnail_key,
-                &sprites_key,
-                &entrypoint,
-            )
-            .await?;
-
-            // Save subtitle metadata to database
-            for (idx, sub) in subtitle_streams.iter().enumerate() {
-                let ext = match sub.codec_name.as_str() {
-                    "ass" | "ssa" => "ass",
-                    "subrip" | "srt" => "srt",
-                    _ => "ass",
-                };
-                let storage_key = format!("{}/subtitles/track_{}.{}", output_id, idx, ext);
-
-                if let Err(e) = save_subtitle(
-                    &state_clone.db_pool,
-                    &output_id,
-                    idx as i32,
-                    sub.language.as_deref(),
-                    sub.title.as_deref(),
-                    &sub.codec_name,
-                    &storage_key,
-                    None, // idx_storage_key for VobSub
-                    sub.is_default,
-                    sub.is_forced,
-                )
-                .await
-                {
-                    error!("Failed to save subtitle metadata for track {}: {}", idx, e);
-                }
-            }
-
-            // Save attachment metadata to database
-            for att in &attachment_streams {
-                let storage_key = format!("{}/fonts/{}", output_id, att.filename);
-
-                if let Err(e) = save_attachment(
-                    &state_clone.db_pool,
-                    &output_id,
-                    &att.filename,
-                    &att.mimetype,
-                    &storage_key,
-                )
-                .await
-                {
-                    error!(
-                        "Failed to save attachment metadata for {}: {}",
-                        att.filename, e
-                    );
-                }
-            }
-
-            // Extract and save chapters from video
-            let chapter_streams = get_chapters(&video_path_clone).await.unwrap_or_default();
-            for (idx, chapter) in chapter_streams.iter().enumerate() {
-                if let Err(e) = save_chapter(
-                    &state_clone.db_pool,
-                    &output_id,
-                    idx as i32,
-                    chapter.start_time,
-                    chapter.end_time,
-                    &chapter.title,
-                )
-                .await
-                {
-                    error!("Failed to save chapter metadata for index {}: {}", idx, e);
-                }
-            }
-
-            let _ = fs::remove_file(&video_path_clone).await;
-            let _ = fs::remove_dir_all(&hls_dir).await;
-
-            let player_url = format!("/player/{}", output_id);
-            Ok::<_, anyhow::Error>(UploadResponse {
-                player_url,
-                upload_id: upload_id_clone.clone(),
-            })
-        }
-        .await;
-
-        match result {
-            Ok(response) => {
-                let completion_progress = ProgressUpdate {
-                    stage: "Completed".to_string(),
-                    current_chunk: 1,
-                    total_chunks: 1,
-                    percentage: 100,
-                    details: Some("Upload and processing complete".to_string()),
-                    status: "completed".to_string(),
-                    result: Some(response),
-                    error: None,
-                    video_name: Some(video_name_clone.clone()),
-                    created_at: 0,
-                };
-                update_progress(&state_clone.progress, &upload_id_clone, completion_progress).await;
-            }
-            Err(e) => {
-                error!("Background processing failed: {:?}", e);
-                let error_progress = ProgressUpdate {
-                    stage: "Failed".to_string(),
-                    current_chunk: 0,
-                    total_chunks: 1,
-                    percentage: 0,
-                    details: Some(format!("Processing failed: {}", e)),
-                    status: "failed".to_string(),
-                    result: None,
-                    error: Some(e.to_string()),
-                    video_name: Some(video_name_clone.clone()),
-                    created_at: 0,
-                };
-                update_progress(&state_clone.progress, &upload_id_clone, error_progress).await;
-            }
-        }
-
-        tokio::time::sleep(Duration::from_secs(10)).await;
-        let mut progress_map = state_clone.progress.write().await;
-        if let Some(entry) = progress_map.get(&upload_id_clone)
-            && (entry.status == "completed" || entry.status == "failed")
-        {
-            progress_map.remove(&upload_id_clone);
-        }
-    });
+    queue_upload(&state, &upload_id, &final_path, &body.name, &tags, &options).await?;
 
     Ok(Json(UploadAccepted {
         upload_id,
@@ -1040,40 +669,45 @@ pub async fn finalize_chunked_upload(
     }))
 }
 
-pub async fn list_queues(State(state): State<AppState>) -> Json<QueueListResponse> {
-    let progress_map = state.progress.read().await;
-
-    let mut items: Vec<QueueItem> = progress_map
-        .iter()
-        .map(|(id, p)| QueueItem {
-            upload_id: id.clone(),
-            stage: p.stage.clone(),
-            current_chunk: p.current_chunk,
-            total_chunks: p.total_chunks,
-            percentage: p.percentage,
-            details: p.details.clone(),
-            status: p.status.clone(),
-            video_name: p.video_name.clone(),
-            created_at: p.created_at,
+pub async fn list_queues(
+    State(state): State<AppState>,
+) -> Result<Json<QueueListResponse>, (StatusCode, String)> {
+    let completed_since = now_millis().saturating_sub(COMPLETED_JOB_RETENTION_MS);
+    let jobs = list_jobs(&state.db_pool, completed_since)
+        .await
+        .map_err(internal_err)?;
+
+    // Jobs come back ordered by created_at, which keeps the queue order stable (oldest first)
+    let items: Vec<QueueItem> = jobs
+        .into_iter()
+        .map(|job| QueueItem {
+            upload_id: job.id,
+            stage: job.stage,
+            current_chunk: job.current_chunk,
+            total_chunks: job.total_chunks,
+            percentage: job.percentage,
+            details: job.details,
+            status: job.status,
+            error: job.error,
+            attempts: job.attempts,
+            video_name: job.video_name,
+            created_at: job.created_at,
         })
         .collect();
 
-    // Sort by created_at to maintain consistent queue order (oldest first)
-    items.sort_by_key(|item| item.created_at);
-
     let active_count = items
         .iter()
-        .filter(|i| i.status == "processing" || i.status == "initializing")
+        .filter(|i| matches!(i.status.as_str(), "initializing" | "queued" | "processing"))
         .count() as u32;
     let completed_count = items.iter().filter(|i| i.status == "completed").count() as u32;
     let failed_count = items.iter().filter(|i| i.status == "failed").count() as u32;
 
-    Json(QueueListResponse {
+    Ok(Json(QueueListResponse {
         items,
         active_count,
         completed_count,
         failed_count,
-    })
+    }))
 }
 
 #[derive(serde::Serialize)]
@@ -1088,62 +722,93 @@ pub async fn cancel_queue(
 ) -> Result<Json<CancelQueueResponse>, (StatusCode, String)> {
     info!("Attempting to cancel queue: {}", upload_id);
 
-    // Check if the queue item exists and is in a cancellable state
-    let mut progress_map = state.progress.write().await;
-
-    if let Some(progress) = progress_map.get(&upload_id) {
-        // Only allow cancellation of items that are "initializing" or in early "processing" stages
-        // We cannot cancel items that are actively being encoded by FFmpeg
-        let cancellable_stages = [
-            "Initializing upload",
-            "Queued for processing",
-            "Receiving chunks",
-        ];
-        let is_cancellable = progress.status == "initializing"
-            || (progress.status == "processing"
-                && cancellable_stages.contains(&progress.stage.as_str()));
-
-        if !is_cancellable {
-            return Err((
-                StatusCode::CONFLICT,
-                format!(
-                    "Cannot cancel: video is already being processed (stage: {})",
-                    progress.stage
-                ),
-            ));
-        }
+    // Jobs that are running are stopped through their cancellation token. The worker kills
+    // the ffmpeg children, removes partial output and marks the job cancelled.
+    if let Some(token) = state.job_cancellations.read().await.get(&upload_id) {
+        token.cancel();
+        return Ok(Json(CancelQueueResponse {
+            cancelled: true,
+            message: "Cancellation requested, processing will stop shortly".to_string(),
+        }));
+    }
 
-        // Mark as cancelled (we'll use "failed" status with a specific message)
-        let cancelled_progress = ProgressUpdate {
-            stage: "Cancelled".to_string(),
-            current_chunk: 0,
-            total_chunks: progress.total_chunks,
-            percentage: 0,
-            details: Some("Cancelled by user".to_string()),
-            status: "failed".to_string(),
-            result: None,
-            error: Some("Cancelled by user".to_string()),
-            video_name: progress.video_name.clone(),
-            created_at: progress.created_at,
+    // Jobs that are still uploading or waiting in the queue are cancelled directly
+    let Some(job) = cancel_pending_job(&state.db_pool, &upload_id)
+        .await
+        .map_err(internal_err)?
+    else {
+        return match get_job(&state.db_pool, &upload_id)
+            .await
+            .map_err(internal_err)?
+        {
+            Some(job) if job.is_finished() => Err((
+                StatusCode::CONFLICT,
+                format!("Cannot cancel: job has already finished (stage: {})", job.stage),
+            )),
+            // Claimed by the worker but its cancellation token isn't registered yet
+            Some(_) => Err((
+                StatusCode::CONFLICT,
+                "Cannot cancel: job is starting, try again in a moment".to_string(),
+            )),
+            None => Err((StatusCode::NOT_FOUND, "Queue item not found".to_string())),
         };
-        progress_map.insert(upload_id.clone(), cancelled_progress);
-
-        // Also clean up any chunked upload data if it exists
-        drop(progress_map); // Release the lock before acquiring another
-        let mut chunked_uploads = state.chunked_uploads.write().await;
-        if let Some(chunked) = chunked_uploads.remove(&upload_id) {
-            // Clean up temp directory
-            let _ = fs::remove_dir_all(&chunked.temp_dir).await;
-            info!("Cleaned up chunked upload temp files for {}", upload_id);
-        }
+    };
 
-        Ok(Json(CancelQueueResponse {
-            cancelled: true,
-            message: "Queue item cancelled successfully".to_string(),
-        }))
-    } else {
-        Err((StatusCode::NOT_FOUND, "Queue item not found".to_string()))
+    // Queued jobs already have an assembled source file waiting for the worker
+    if let Some(source) = job.source_path.as_deref() {
+        let _ = fs::remove_file(source).await;
+    }
+
+    // Also clean up any chunked upload data if it exists
+    let mut chunked_uploads = state.chunked_uploads.write().await;
+    if let Some(chunked) = chunked_uploads.remove(&upload_id) {
+        // Clean up temp directory
+        let _ = fs::remove_dir_all(&chunked.temp_dir).await;
+        info!("Cleaned up chunked upload temp files for {}", upload_id);
+    }
+
+    Ok(Json(CancelQueueResponse {
+        cancelled: true,
+        message: "Queue item cancelled successfully".to_string(),
+    }))
+}
+
+/// Remove a single completed or failed job from the queue list
+pub async fn remove_queue(
+    State(state): State<AppState>,
+    Path(upload_id): Path<String>,
+) -> Result<Json<CancelQueueResponse>, (StatusCode, String)> {
+    let removed = delete_finished_jobs(&state.db_pool, Some(&upload_id))
+        .await
+        .map_err(internal_err)?;
+
+    if removed == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            "Queue item not found or still in progress".to_string(),
+        ));
     }
+
+    Ok(Json(CancelQueueResponse {
+        cancelled: false,
+        message: "Queue item removed".to_string(),
+    }))
+}
+
+/// Remove every failed job from the queue list
+pub async fn clear_failed_queues(
+    State(state): State<AppState>,
+) -> Result<Json<CancelQueueResponse>, (StatusCode, String)> {
+    let removed = delete_finished_jobs(&state.db_pool, None)
+        .await
+        .map_err(internal_err)?;
+
+    info!("Cleared {} failed jobs", removed);
+
+    Ok(Json(CancelQueueResponse {
+        cancelled: false,
+        message: format!("Removed {} failed items", removed),
+    }))
 }
 
 pub async fn get_progress(
@@ -1170,28 +835,34 @@ pub async fn get_progress(
         let timeout = Duration::from_secs(60); // Wait up to 60s for upload to start
 
         loop {
-            let progress = {
-                let progress_map = state.progress.read().await;
-                progress_map.get(&upload_id).cloned()
+            let job = match get_job(&state.db_pool, &upload_id).await {
+                Ok(job) => job,
+                Err(e) => {
+                    error!("Failed to read progress for {}: {}", upload_id, e);
+                    None
+                }
             };
 
-            if let Some(p) = progress {
-                // Only yield if changed or every few seconds to keep alive
+            if let Some(job) = job {
+                let result = job.player_url.clone().map(|player_url| UploadResponse {
+                    player_url,
+                    upload_id: job.id.clone(),
+                });
                 let json = serde_json::to_string(&ProgressResponse {
-                    stage: p.stage.clone(),
-                    current_chunk: p.current_chunk,
-                    total_chunks: p.total_chunks,
-                    percentage: p.percentage,
-                    details: p.details.clone(),
-                    status: p.status.clone(),
-                    result: p.result.clone(),
-                    error: p.error.clone(),
+                    stage: job.stage.clone(),
+                    current_chunk: job.current_chunk,
+                    total_chunks: job.total_chunks,
+                    percentage: job.percentage,
+                    details: job.details.clone(),
+                    status: job.status.clone(),
+                    result,
+                    error: job.error.clone(),
                 })
                 .unwrap_or_default();
 
                 yield Ok(Event::default().data(json));
 
-                if p.status == "completed" || p.status == "failed" {
+                if job.is_finished() {
                     // Wait a bit to ensure client receives the message before closing
                     tokio::time::sleep(Duration::from_secs(3)).await;
                     break;
@@ -1229,18 +900,21 @@ pub async fn cleanup_uploads(
     let mut cleaned_uploads = 0;
     let mut cleaned_progress = 0;
 
-    // Clean up chunked uploads that are older than timeout or not in progress map
+    let jobs = list_jobs(&state.db_pool, now)
+        .await
+        .map_err(internal_err)?;
+
+    // Clean up chunked uploads that are older than timeout or have no live job
     {
-        let progress_map = state.progress.read().await;
         let mut uploads = state.chunked_uploads.write().await;
 
         let mut to_remove = Vec::new();
         for (id, upload) in uploads.iter() {
-            // Remove if stale OR if there's no corresponding progress entry
+            // Remove if stale OR if there's no corresponding unfinished job
             let is_stale = now.saturating_sub(upload.last_activity) > STALE_UPLOAD_TIMEOUT_MS;
-            let no_progress = !progress_map.contains_key(id);
+            let no_job = !jobs.iter().any(|j| &j.id == id && !j.is_finished());
 
-            if is_stale || no_progress {
+            if is_stale || no_job {
                 to_remove.push((id.clone(), upload.temp_dir.clone()));
             }
         }
@@ -1253,41 +927,54 @@ pub async fn cleanup_uploads(
         }
     }
 
-    // Clean up progress entries that are stuck (not completed/failed and older than 1 hour)
+    // Fail uploads that are stuck (still receiving data after 1 hour).
+    // Queued and processing jobs are owned by the worker and left alone.
+    let hour_ago = now.saturating_sub(60 * 60 * 1000);
+    for job in jobs
+        .iter()
+        .filter(|j| j.status == "initializing" && j.updated_at < hour_ago)
     {
-        let mut progress_map = state.progress.write().await;
-        let hour_ago = now.saturating_sub(60 * 60 * 1000);
-
-        let mut to_remove = Vec::new();
-        for (id, progress) in progress_map.iter() {
-            if progress.status != "completed"
-                && progress.status != "failed"
-                && progress.created_at < hour_ago
-            {
-                to_remove.push(id.clone());
-            }
-        }
-
-        for id in to_remove {
-            progress_map.remove(&id);
-            cleaned_progress += 1;
-            info!("Cleaned up stuck progress entry: {}", id);
+        let progress = failed_progress(
+            "Upload stalled",
+            "Upload stalled and was cleaned up".to_string(),
+        );
+        if let Err(e) = update_job_progress(&state.db_pool, &job.id, &progress).await {
+            error!("Failed to mark stuck upload {} as failed: {}", job.id, e);
+            continue;
         }
+        cleaned_progress += 1;
+        info!("Cleaned up stuck upload: {}", job.id);
     }
 
+    // Drop finished jobs past the retention window
+    cleaned_progress += remove_old_jobs(&state).await as usize;
+
     // Also clean up temp directories on disk that don't have corresponding entries
+    let active_videos: HashSet<&str> = jobs
+        .iter()
+        .filter(|j| j.status == "queued" || j.status == "processing")
+        .filter_map(|j| j.video_id.as_deref())
+        .collect();
+
     if let Ok(mut entries) = tokio::fs::read_dir(std::env::temp_dir()).await {
         while let Ok(Some(entry)) = entries.next_entry().await {
-            if let Some(name) = entry.file_name().to_str() {
-                if name.starts_with("chunked-") {
-                    let upload_id = name.trim_start_matches("chunked-");
-                    let uploads = state.chunked_uploads.read().await;
-                    if !uploads.contains_key(upload_id) {
-                        let _ = fs::remove_dir_all(entry.path()).await;
-                        cleaned_uploads += 1;
-                        info!("Cleaned up orphaned temp directory: {}", name);
-                    }
+            let Some(name) = entry.file_name().to_str().map(|s| s.to_string()) else {
+                continue;
+            };
+
+            if let Some(upload_id) = name.strip_prefix("chunked-") {
+                let uploads = state.chunked_uploads.read().await;
+                if !uploads.contains_key(upload_id) {
+                    let _ = fs::remove_dir_all(entry.path()).await;
+                    cleaned_uploads += 1;
+                    info!("Cleaned up orphaned temp directory: {}", name);
                 }
+            } else if let Some(video_id) = name.strip_prefix("hls-")
+                && !active_videos.contains(video_id)
+            {
+                let _ = fs::remove_dir_all(entry.path()).await;
+                cleaned_uploads += 1;
+                info!("Cleaned up orphaned encode directory: {}", name);
             }
         }
     }
@@ -1,10 +1,11 @@
 use crate::database::{
-    count_videos, delete_videos as db_delete_videos, get_video_ids_with_prefix,
-    list_videos as db_list_videos, update_video as db_update_video,
+    accept_draft_chapters, count_videos, delete_videos as db_delete_videos, get_chapters_for_video, get_video,
+    get_video_ids_with_prefix, list_videos as db_list_videos, replace_chapters,
+    update_video as db_update_video,
 };
 use crate::handlers::common::internal_err;
-use crate::storage::bulk_delete_from_r2;
-use crate::types::{AppState, VideoListResponse, VideoQuery};
+use crate::storage::delete_prefix_from_r2;
+use crate::types::{AppState, ChapterInfo, ChapterListResponse, VideoListResponse, VideoQuery};
 
 use axum::{
     Json,
@@ -100,54 +101,14 @@ pub async fn delete_videos(
     for video_id in &existing_ids {
         let prefix = format!("{}/", video_id);
 
-        // Collect all object keys with this prefix
-        let mut keys_to_delete = Vec::new();
-        let mut continuation_token: Option<String> = None;
-
-        match async {
-            loop {
-                let list_resp = state
-                    .s3
-                    .list_objects_v2()
-                    .bucket(&state.config.r2.bucket)
-                    .prefix(&prefix)
-                    .set_continuation_token(continuation_token.clone())
-                    .send()
-                    .await?;
-
-                if let Some(contents) = list_resp.contents {
-                    for obj in contents {
-                        if let Some(key) = obj.key {
-                            keys_to_delete.push(key);
-                        }
-                    }
-                }
-
-                if list_resp.is_truncated.unwrap_or(false) {
-                    continuation_token = list_resp.next_continuation_token;
-                } else {
-                    break;
-                }
-            }
-            Ok::<(), aws_sdk_s3::Error>(())
-        }
-        .await
-        {
-            Ok(_) => {
-                // Bulk delete all collected keys
-                if !keys_to_delete.is_empty() {
-                    match bulk_delete_from_r2(&state, keys_to_delete).await {
-                        Ok(deleted) => {
-                            info!("Bulk deleted {} objects for video {}", deleted, video_id);
-                        }
-                        Err(e) => {
-                            tracing::warn!("Failed to bulk delete R2 objects for video {}: {}. Continuing with database deletion.", video_id, e);
-                        }
-                    }
+        match delete_prefix_from_r2(&state, &prefix).await {
+            Ok(deleted) => {
+                if deleted > 0 {
+                    info!("Bulk deleted {} objects for video {}", deleted, video_id);
                 }
             }
             Err(e) => {
-                tracing::warn!("Failed to list R2 objects for video {}: {}. Continuing with database deletion.", video_id, e);
+                tracing::warn!("Failed to delete R2 objects for video {}: {}. Continuing with database deletion.", video_id, e);
             }
         }
     }
@@ -203,3 +164,238 @@ pub async fn update_video_visibility(
 
     Ok(StatusCode::OK)
 }
+
+/// Longest accepted chapter title
+const MAX_CHAPTER_TITLE_CHARS: usize = 200;
+
+#[derive(serde::Deserialize)]
+pub struct ChapterRequest {
+    pub title: String,
+    pub start_time: f64,
+    pub end_time: f64,
+}
+
+#[derive(serde::Deserialize)]
+pub struct UpdateChapterRequest {
+    pub title: Option<String>,
+    pub start_time: Option<f64>,
+    pub end_time: Option<f64>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct ReplaceChaptersRequest {
+    pub chapters: Vec<ChapterRequest>,
+}
+
+/// Add a chapter. Chapters are kept in start time order, so `chapter_index` values of later
+/// chapters shift. Draft chapters the new one overlaps are dropped.
+pub async fn add_chapter(
+    State(state): State<AppState>,
+    Path(video_id): Path<String>,
+    Json(body): Json<ChapterRequest>,
+) -> Result<(StatusCode, Json<ChapterListResponse>), (StatusCode, String)> {
+    let mut chapters = load_chapters(&state, &video_id).await?;
+    let chapter = ChapterInfo {
+        start_time: body.start_time,
+        end_time: body.end_time,
+        title: body.title,
+        is_draft: false,
+    };
+    drop_overlapped_drafts(&mut chapters, &chapter);
+    chapters.push(chapter);
+
+    let response = save_chapters(&state, &video_id, chapters).await?;
+    Ok((StatusCode::CREATED, response))
+}
+
+/// Replace all chapters of a video at once (bulk edits and reordering). The new chapters are
+/// published, replacing any drafts.
+pub async fn replace_video_chapters(
+    State(state): State<AppState>,
+    Path(video_id): Path<String>,
+    Json(body): Json<ReplaceChaptersRequest>,
+) -> Result<Json<ChapterListResponse>, (StatusCode, String)> {
+    let chapters = body
+        .chapters
+        .into_iter()
+        .map(|chapter| ChapterInfo {
+            start_time: chapter.start_time,
+            end_time: chapter.end_time,
+            title: chapter.title,
+            is_draft: false,
+        })
+        .collect();
+
+    save_chapters(&state, &video_id, chapters).await
+}
+
+/// All chapters of a video including drafts proposed by chapter detection
+pub async fn list_all_chapters(
+    State(state): State<AppState>,
+    Path(video_id): Path<String>,
+) -> Result<Json<ChapterListResponse>, (StatusCode, String)> {
+    let chapters = get_chapters_for_video(&state.db_pool, &video_id, true)
+        .await
+        .map_err(internal_err)?;
+
+    Ok(Json(ChapterListResponse { chapters }))
+}
+
+/// Publish the draft chapters of a video as they currently are
+pub async fn accept_chapters(
+    State(state): State<AppState>,
+    Path(video_id): Path<String>,
+) -> Result<Json<ChapterListResponse>, (StatusCode, String)> {
+    accept_draft_chapters(&state.db_pool, &video_id)
+        .await
+        .map_err(internal_err)?;
+
+    let chapters = get_chapters_for_video(&state.db_pool, &video_id, true)
+        .await
+        .map_err(internal_err)?;
+
+    Ok(Json(ChapterListResponse { chapters }))
+}
+
+/// Edit the title and/or times of the chapter at `chapter_index`; drafts stay drafts until
+/// accepted. Editing a published chapter drops the draft chapters it then overlaps.
+pub async fn update_chapter(
+    State(state): State<AppState>,
+    Path((video_id, chapter_index)): Path<(String, usize)>,
+    Json(body): Json<UpdateChapterRequest>,
+) -> Result<Json<ChapterListResponse>, (StatusCode, String)> {
+    let mut chapters = load_chapters(&state, &video_id).await?;
+    let chapter = chapters
+        .get_mut(chapter_index)
+        .ok_or_else(|| (StatusCode::NOT_FOUND, "Chapter not found".to_string()))?;
+
+    if let Some(title) = body.title {
+        chapter.title = title;
+    }
+    if let Some(start_time) = body.start_time {
+        chapter.start_time = start_time;
+    }
+    if let Some(end_time) = body.end_time {
+        chapter.end_time = end_time;
+    }
+    if !chapter.is_draft {
+        let edited = chapter.clone();
+        drop_overlapped_drafts(&mut chapters, &edited);
+    }
+
+    save_chapters(&state, &video_id, chapters).await
+}
+
+pub async fn delete_chapter(
+    State(state): State<AppState>,
+    Path((video_id, chapter_index)): Path<(String, usize)>,
+) -> Result<Json<ChapterListResponse>, (StatusCode, String)> {
+    let mut chapters = load_chapters(&state, &video_id).await?;
+    if chapter_index >= chapters.len() {
+        return Err((StatusCode::NOT_FOUND, "Chapter not found".to_string()));
+    }
+    chapters.remove(chapter_index);
+
+    save_chapters(&state, &video_id, chapters).await
+}
+
+async fn load_chapters(
+    state: &AppState,
+    video_id: &str,
+) -> Result<Vec<ChapterInfo>, (StatusCode, String)> {
+    let chapters = get_chapters_for_video(&state.db_pool, video_id, true)
+        .await
+        .map_err(internal_err)?;
+
+    Ok(chapters
+        .into_iter()
+        .map(|chapter| ChapterInfo {
+            start_time: chapter.start_time,
+            end_time: chapter.end_time,
+            title: chapter.title,
+            is_draft: chapter.is_draft,
+        })
+        .collect())
+}
+
+/// Detected drafts cover the whole timeline, so a published chapter replaces the drafts it
+/// overlaps instead of failing the overlap check against them
+fn drop_overlapped_drafts(chapters: &mut Vec<ChapterInfo>, published: &ChapterInfo) {
+    chapters.retain(|chapter| {
+        !chapter.is_draft
+            || chapter.end_time <= published.start_time
+            || chapter.start_time >= published.end_time
+    });
+}
+
+/// Validate the chapters against the video and store them in start time order
+async fn save_chapters(
+    state: &AppState,
+    video_id: &str,
+    mut chapters: Vec<ChapterInfo>,
+) -> Result<Json<ChapterListResponse>, (StatusCode, String)> {
+    let video = get_video(&state.db_pool, video_id)
+        .await
+        .map_err(|_| (StatusCode::NOT_FOUND, "Video not found".to_string()))?;
+
+    validate_chapters(&mut chapters, video.duration as f64)?;
+
+    replace_chapters(&state.db_pool, video_id, &chapters)
+        .await
+        .map_err(internal_err)?;
+
+    let chapters = get_chapters_for_video(&state.db_pool, video_id, true)
+        .await
+        .map_err(internal_err)?;
+
+    Ok(Json(ChapterListResponse { chapters }))
+}
+
+/// Titles must be non-empty, times within the video and chapters must not overlap (touching is
+/// fine). Sorts the chapters by start time.
+fn validate_chapters(chapters: &mut [ChapterInfo], duration: f64) -> Result<(), (StatusCode, String)> {
+    // The stored duration is rounded to whole seconds
+    let max_end = duration + 0.5;
+    let invalid = |message: String| Err((StatusCode::BAD_REQUEST, message));
+
+    for chapter in chapters.iter_mut() {
+        chapter.title = chapter.title.trim().to_string();
+        if chapter.title.is_empty() {
+            return invalid("Chapter title must not be empty".to_string());
+        }
+        if chapter.title.chars().count() > MAX_CHAPTER_TITLE_CHARS {
+            return invalid(format!(
+                "Chapter title must be at most {} characters",
+                MAX_CHAPTER_TITLE_CHARS
+            ));
+        }
+        if !chapter.start_time.is_finite() || !chapter.end_time.is_finite() {
+            return invalid(format!("Chapter '{}' has an invalid time", chapter.title));
+        }
+        if chapter.start_time < 0.0 || chapter.end_time <= chapter.start_time {
+            return invalid(format!(
+                "Chapter '{}' must start at or after 0 and end after it starts",
+                chapter.title
+            ));
+        }
+        if chapter.end_time > max_end {
+            return invalid(format!(
+                "Chapter '{}' ends after the video ({}s)",
+                chapter.title, duration
+            ));
+        }
+    }
+
+    chapters.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+
+    for pair in chapters.windows(2) {
+        if pair[1].start_time < pair[0].end_time {
+            return invalid(format!(
+                "Chapters '{}' and '{}' overlap",
+                pair[0].title, pair[1].title
+            ));
+        }
+    }
+
+    Ok(())
+}
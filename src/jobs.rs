@@ -0,0 +1,811 @@
+use crate::chapters::detect_chapters;
+use crate::handlers::common::now_millis;
+use crate::database::{
+    claim_next_job, get_interrupted_jobs, get_video, prune_finished_jobs, requeue_job, save_attachment,
+    save_audio_track, save_chapter, save_subtitle, save_video, update_job_progress,
+};
+use crate::ladder::analyze_ladder;
+use crate::storage::{delete_prefix_from_r2, upload_hls_to_r2};
+use crate::storyboard::{STORYBOARD_DIR, plan_storyboard, write_storyboard_track};
+use crate::thumbnail::{POSTERS_DIR, collect_posters};
+use crate::types::{AppState, AudioRendition, Job, PosterImage, ProgressUpdate, UploadResponse};
+use crate::video::{
+    audio_renditions, detect_crop, detect_interlacing, encode_to_hls, extract_all_attachments, extract_subtitle,
+    extract_vobsub_subtitle, get_attachments, get_audio_streams, get_chapters,
+    get_subtitle_extension, get_subtitle_streams, get_variants_for_source, get_video_metadata,
+    is_bitmap_subtitle, is_pgs_subtitle, is_vobsub_subtitle,
+};
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::fs;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+// A job interrupted this many times by restarts is marked failed instead of re-queued
+const MAX_JOB_ATTEMPTS: u32 = 3;
+
+// How often the worker re-checks the queue when nobody notifies it
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Completed and failed jobs are deleted from the queue after this long
+const FINISHED_JOB_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// How often the worker prunes finished jobs
+const JOB_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Temp directory holding the HLS output of a video while it is being processed
+pub fn hls_temp_dir(video_id: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("hls-{}", video_id))
+}
+
+/// Temp directory holding the chunks of an in-progress chunked upload
+pub fn chunked_temp_dir(upload_id: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("chunked-{}", upload_id))
+}
+
+async fn set_progress(state: &AppState, job_id: &str, update: ProgressUpdate) {
+    if let Err(e) = update_job_progress(&state.db_pool, job_id, &update).await {
+        error!("Failed to record progress for job {}: {}", job_id, e);
+    }
+}
+
+/// Progress update that moves a job into the failed state
+pub fn failed_progress(message: &str, details: String) -> ProgressUpdate {
+    ProgressUpdate {
+        stage: "Failed".to_string(),
+        current_chunk: 0,
+        total_chunks: 1,
+        percentage: 0,
+        details: Some(details),
+        status: "failed".to_string(),
+        result: None,
+        error: Some(message.to_string()),
+        video_name: None,
+    }
+}
+
+/// Background worker that drains the `jobs` table. Runs for the lifetime of the server.
+pub async fn run_worker(state: AppState) {
+    recover_jobs(&state).await;
+
+    let slots = Arc::new(Semaphore::new(
+        state.config.server.max_concurrent_encodes.max(1),
+    ));
+    let mut last_prune: Option<Instant> = None;
+
+    loop {
+        if last_prune.is_none_or(|at| at.elapsed() >= JOB_PRUNE_INTERVAL) {
+            remove_old_jobs(&state).await;
+            last_prune = Some(Instant::now());
+        }
+
+        let permit = match Arc::clone(&slots).acquire_owned().await {
+            Ok(permit) => permit,
+            Err(_) => return,
+        };
+
+        match claim_next_job(&state.db_pool).await {
+            Ok(Some(job)) => {
+                info!(
+                    "Starting job {} (attempt {}/{})",
+                    job.id, job.attempts, MAX_JOB_ATTEMPTS
+                );
+                let state = state.clone();
+                tokio::spawn(async move {
+                    run_job(&state, job).await;
+                    drop(permit);
+                });
+            }
+            Ok(None) => {
+                drop(permit);
+                let _ = tokio::time::timeout(JOB_POLL_INTERVAL, state.job_notify.notified()).await;
+            }
+            Err(e) => {
+                drop(permit);
+                error!("Failed to claim next job: {}", e);
+                tokio::time::sleep(JOB_POLL_INTERVAL).await;
+            }
+        }
+    }
+}
+
+/// Delete finished jobs older than the retention window so the queue table doesn't grow forever.
+/// Returns the number of deleted jobs.
+pub async fn remove_old_jobs(state: &AppState) -> u64 {
+    let cutoff = now_millis().saturating_sub(FINISHED_JOB_RETENTION.as_millis() as u64);
+    match prune_finished_jobs(&state.db_pool, cutoff).await {
+        Ok(removed) => {
+            if removed > 0 {
+                info!("Removed {} finished jobs past the retention window", removed);
+            }
+            removed
+        }
+        Err(e) => {
+            error!("Failed to prune finished jobs: {}", e);
+            0
+        }
+    }
+}
+
+/// Resolve jobs left behind by a previous run: resume encodes, fail lost uploads
+async fn recover_jobs(state: &AppState) {
+    let jobs = match get_interrupted_jobs(&state.db_pool).await {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Failed to load interrupted jobs: {}", e);
+            return;
+        }
+    };
+
+    for job in jobs {
+        if job.status == "initializing" {
+            // Chunk bookkeeping lives in memory, so a partially received upload cannot resume
+            let _ = fs::remove_dir_all(chunked_temp_dir(&job.id)).await;
+            warn!("Upload {} was interrupted by a restart before it finished", job.id);
+            set_progress(
+                state,
+                &job.id,
+                failed_progress(
+                    "Server restarted before the upload finished",
+                    "Upload interrupted, please upload the file again".to_string(),
+                ),
+            )
+            .await;
+            continue;
+        }
+
+        if let Some(video_id) = job.video_id.as_deref() {
+            let _ = fs::remove_dir_all(hls_temp_dir(video_id)).await;
+
+            // The video row commits with its tracks, so if it exists the job actually finished
+            if get_video(&state.db_pool, video_id).await.is_ok() {
+                if let Some(source) = job.source_path.as_deref() {
+                    let _ = fs::remove_file(source).await;
+                }
+                info!("Job {} had already finished before the restart", job.id);
+                set_progress(state, &job.id, completed_progress(&job.id, video_id)).await;
+                continue;
+            }
+        }
+
+        let source_exists = match job.source_path.as_deref() {
+            Some(path) => fs::try_exists(path).await.unwrap_or(false),
+            None => false,
+        };
+
+        if !source_exists {
+            warn!("Source file for job {} is gone, marking it failed", job.id);
+            set_progress(
+                state,
+                &job.id,
+                failed_progress(
+                    "Source file missing after server restart",
+                    "Processing failed: source file missing after server restart".to_string(),
+                ),
+            )
+            .await;
+        } else if job.attempts >= MAX_JOB_ATTEMPTS {
+            warn!(
+                "Job {} was interrupted {} times, giving up",
+                job.id, job.attempts
+            );
+            if let Some(source) = job.source_path.as_deref() {
+                let _ = fs::remove_file(source).await;
+            }
+            set_progress(
+                state,
+                &job.id,
+                failed_progress(
+                    "Processing was interrupted too many times",
+                    format!(
+                        "Processing failed: interrupted {} times by server restarts",
+                        job.attempts
+                    ),
+                ),
+            )
+            .await;
+        } else {
+            info!("Re-queueing interrupted job {}", job.id);
+            if let Err(e) = requeue_job(&state.db_pool, &job.id).await {
+                error!("Failed to re-queue job {}: {}", job.id, e);
+            }
+        }
+    }
+}
+
+fn completed_progress(job_id: &str, video_id: &str) -> ProgressUpdate {
+    ProgressUpdate {
+        stage: "Completed".to_string(),
+        current_chunk: 1,
+        total_chunks: 1,
+        percentage: 100,
+        details: Some("Upload and processing complete".to_string()),
+        status: "completed".to_string(),
+        result: Some(UploadResponse {
+            player_url: format!("/player/{}", video_id),
+            upload_id: job_id.to_string(),
+        }),
+        error: None,
+        video_name: None,
+    }
+}
+
+/// Run a claimed job to completion and record its final state
+async fn run_job(state: &AppState, job: Job) {
+    let cancel = CancellationToken::new();
+    state
+        .job_cancellations
+        .write()
+        .await
+        .insert(job.id.clone(), cancel.clone());
+
+    let result = process_job(state, &job, &cancel).await;
+
+    state.job_cancellations.write().await.remove(&job.id);
+
+    match result {
+        Ok(response) => {
+            let completion_progress = ProgressUpdate {
+                stage: "Completed".to_string(),
+                current_chunk: 1,
+                total_chunks: 1,
+                percentage: 100,
+                details: Some("Upload and processing complete".to_string()),
+                status: "completed".to_string(),
+                result: Some(response),
+                error: None,
+                video_name: None,
+            };
+            set_progress(state, &job.id, completion_progress).await;
+        }
+        Err(e) => {
+            let cancelled = cancel.is_cancelled();
+            if cancelled {
+                info!("Job {} cancelled, cleaning up", job.id);
+            } else {
+                error!("Background processing failed for job {}: {:?}", job.id, e);
+            }
+
+            // Nothing retries a failed job, so drop its files now instead of leaving orphans
+            if let Some(source) = job.source_path.as_deref() {
+                let _ = fs::remove_file(source).await;
+            }
+            if let Some(video_id) = job.video_id.as_deref() {
+                let _ = fs::remove_dir_all(hls_temp_dir(video_id)).await;
+
+                // A cancelled upload may have left some objects behind in the bucket
+                if cancelled {
+                    match delete_prefix_from_r2(state, &format!("{}/", video_id)).await {
+                        Ok(deleted) if deleted > 0 => {
+                            info!("Removed {} partially uploaded objects for job {}", deleted, job.id)
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("Failed to remove partial upload for job {}: {}", job.id, e),
+                    }
+                }
+            }
+
+            let progress = if cancelled {
+                ProgressUpdate {
+                    stage: "Cancelled".to_string(),
+                    current_chunk: 0,
+                    total_chunks: 1,
+                    percentage: 0,
+                    details: Some("Cancelled by user".to_string()),
+                    status: "failed".to_string(),
+                    result: None,
+                    error: Some("Cancelled by user".to_string()),
+                    video_name: None,
+                }
+            } else {
+                failed_progress(&e.to_string(), format!("Processing failed: {}", e))
+            };
+            set_progress(state, &job.id, progress).await;
+        }
+    }
+}
+
+/// Bail out between pipeline stages once the job has been cancelled
+fn ensure_not_cancelled(cancel: &CancellationToken) -> Result<()> {
+    if cancel.is_cancelled() {
+        anyhow::bail!("Job cancelled");
+    }
+    Ok(())
+}
+
+/// Encode, upload and register a single video
+async fn process_job(
+    state: &AppState,
+    job: &Job,
+    cancel: &CancellationToken,
+) -> Result<UploadResponse> {
+    let source_path = job
+        .source_path
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("job {} has no source file", job.id))?;
+    let video_path = Path::new(source_path).to_path_buf();
+    let output_id = job
+        .video_id
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("job {} has no video id", job.id))?;
+    let video_name = job.video_name.clone().unwrap_or_default();
+
+    let hls_dir = hls_temp_dir(&output_id);
+    // Start from a clean directory in case a previous attempt left partial output
+    let _ = fs::remove_dir_all(&hls_dir).await;
+    fs::create_dir_all(&hls_dir)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    let mut metadata = get_video_metadata(&video_path).await?;
+    let video_duration = metadata.duration;
+    info!(
+        "Source {}: {}x{} (SAR {:.3}, DAR {:.3}), rotation {}, {:.3} fps, {}s, color {}/{}/{} ({})",
+        job.id,
+        metadata.width,
+        metadata.height,
+        metadata.sample_aspect_ratio,
+        metadata.display_aspect_ratio,
+        metadata.rotation,
+        metadata.frame_rate,
+        metadata.duration,
+        metadata.color_primaries.as_deref().unwrap_or("unknown"),
+        metadata.color_transfer.as_deref().unwrap_or("unknown"),
+        metadata.color_space.as_deref().unwrap_or("unknown"),
+        metadata
+            .hdr_format()
+            .map(|hdr| hdr.video_range())
+            .unwrap_or("SDR")
+    );
+
+    let interlacing = {
+        let _permit = state.ffmpeg_semaphore.acquire().await?;
+        tokio::select! {
+            interlacing = detect_interlacing(&video_path, &metadata) => interlacing,
+            _ = cancel.cancelled() => anyhow::bail!("Job cancelled"),
+        }
+    };
+    info!("Source {}: {}", job.id, interlacing.summary);
+    metadata.interlaced = interlacing.field_order;
+
+    let auto_crop = job
+        .options
+        .auto_crop
+        .unwrap_or(state.config.video.auto_crop.enabled);
+    let crop = match job.options.crop {
+        Some(crop) => {
+            let (frame_width, frame_height) = metadata.frame_size();
+            if crop.fits(frame_width, frame_height) {
+                Some(crop)
+            } else {
+                warn!(
+                    "Crop {} of job {} is outside the {}x{} frame, not cropping",
+                    crop.filter(),
+                    job.id,
+                    frame_width,
+                    frame_height
+                );
+                None
+            }
+        }
+        None if auto_crop => {
+            let detection = {
+                let _permit = state.ffmpeg_semaphore.acquire().await?;
+                let config = &state.config.video.auto_crop;
+                tokio::select! {
+                    detection = detect_crop(&video_path, &metadata, config) => detection,
+                    _ = cancel.cancelled() => anyhow::bail!("Job cancelled"),
+                }
+            };
+            detection.unwrap_or_else(|e| {
+                warn!("Crop detection failed for job {}, not cropping: {}", job.id, e);
+                None
+            })
+        }
+        None => None,
+    };
+    if let Some(crop) = crop {
+        metadata.apply_crop(crop);
+        let (width, height) = metadata.display_size();
+        info!(
+            "Source {}: cropping to {} ({}x{} displayed)",
+            job.id,
+            crop.filter(),
+            width,
+            height
+        );
+    }
+
+    let mut variants = get_variants_for_source(&metadata, &state.config.video);
+    let mut complexity = None;
+
+    // A configured ladder sets its rates explicitly, the analysis would overwrite them
+    let per_title = state.config.video.per_title_ladder && state.config.video.ladder.is_empty();
+    if per_title && !variants.is_empty() {
+        let analysis_progress = ProgressUpdate {
+            stage: "Analyzing".to_string(),
+            current_chunk: 0,
+            total_chunks: 1,
+            percentage: 0,
+            details: Some("Measuring source complexity...".to_string()),
+            status: "processing".to_string(),
+            result: None,
+            error: None,
+            video_name: None,
+        };
+        set_progress(state, &job.id, analysis_progress).await;
+
+        let analysis = {
+            // Probe encodes count against the encode limit like any other ffmpeg run
+            let _permit = state.ffmpeg_semaphore.acquire().await?;
+            analyze_ladder(&video_path, &variants, &metadata, cancel).await
+        };
+
+        match analysis {
+            Ok(analysis) => {
+                complexity = Some(analysis.complexity);
+                variants = analysis.variants;
+            }
+            Err(e) => {
+                ensure_not_cancelled(cancel)?;
+                warn!(
+                    "Complexity analysis failed for job {}, using the default ladder: {}",
+                    job.id, e
+                );
+            }
+        }
+    }
+
+    let available_resolutions: Vec<String> = variants.iter().map(|v| v.label.clone()).collect();
+
+    let encoding_progress = ProgressUpdate {
+        stage: "FFmpeg processing".to_string(),
+        current_chunk: 0,
+        total_chunks: variants.len() as u32,
+        percentage: 0,
+        details: Some("Starting encoding...".to_string()),
+        status: "processing".to_string(),
+        result: None,
+        error: None,
+        video_name: None,
+    };
+    set_progress(state, &job.id, encoding_progress).await;
+
+    // Get audio streams for multi-audio encoding
+    let audio_streams = get_audio_streams(&video_path).await.unwrap_or_default();
+    // Text subtitles also get WebVTT renditions in the HLS output
+    let subtitle_streams = get_subtitle_streams(&video_path).await.unwrap_or_default();
+
+    // Loudness normalization can be switched per upload, otherwise the config decides
+    let normalize_audio = job
+        .options
+        .normalize_audio
+        .unwrap_or(state.config.video.audio.loudnorm.enabled);
+
+    // Thumbnail interval and sheet layout follow the duration
+    let storyboard = plan_storyboard(&metadata);
+
+    let loudness = encode_to_hls(
+        &video_path,
+        &hls_dir,
+        &state.db_pool,
+        &job.id,
+        state.ffmpeg_semaphore.clone(),
+        &state.config.video,
+        &variants,
+        &metadata,
+        &audio_streams,
+        &subtitle_streams,
+        &storyboard,
+        normalize_audio,
+        cancel,
+    )
+    .await?;
+    ensure_not_cancelled(cancel)?;
+
+    // Thumbnails track for the scrub preview, pointing into the sheets that were generated
+    let storyboard = match write_storyboard_track(
+        &hls_dir.join(STORYBOARD_DIR),
+        &storyboard,
+        metadata.duration,
+    )
+    .await
+    {
+        Ok(storyboard) => Some(storyboard),
+        Err(e) => {
+            warn!("Storyboard unavailable for job {}: {}", job.id, e);
+            None
+        }
+    };
+
+    // Extract subtitles and attachments from the source video
+    let attachment_streams = get_attachments(&video_path).await.unwrap_or_default();
+
+    // Create directories for subtitles and fonts
+    let subtitles_dir = hls_dir.join("subtitles");
+    let fonts_dir = hls_dir.join("fonts");
+
+    if !subtitle_streams.is_empty() {
+        fs::create_dir_all(&subtitles_dir).await?;
+    }
+    if !attachment_streams.is_empty() {
+        fs::create_dir_all(&fonts_dir).await?;
+        // Extract all font attachments
+        extract_all_attachments(&video_path, &fonts_dir).await?;
+    }
+
+    // Extract each subtitle stream. Text formats are converted, bitmap formats are copied:
+    // PGS as .sup, VobSub as a .sub + .idx pair. Only extracted tracks are saved later on.
+    let mut extracted_subtitles = Vec::new();
+    for (idx, sub) in subtitle_streams.iter().enumerate() {
+        let codec = sub.codec_name.as_str();
+        if is_bitmap_subtitle(codec) && !is_pgs_subtitle(codec) && !is_vobsub_subtitle(codec) {
+            warn!(
+                "Skipping subtitle stream {} (track {}): {} is not supported by the player",
+                sub.stream_index, idx, codec
+            );
+            continue;
+        }
+
+        let sub_filename = format!("track_{}.{}", idx, get_subtitle_extension(codec));
+        let sub_path = subtitles_dir.join(&sub_filename);
+
+        // Use enumerate index (idx) as relative subtitle stream index
+        let result = if is_vobsub_subtitle(codec) {
+            // mkvextract keeps the original .idx (palette, timing); ffmpeg's VobSub muxer
+            // covers non-MKV sources
+            match extract_vobsub_subtitle(&video_path, idx as i32, &subtitles_dir, idx).await {
+                Ok(_) => Ok(()),
+                Err(e) => {
+                    warn!(
+                        "mkvextract failed for VobSub track {}, falling back to ffmpeg: {}",
+                        idx, e
+                    );
+                    extract_subtitle(&video_path, idx as i32, &sub_path, codec).await
+                }
+            }
+        } else {
+            extract_subtitle(&video_path, idx as i32, &sub_path, codec).await
+        };
+
+        match result {
+            Ok(()) => {
+                let idx_filename = is_vobsub_subtitle(codec).then(|| format!("track_{}.idx", idx));
+                extracted_subtitles.push((idx, sub, sub_filename, idx_filename));
+            }
+            Err(e) => error!(
+                "Failed to extract subtitle stream {} (track {}): {}",
+                sub.stream_index, idx, e
+            ),
+        }
+    }
+
+    ensure_not_cancelled(cancel)?;
+
+    // Embedded chapters win; otherwise chapters may be proposed from scene and silence detection
+    let mut chapters = get_chapters(&video_path).await.unwrap_or_default();
+    let auto_chapters = job
+        .options
+        .auto_chapters
+        .unwrap_or(state.config.video.auto_chapters.enabled);
+
+    if chapters.is_empty() && auto_chapters {
+        let chapters_progress = ProgressUpdate {
+            stage: "Analyzing".to_string(),
+            current_chunk: 0,
+            total_chunks: 1,
+            percentage: 0,
+            details: Some("Detecting chapters...".to_string()),
+            status: "processing".to_string(),
+            result: None,
+            error: None,
+            video_name: None,
+        };
+        set_progress(state, &job.id, chapters_progress).await;
+
+        let detection = {
+            let _permit = state.ffmpeg_semaphore.acquire().await?;
+            detect_chapters(
+                &video_path,
+                metadata.duration,
+                !audio_streams.is_empty(),
+                &state.config.video.auto_chapters,
+                cancel,
+            )
+            .await
+        };
+
+        match detection {
+            Ok(drafts) => chapters = drafts,
+            Err(e) => {
+                ensure_not_cancelled(cancel)?;
+                warn!("Chapter detection failed for job {}: {}", job.id, e);
+            }
+        }
+    }
+
+    let upload_progress = ProgressUpdate {
+        stage: "Upload to R2".to_string(),
+        current_chunk: 0,
+        total_chunks: 1,
+        percentage: 0,
+        details: Some("Uploading segments to storage...".to_string()),
+        status: "processing".to_string(),
+        result: None,
+        error: None,
+        video_name: None,
+    };
+    set_progress(state, &job.id, upload_progress).await;
+
+    info!("Starting R2 upload for video: {}", output_id);
+    let prefix = format!("{}/", output_id);
+    let playlist_key = upload_hls_to_r2(state, &hls_dir, &prefix, Some(&job.id), cancel).await?;
+    info!("Completed R2 upload. Master playlist key: {}", playlist_key);
+
+    let thumbnail_key = format!("{}/thumbnail.jpg", output_id);
+    // sprites_key keeps pointing at a single sheet for clients that predate the storyboard.
+    // Without one it stays empty; the thumbnail is not a sprite sheet.
+    let sprites_key = storyboard
+        .as_ref()
+        .map(|storyboard| format!("{}/{}/{}", output_id, STORYBOARD_DIR, storyboard.sheets[0]));
+    let entrypoint = playlist_key.clone();
+    // Only written when the renditions were packaged as CMAF
+    let dash_manifest_key = hls_dir
+        .join("manifest.mpd")
+        .exists()
+        .then(|| format!("{}/manifest.mpd", output_id));
+    // Hover preview; missing when the encoder couldn't produce it
+    let preview_key = |file: &str| {
+        hls_dir
+            .join(file)
+            .exists()
+            .then(|| format!("{}/{}", output_id, file))
+    };
+    let preview_webp_key = preview_key("preview.webp");
+    let preview_mp4_key = preview_key("preview.mp4");
+    let posters: Vec<PosterImage> = collect_posters(&hls_dir.join(POSTERS_DIR))
+        .await
+        .into_iter()
+        .map(|poster| PosterImage {
+            key: format!("{}/{}/{}", output_id, POSTERS_DIR, poster.key),
+            ..poster
+        })
+        .collect();
+
+    // Last chance to cancel: once the video row exists the job counts as finished
+    ensure_not_cancelled(cancel)?;
+
+    // The video and its track rows commit together so a crash never leaves a bare video row
+    let mut tx = state.db_pool.begin().await?;
+
+    save_video(
+        &mut *tx,
+        &output_id,
+        &video_name,
+        &job.tags,
+        &available_resolutions,
+        video_duration,
+        &thumbnail_key,
+        sprites_key.as_deref(),
+        &entrypoint,
+        dash_manifest_key.as_deref(),
+        complexity,
+        &variants,
+        storyboard.as_ref(),
+        preview_webp_key.as_deref(),
+        preview_mp4_key.as_deref(),
+        &posters,
+        metadata.crop.as_ref(),
+        metadata.interlaced,
+    )
+    .await?;
+
+    // Save audio track metadata (source stream plus the encoded renditions) to database
+    for (idx, audio) in audio_streams.iter().enumerate() {
+        let renditions: Vec<_> = audio_renditions(audio, idx, &state.config.video.audio)
+            .into_iter()
+            .map(|rendition| AudioRendition {
+                playlist_key: Some(format!("{}/{}/index.m3u8", output_id, rendition.name)),
+                ..rendition
+            })
+            .collect();
+        let playlist_key = renditions.first().and_then(|r| r.playlist_key.as_deref());
+
+        if let Err(e) = save_audio_track(
+            &mut *tx,
+            &output_id,
+            idx as i32,
+            audio.language.as_deref(),
+            audio.title.as_deref(),
+            &audio.codec_name,
+            audio.channels,
+            audio.sample_rate,
+            audio.bit_rate,
+            audio.is_default || idx == 0,
+            playlist_key,
+            &renditions,
+            loudness.get(idx).and_then(|l| l.as_ref()),
+        )
+        .await
+        {
+            error!("Failed to save audio track metadata for track {}: {}", idx, e);
+        }
+    }
+
+    // Save subtitle metadata to database
+    for (idx, sub, sub_filename, idx_filename) in &extracted_subtitles {
+        let storage_key = format!("{}/subtitles/{}", output_id, sub_filename);
+        let idx_storage_key = idx_filename
+            .as_ref()
+            .map(|filename| format!("{}/subtitles/{}", output_id, filename));
+
+        if let Err(e) = save_subtitle(
+            &mut *tx,
+            &output_id,
+            *idx as i32,
+            sub.language.as_deref(),
+            sub.title.as_deref(),
+            &sub.codec_name,
+            &storage_key,
+            idx_storage_key.as_deref(),
+            sub.is_default,
+            sub.is_forced,
+        )
+        .await
+        {
+            error!("Failed to save subtitle metadata for track {}: {}", idx, e);
+        }
+    }
+
+    // Save attachment metadata to database
+    for att in &attachment_streams {
+        let storage_key = format!("{}/fonts/{}", output_id, att.filename);
+
+        if let Err(e) = save_attachment(
+            &mut *tx,
+            &output_id,
+            &att.filename,
+            &att.mimetype,
+            &storage_key,
+        )
+        .await
+        {
+            error!(
+                "Failed to save attachment metadata for {}: {}",
+                att.filename, e
+            );
+        }
+    }
+
+    // Save embedded chapters, or the detected ones as drafts for review
+    for (idx, chapter) in chapters.iter().enumerate() {
+        if let Err(e) = save_chapter(
+            &mut *tx,
+            &output_id,
+            idx as i32,
+            chapter.start_time,
+            chapter.end_time,
+            &chapter.title,
+            chapter.is_draft,
+        )
+        .await
+        {
+            error!("Failed to save chapter metadata for index {}: {}", idx, e);
+        }
+    }
+
+    tx.commit().await?;
+
+    let _ = fs::remove_file(&video_path).await;
+    let _ = fs::remove_dir_all(&hls_dir).await;
+
+    let player_url = format!("/player/{}", output_id);
+    Ok(UploadResponse {
+        player_url,
+        upload_id: job.id.clone(),
+    })
+}
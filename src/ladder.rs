@@ -0,0 +1,267 @@
+use crate::types::{VideoMetadata, VideoVariant};
+use crate::video::{even_dimension, source_filter};
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::process::Command;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+/// Length of each sampled probe encode in seconds
+const SAMPLE_SECONDS: f64 = 4.0;
+/// Positions (fraction of the duration) of the sampled segments
+const SAMPLE_POSITIONS: [f64; 3] = [0.15, 0.5, 0.85];
+/// Probe encodes run at this short edge (or the source's if smaller)
+const PROBE_SHORT_EDGE: u32 = 540;
+/// CRF of the probe encodes, roughly the quality the default ladder targets
+const PROBE_CRF: u32 = 23;
+
+/// Complexity is clamped so a broken probe can't produce absurd bitrates
+const MIN_COMPLEXITY: f64 = 0.2;
+const MAX_COMPLEXITY: f64 = 1.5;
+/// Floor for a scaled rung bitrate in kbps
+const MIN_RUNG_KBPS: u32 = 200;
+/// A rung is only worth encoding if it saves this much over the next rung down...
+const MIN_RUNG_RATIO: f64 = 1.5;
+/// ...and at least this many kbps
+const MIN_RUNG_STEP_KBPS: u32 = 400;
+
+/// Result of the per-title analysis: the measured complexity and the ladder derived from it
+#[derive(Clone, Debug)]
+pub struct LadderAnalysis {
+    /// Probe bitrate relative to the default ladder's assumption (1.0 = typical live action)
+    pub complexity: f64,
+    pub variants: Vec<VideoVariant>,
+}
+
+/// Measure the source's complexity with fast CRF probe encodes of a few sampled segments and
+/// build a per-title ladder from the default rungs for `base_variants`.
+pub async fn analyze_ladder(
+    input: &Path,
+    base_variants: &[VideoVariant],
+    metadata: &VideoMetadata,
+    cancel: &CancellationToken,
+) -> Result<LadderAnalysis> {
+    // Probe at the source's display aspect ratio so the reference bitrate covers the same pixels
+    let (display_width, display_height) = metadata.display_size();
+    let scale = metadata.short_edge().min(PROBE_SHORT_EDGE) as f64 / metadata.short_edge().max(1) as f64;
+    let probe_width = even_dimension(display_width as f64 * scale);
+    let probe_height = even_dimension(display_height as f64 * scale);
+    // Combing and black bars would skew the probe bitrate
+    let mut filter = format!("scale={}:{},setsar=1", probe_width, probe_height);
+    if let Some(source_filter) = source_filter(metadata) {
+        filter = format!("{},{}", source_filter, filter);
+    }
+    let mut sample_kbps = Vec::new();
+
+    for (start, length) in sample_windows(metadata.duration) {
+        let kbps = tokio::select! {
+            kbps = probe_sample(input, start, length, &filter) => kbps?,
+            _ = cancel.cancelled() => anyhow::bail!("Analysis cancelled"),
+        };
+        sample_kbps.push(kbps);
+    }
+
+    let mean_kbps = sample_kbps.iter().sum::<f64>() / sample_kbps.len() as f64;
+    if mean_kbps <= 0.0 {
+        anyhow::bail!("complexity probe produced no output");
+    }
+
+    let reference_kbps =
+        VideoVariant::calculate_bitrate(probe_width, probe_height, metadata.frame_rate) as f64;
+    let complexity = (mean_kbps / reference_kbps).clamp(MIN_COMPLEXITY, MAX_COMPLEXITY);
+
+    // Peak-to-average across samples decides the VBR headroom of every rung
+    let peak_ratio = if sample_kbps.len() > 1 {
+        let peak = sample_kbps.iter().cloned().fold(0.0, f64::max);
+        (peak / mean_kbps).clamp(1.2, 2.0)
+    } else {
+        1.5
+    };
+
+    let variants = build_ladder(base_variants, complexity, peak_ratio);
+
+    info!(
+        "Per-title analysis: probe {:.0}kbps at {}x{} (samples: {:?}), complexity {:.2}, ladder: {}",
+        mean_kbps,
+        probe_width,
+        probe_height,
+        sample_kbps.iter().map(|k| k.round() as u32).collect::<Vec<_>>(),
+        complexity,
+        variants
+            .iter()
+            .map(|v| format!("{}@{}k/{}k", v.label, v.bitrate, v.maxrate))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    Ok(LadderAnalysis {
+        complexity,
+        variants,
+    })
+}
+
+/// (start, length) of each sampled window; short videos are probed in one pass
+fn sample_windows(duration: u32) -> Vec<(f64, f64)> {
+    let duration = duration as f64;
+    if duration <= SAMPLE_SECONDS * SAMPLE_POSITIONS.len() as f64 {
+        return vec![(0.0, duration.max(1.0))];
+    }
+
+    SAMPLE_POSITIONS
+        .iter()
+        .map(|position| {
+            let start = (duration * position - SAMPLE_SECONDS / 2.0)
+                .clamp(0.0, duration - SAMPLE_SECONDS);
+            (start, SAMPLE_SECONDS)
+        })
+        .collect()
+}
+
+/// Encode one window at constant quality through `filter` and return the resulting bitrate in kbps
+async fn probe_sample(input: &Path, start: f64, length: f64, filter: &str) -> Result<f64> {
+    let output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-ss")
+        .arg(format!("{:.3}", start))
+        .arg("-t")
+        .arg(format!("{:.3}", length))
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg("0:v:0")
+        .arg("-an")
+        .arg("-sn")
+        .arg("-vf")
+        .arg(filter)
+        .arg("-c:v")
+        .arg("libx264")
+        .arg("-preset")
+        .arg("veryfast")
+        .arg("-crf")
+        .arg(PROBE_CRF.to_string())
+        .arg("-pix_fmt")
+        .arg("yuv420p")
+        .arg("-f")
+        .arg("matroska")
+        .arg("pipe:1")
+        .output()
+        .await
+        .context("failed to run ffmpeg complexity probe")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg complexity probe failed: {}", stderr);
+    }
+
+    Ok(output.stdout.len() as f64 * 8.0 / 1000.0 / length)
+}
+
+/// Scale the default rungs by the measured complexity and drop rungs that are too close to the
+/// one below them to be worth the storage. The lowest and highest rungs are always kept.
+fn build_ladder(base_variants: &[VideoVariant], complexity: f64, peak_ratio: f64) -> Vec<VideoVariant> {
+    let scaled: Vec<VideoVariant> = base_variants
+        .iter()
+        .map(|base| {
+            let bitrate = ((base.bitrate as f64 * complexity).round() as u32).clamp(MIN_RUNG_KBPS, 20000);
+            VideoVariant {
+                bitrate,
+                maxrate: (bitrate as f64 * peak_ratio).round() as u32,
+                ..base.clone()
+            }
+        })
+        .collect();
+
+    let mut ladder: Vec<VideoVariant> = Vec::with_capacity(scaled.len());
+    let last_index = scaled.len().saturating_sub(1);
+
+    for (index, rung) in scaled.into_iter().enumerate() {
+        let Some(below) = ladder.last() else {
+            ladder.push(rung);
+            continue;
+        };
+
+        let worth_it = rung.bitrate as f64 >= below.bitrate as f64 * MIN_RUNG_RATIO
+            && rung.bitrate >= below.bitrate + MIN_RUNG_STEP_KBPS;
+
+        if worth_it {
+            ladder.push(rung);
+        } else if index == last_index {
+            // The top rung sets the best available quality, so it replaces the rung below instead
+            if ladder.len() > 1 {
+                ladder.pop();
+            }
+            ladder.push(rung);
+        }
+    }
+
+    ladder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rungs(bitrates: &[(&str, u32)]) -> Vec<VideoVariant> {
+        bitrates
+            .iter()
+            .map(|(label, bitrate)| VideoVariant {
+                bitrate: *bitrate,
+                ..VideoVariant::new(label, 1280, 720, 30.0)
+            })
+            .collect()
+    }
+
+    fn bitrates(ladder: &[VideoVariant]) -> Vec<(&str, u32)> {
+        ladder
+            .iter()
+            .map(|v| (v.label.as_str(), v.bitrate))
+            .collect()
+    }
+
+    #[test]
+    fn test_build_ladder_keeps_distinct_rungs() {
+        let base = rungs(&[("480p", 1000), ("720p", 2000), ("1080p", 4000)]);
+        let ladder = build_ladder(&base, 1.0, 1.5);
+        assert_eq!(
+            bitrates(&ladder),
+            vec![("480p", 1000), ("720p", 2000), ("1080p", 4000)]
+        );
+        assert_eq!(ladder[2].maxrate, 6000);
+    }
+
+    #[test]
+    fn test_build_ladder_clamps_bitrates() {
+        let base = rungs(&[("480p", 1000), ("720p", 2000), ("2160p", 16000)]);
+        // Simple content bottoms out at the floor, the top rung survives
+        let ladder = build_ladder(&base, 0.1, 1.5);
+        assert_eq!(
+            bitrates(&ladder),
+            vec![("480p", MIN_RUNG_KBPS), ("2160p", 1600)]
+        );
+        // ...and complex content is capped
+        let ladder = build_ladder(&base, 1.5, 1.5);
+        assert_eq!(
+            bitrates(&ladder),
+            vec![("480p", 1500), ("720p", 3000), ("2160p", 20000)]
+        );
+    }
+
+    #[test]
+    fn test_build_ladder_drops_close_rungs() {
+        let base = rungs(&[("480p", 1000), ("720p", 1300), ("1080p", 3000)]);
+        let ladder = build_ladder(&base, 1.0, 1.5);
+        assert_eq!(bitrates(&ladder), vec![("480p", 1000), ("1080p", 3000)]);
+    }
+
+    #[test]
+    fn test_build_ladder_top_rung_replaces_close_rung_below() {
+        let base = rungs(&[("480p", 1000), ("720p", 2000), ("1080p", 2300)]);
+        let ladder = build_ladder(&base, 1.0, 1.5);
+        assert_eq!(bitrates(&ladder), vec![("480p", 1000), ("1080p", 2300)]);
+    }
+}
@@ -1,10 +1,18 @@
+mod chapters;
 mod config;
+mod dash;
 mod database;
 mod handlers;
+mod jobs;
+mod ladder;
 mod rate_limit;
 mod storage;
+mod storyboard;
+mod subtitles;
+mod thumbnail;
 mod types;
 mod video;
+mod webvtt;
 
 use anyhow::{Context, Result};
 use aws_sdk_s3::{Client as S3Client, config::Region};
@@ -20,7 +28,7 @@ use axum::{
 };
 use config::Config;
 use std::{collections::HashMap, net::SocketAddr, sync::Arc};
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 use tower_http::cors::{CorsLayer};
 use tower_http::services::{ServeDir, ServeFile};
 use tracing::info;
@@ -102,8 +110,6 @@ async fn main() -> Result<()> {
     let database_url = "sqlite://videos.db";
     let db_pool = database::initialize_database(database_url).await?;
 
-    let progress = Arc::new(RwLock::new(HashMap::new()));
-
     let ffmpeg_semaphore = Arc::new(tokio::sync::Semaphore::new(
         config.server.max_concurrent_encodes,
     ));
@@ -126,12 +132,16 @@ async fn main() -> Result<()> {
         config,
         s3,
         db_pool,
-        progress: progress.clone(),
+        job_notify: Arc::new(Notify::new()),
+        job_cancellations: Arc::new(RwLock::new(HashMap::new())),
         ffmpeg_semaphore,
         chunked_uploads: Arc::new(RwLock::new(HashMap::new())),
         auth_rate_limiter,
     };
 
+    // Drain the persistent job queue, resuming anything interrupted by the last shutdown
+    tokio::spawn(jobs::run_worker(state.clone()));
+
     let public_routes = Router::new()
         .route("/videos/{id}/subtitles", get(handlers::get_video_subtitles))
         .route(
@@ -161,8 +171,37 @@ async fn main() -> Result<()> {
         .route("/videos", delete(handlers::delete_videos))
         .route("/videos/{id}", put(handlers::update_video))
         .route("/videos/{id}/visibility", put(handlers::update_video_visibility))
+        .route(
+            "/videos/{id}/chapters",
+            post(handlers::add_chapter).put(handlers::replace_video_chapters),
+        )
+        .route("/videos/{id}/chapters/all", get(handlers::list_all_chapters))
+        .route("/videos/{id}/chapters/accept", post(handlers::accept_chapters))
+        .route(
+            "/videos/{id}/chapters/{index}",
+            put(handlers::update_chapter).delete(handlers::delete_chapter),
+        )
+        .route("/videos/{id}/subtitles", post(handlers::upload_subtitle))
+        .route(
+            "/videos/{id}/subtitles/{track_with_ext}",
+            put(handlers::replace_subtitle)
+                .patch(handlers::update_subtitle)
+                .delete(handlers::delete_subtitle),
+        )
+        .route(
+            "/videos/{id}/subtitles/{track_with_ext}/retime",
+            post(handlers::retime_subtitle_track),
+        )
+        .route(
+            "/videos/{id}/subtitles/{track_with_ext}/revert",
+            post(handlers::revert_subtitle),
+        )
+        .route("/videos/{id}/thumbnail", post(handlers::upload_thumbnail))
+        .route("/videos/{id}/thumbnail/frame", post(handlers::pick_thumbnail_frame))
         .route("/queues", get(handlers::list_queues))
         .route("/queues/{id}", delete(handlers::cancel_queue))
+        .route("/queues/{id}/remove", delete(handlers::remove_queue))
+        .route("/queues/failed", delete(handlers::clear_failed_queues))
         .route("/queues/cleanup", post(handlers::cleanup_uploads))
         .route("/auth/check", get(check_auth))
         .route("/config", get(handlers::get_config_info))
@@ -177,6 +216,7 @@ async fn main() -> Result<()> {
     let app = Router::new()
         .nest("/api", api_routes)
         .route("/hls/{id}/{*file}", get(handlers::get_hls_file))
+        .route("/dash/{id}/{file}", get(handlers::get_dash_file))
         .route("/player/{id}", get(handlers::get_player))
         .route("/jassub/{filename}", get(handlers::get_jassub_worker))
         .route("/libbitsub/{filename}", get(handlers::get_libbitsub_worker))
@@ -198,6 +238,7 @@ async fn main() -> Result<()> {
                     Method::GET,
                     Method::POST,
                     Method::PUT,
+                    Method::PATCH,
                     Method::DELETE,
                     Method::OPTIONS,
                 ])
@@ -4,10 +4,12 @@ use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::RwLock;
 
+// IP -> (attempt_count, lockout_until)
+type AttemptsMap = HashMap<IpAddr, (u32, Option<Instant>)>;
+
 #[derive(Clone)]
 pub struct AuthRateLimiter {
-    // IP -> (attempt_count, lockout_until)
-    attempts: Arc<RwLock<HashMap<IpAddr, (u32, Option<Instant>)>>>,
+    attempts: Arc<RwLock<AttemptsMap>>,
 }
 
 impl AuthRateLimiter {
@@ -56,7 +58,7 @@ impl AuthRateLimiter {
         let mut map = self.attempts.write().await;
         let now = Instant::now();
         map.retain(|_, (_, lockout)| {
-            lockout.map_or(false, |l| now < l + Duration::from_secs(300))
+            lockout.is_some_and(|l| now < l + Duration::from_secs(300))
         });
     }
 }
@@ -1,3 +1,4 @@
+use crate::database::update_job_progress;
 use crate::types::{AppState, ProgressUpdate};
 use anyhow::{Context, Result};
 use aws_sdk_s3::primitives::ByteStream;
@@ -8,6 +9,7 @@ use std::sync::Arc;
 use std::sync::atomic::{AtomicU32, Ordering};
 use tokio::fs::{self, File};
 use tokio::io::AsyncReadExt;
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info};
 
 // 100 MB threshold for multipart upload
@@ -35,12 +37,22 @@ pub async fn upload_large_file_to_r2(
         // Set cache headers and content-type based on file type
         let (cache_control, content_type) = if key.ends_with(".ts") {
             ("public, max-age=31536000, immutable", "video/mp2t")
+        } else if key.ends_with(".m4s") {
+            ("public, max-age=31536000, immutable", "video/iso.segment")
+        } else if key.ends_with(".mp4") {
+            ("public, max-age=31536000, immutable", "video/mp4")
         } else if key.ends_with(".m3u8") {
             ("public, max-age=60", "application/vnd.apple.mpegurl")
+        } else if key.ends_with(".mpd") {
+            ("public, max-age=60", "application/dash+xml")
         } else if key.ends_with(".jpg") || key.ends_with(".jpeg") {
             ("public, max-age=31536000, immutable", "image/jpeg")
         } else if key.ends_with(".png") {
             ("public, max-age=31536000, immutable", "image/png")
+        } else if key.ends_with(".webp") {
+            ("public, max-age=31536000, immutable", "image/webp")
+        } else if key.ends_with(".avif") {
+            ("public, max-age=31536000, immutable", "image/avif")
         } else if key.ends_with(".vtt") {
             ("public, max-age=31536000, immutable", "text/vtt")
         } else if key.ends_with(".ass") || key.ends_with(".ssa") {
@@ -74,12 +86,22 @@ pub async fn upload_large_file_to_r2(
     // Determine content-type for multipart upload
     let content_type = if key.ends_with(".ts") {
         "video/mp2t"
+    } else if key.ends_with(".m4s") {
+        "video/iso.segment"
+    } else if key.ends_with(".mp4") {
+        "video/mp4"
     } else if key.ends_with(".m3u8") {
         "application/vnd.apple.mpegurl"
+    } else if key.ends_with(".mpd") {
+        "application/dash+xml"
     } else if key.ends_with(".jpg") || key.ends_with(".jpeg") {
         "image/jpeg"
     } else if key.ends_with(".png") {
         "image/png"
+    } else if key.ends_with(".webp") {
+        "image/webp"
+    } else if key.ends_with(".avif") {
+        "image/avif"
     } else if key.ends_with(".vtt") {
         "text/vtt"
     } else if key.ends_with(".ass") || key.ends_with(".ssa") {
@@ -239,11 +261,14 @@ async fn read_file_chunked(path: &PathBuf) -> Result<Vec<u8>> {
     Ok(buffer)
 }
 
+/// Upload an HLS output directory to R2. Stops early (dropping any in-flight requests) when
+/// `cancel` fires; the caller is responsible for removing keys that were already written.
 pub async fn upload_hls_to_r2(
     state: &AppState,
     hls_dir: &PathBuf,
     prefix: &str,
     upload_id: Option<&str>,
+    cancel: &CancellationToken,
 ) -> Result<String> {
     let mut master_playlist_key = None;
     let mut files_to_upload = Vec::new();
@@ -293,7 +318,7 @@ pub async fn upload_hls_to_r2(
     let total_files = files_to_upload.len() as u32;
     let uploaded_count = Arc::new(AtomicU32::new(0));
 
-    let upload_results: Vec<Result<String>> = stream::iter(files_to_upload)
+    let uploads = stream::iter(files_to_upload)
         .map(|(path, key)| {
             let state = state.clone();
             let uploaded_count = Arc::clone(&uploaded_count);
@@ -307,20 +332,39 @@ pub async fn upload_hls_to_r2(
                 let (cache_control, content_type) = if key.ends_with(".ts") {
                     // Video segments: cache aggressively (1 year, immutable)
                     ("public, max-age=31536000, immutable", "video/mp2t")
+                } else if key.ends_with(".m4s") {
+                    // fMP4 media segments
+                    ("public, max-age=31536000, immutable", "video/iso.segment")
+                } else if key.ends_with(".mp4") {
+                    // fMP4 init segments
+                    ("public, max-age=31536000, immutable", "video/mp4")
                 } else if key.ends_with(".m3u8") {
                     // Playlists: cache briefly (1 minute) for updates
                     ("public, max-age=60", "application/vnd.apple.mpegurl")
+                } else if key.ends_with(".mpd") {
+                    // DASH manifest: same caching as playlists
+                    ("public, max-age=60", "application/dash+xml")
                 } else if key.ends_with(".jpg") || key.ends_with(".jpeg") {
                     // Images: cache aggressively (1 year, immutable)
                     ("public, max-age=31536000, immutable", "image/jpeg")
                 } else if key.ends_with(".png") {
                     ("public, max-age=31536000, immutable", "image/png")
+                } else if key.ends_with(".webp") {
+                    ("public, max-age=31536000, immutable", "image/webp")
+                } else if key.ends_with(".avif") {
+                    ("public, max-age=31536000, immutable", "image/avif")
                 } else if key.ends_with(".vtt") {
                     ("public, max-age=31536000, immutable", "text/vtt")
                 } else if key.ends_with(".ass") || key.ends_with(".ssa") {
                     ("public, max-age=31536000, immutable", "text/x-ssa")
                 } else if key.ends_with(".srt") {
                     ("public, max-age=31536000, immutable", "text/srt")
+                } else if key.ends_with(".idx") {
+                    // VobSub index (plain text, paired with a .sub)
+                    ("public, max-age=31536000, immutable", "text/plain")
+                } else if key.ends_with(".sup") || key.ends_with(".sub") {
+                    // Bitmap subtitles (PGS, VobSub)
+                    ("public, max-age=31536000, immutable", "application/octet-stream")
                 } else {
                     // Other files: moderate caching (1 hour)
                     ("public, max-age=3600", "application/octet-stream")
@@ -344,14 +388,6 @@ pub async fn upload_hls_to_r2(
                 let current = uploaded_count.fetch_add(1, Ordering::Relaxed) + 1;
                 if let Some(id) = upload_id {
                     let percentage = ((current as f32 / total_files as f32) * 100.0) as u32;
-                    // Preserve video_name and created_at from existing progress
-                    let (existing_video_name, existing_created_at) = {
-                        let progress_map = state.progress.read().await;
-                        progress_map
-                            .get(&id)
-                            .map(|p| (p.video_name.clone(), p.created_at))
-                            .unwrap_or((None, 0))
-                    };
                     let progress_update = ProgressUpdate {
                         stage: "Upload to R2".to_string(),
                         current_chunk: current,
@@ -361,18 +397,23 @@ pub async fn upload_hls_to_r2(
                         status: "processing".to_string(),
                         result: None,
                         error: None,
-                        video_name: existing_video_name,
-                        created_at: existing_created_at,
+                        video_name: None,
                     };
-                    state.progress.write().await.insert(id, progress_update);
+                    if let Err(e) = update_job_progress(&state.db_pool, &id, &progress_update).await {
+                        error!("Failed to record upload progress for job {}: {}", id, e);
+                    }
                 }
 
                 Ok::<_, anyhow::Error>(key)
             }
         })
         .buffer_unordered(max_concurrent_uploads)
-        .collect()
-        .await;
+        .collect::<Vec<Result<String>>>();
+
+    let upload_results = tokio::select! {
+        results = uploads => results,
+        _ = cancel.cancelled() => anyhow::bail!("Upload to R2 cancelled"),
+    };
 
     // Check for any upload errors
     for result in upload_results {
@@ -385,6 +426,91 @@ pub async fn upload_hls_to_r2(
     Ok(playlist_key)
 }
 
+/// Upload an in-memory object (sidecar subtitles and other small edits). Keys are expected to be
+/// unique per version, so objects are cached like segments.
+pub async fn upload_bytes_to_r2(
+    state: &AppState,
+    key: &str,
+    body: Vec<u8>,
+    content_type: &str,
+) -> Result<()> {
+    state
+        .s3
+        .put_object()
+        .bucket(&state.config.r2.bucket)
+        .key(key)
+        .body(body.into())
+        .cache_control("public, max-age=31536000, immutable")
+        .content_type(content_type)
+        .send()
+        .await
+        .with_context(|| format!("Failed to upload {}", key))?;
+
+    info!("Uploaded: {}", key);
+    Ok(())
+}
+
+/// Download a (small) object into memory
+pub async fn download_bytes_from_r2(state: &AppState, key: &str) -> Result<Vec<u8>> {
+    let object = state
+        .s3
+        .get_object()
+        .bucket(&state.config.r2.bucket)
+        .key(key)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", key))?;
+
+    let bytes = object
+        .body
+        .collect()
+        .await
+        .with_context(|| format!("Failed to read {}", key))?
+        .into_bytes();
+
+    Ok(bytes.to_vec())
+}
+
+/// List every object key under `prefix`, following continuation tokens
+pub async fn list_keys_with_prefix(state: &AppState, prefix: &str) -> Result<Vec<String>> {
+    let mut keys = Vec::new();
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let list_resp = state
+            .s3
+            .list_objects_v2()
+            .bucket(&state.config.r2.bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation_token.clone())
+            .send()
+            .await
+            .with_context(|| format!("Failed to list objects under {}", prefix))?;
+
+        if let Some(contents) = list_resp.contents {
+            for obj in contents {
+                if let Some(key) = obj.key {
+                    keys.push(key);
+                }
+            }
+        }
+
+        if list_resp.is_truncated.unwrap_or(false) {
+            continuation_token = list_resp.next_continuation_token;
+        } else {
+            break;
+        }
+    }
+
+    Ok(keys)
+}
+
+/// Delete every object under `prefix` (e.g. `{video_id}/`). Returns the number of deleted objects.
+pub async fn delete_prefix_from_r2(state: &AppState, prefix: &str) -> Result<usize> {
+    let keys = list_keys_with_prefix(state, prefix).await?;
+    bulk_delete_from_r2(state, keys).await
+}
+
 /// Delete multiple objects from R2/S3 in batches (up to 1000 per request).
 /// This is much faster than deleting objects one at a time.
 pub async fn bulk_delete_from_r2(
@@ -0,0 +1,130 @@
+use crate::types::{StoryboardLayout, VideoMetadata};
+use crate::video::even_dimension;
+use crate::webvtt::format_timestamp;
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+use tokio::fs;
+
+/// Directory of the HLS output (and R2 prefix) holding the sprite sheets and the track
+pub const STORYBOARD_DIR: &str = "storyboard";
+/// WebVTT thumbnails track inside the storyboard directory
+pub const STORYBOARD_TRACK: &str = "thumbnails.vtt";
+/// Long edge of a thumbnail in pixels
+const TILE_LONG_EDGE: u32 = 160;
+/// Sheets are at most this many thumbnails wide and high
+const MAX_GRID: u32 = 10;
+/// Longer videos get a wider interval rather than more thumbnails than this
+const MAX_FRAMES: f64 = 1000.0;
+/// Candidate intervals in seconds; the shortest that stays within MAX_FRAMES is used
+const INTERVALS: [f64; 10] = [1.0, 2.0, 3.0, 5.0, 10.0, 15.0, 20.0, 30.0, 60.0, 120.0];
+
+/// Pick the thumbnail interval from the duration and size the tiles and sheets for it.
+/// A one-minute clip gets a thumbnail every second, a two-hour webinar one every 10 seconds.
+pub fn plan_storyboard(metadata: &VideoMetadata) -> StoryboardLayout {
+    let duration = metadata.duration as f64;
+    let interval = INTERVALS
+        .iter()
+        .copied()
+        .find(|interval| duration / interval <= MAX_FRAMES)
+        .unwrap_or(INTERVALS[INTERVALS.len() - 1]);
+    let frames = ((duration / interval).ceil() as u32).max(1);
+
+    let (display_width, display_height) = metadata.display_size();
+    let (tile_width, tile_height) = if display_width == 0 || display_height == 0 {
+        (TILE_LONG_EDGE, even_dimension(TILE_LONG_EDGE as f64 * 9.0 / 16.0))
+    } else if display_width >= display_height {
+        let height = TILE_LONG_EDGE as f64 * display_height as f64 / display_width as f64;
+        (TILE_LONG_EDGE, even_dimension(height))
+    } else {
+        let width = TILE_LONG_EDGE as f64 * display_width as f64 / display_height as f64;
+        (even_dimension(width), TILE_LONG_EDGE)
+    };
+
+    // Short videos get a sheet just big enough for their thumbnails
+    let columns = frames.min(MAX_GRID);
+    let rows = frames.div_ceil(columns).min(MAX_GRID);
+    let sheets = frames.div_ceil(columns * rows);
+
+    StoryboardLayout {
+        interval,
+        tile_width,
+        tile_height,
+        columns,
+        rows,
+        frames,
+        sheets: (0..sheets).map(sheet_name).collect(),
+    }
+}
+
+/// File name of the sheet at `index`, as written by ffmpeg's `sprite_%03d.jpg` pattern
+pub fn sheet_name(index: u32) -> String {
+    format!("sprite_{:03}.jpg", index)
+}
+
+/// ffmpeg filter sampling one frame per interval and tiling them into sheets. `tone_map` is
+/// appended after scaling (empty for SDR sources).
+pub fn sprite_filter(layout: &StoryboardLayout, tone_map: &str) -> String {
+    format!(
+        "fps=1/{},scale={}:{}{},setsar=1,tile={}x{}",
+        layout.interval, layout.tile_width, layout.tile_height, tone_map, layout.columns, layout.rows
+    )
+}
+
+/// Write the WebVTT thumbnails track for the sheets ffmpeg produced in `dir`. Returns the
+/// layout matching what is on disk, which may hold fewer frames than planned when the stream
+/// ends early.
+pub async fn write_storyboard_track(
+    dir: &Path,
+    layout: &StoryboardLayout,
+    duration: u32,
+) -> Result<StoryboardLayout> {
+    let mut sheets = Vec::new();
+    for index in 0.. {
+        let name = sheet_name(index);
+        if !fs::try_exists(dir.join(&name)).await.unwrap_or(false) {
+            break;
+        }
+        sheets.push(name);
+    }
+    if sheets.is_empty() {
+        anyhow::bail!("no sprite sheets were generated");
+    }
+
+    let per_sheet = layout.columns * layout.rows;
+    let frames = layout.frames.min(sheets.len() as u32 * per_sheet);
+    let interval_ms = (layout.interval * 1000.0).round() as u64;
+    let duration_ms = duration as u64 * 1000;
+
+    let mut track = String::from("WEBVTT\n\n");
+    for frame in 0..frames {
+        let start = frame as u64 * interval_ms;
+        let mut end = start + interval_ms;
+        // The last cue ends with the video
+        if frame + 1 == frames && duration_ms > start {
+            end = duration_ms;
+        }
+        let tile = frame % per_sheet;
+        let _ = write!(
+            track,
+            "{} --> {}\n{}#xywh={},{},{},{}\n\n",
+            format_timestamp(start),
+            format_timestamp(end),
+            sheets[(frame / per_sheet) as usize],
+            tile % layout.columns * layout.tile_width,
+            tile / layout.columns * layout.tile_height,
+            layout.tile_width,
+            layout.tile_height
+        );
+    }
+
+    fs::write(dir.join(STORYBOARD_TRACK), track)
+        .await
+        .context("failed to write storyboard track")?;
+
+    Ok(StoryboardLayout {
+        frames,
+        sheets,
+        ..layout.clone()
+    })
+}
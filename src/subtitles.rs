@@ -0,0 +1,438 @@
+use crate::webvtt::{Cue, format_timestamp, parse_cues, parse_timestamp};
+use anyhow::Result;
+use std::fmt::Write as _;
+
+/// Largest sidecar subtitle file accepted
+pub const MAX_SUBTITLE_BYTES: usize = 10 * 1024 * 1024;
+
+/// A sidecar subtitle file after validation, in the format stored on R2
+pub struct NormalizedSubtitle {
+    /// Codec name as stored in the subtitles table ("subrip" or "ass")
+    pub codec: &'static str,
+    pub extension: &'static str,
+    pub content: String,
+}
+
+/// Validate an uploaded SRT/VTT/ASS/SSA file and normalize it to what the player renders:
+/// UTF-8 without BOM and LF line endings; SRT and WebVTT are rewritten as clean, renumbered SRT,
+/// ASS/SSA are kept as they are.
+pub fn normalize_subtitle(bytes: &[u8], file_name: &str) -> Result<NormalizedSubtitle> {
+    if bytes.is_empty() {
+        anyhow::bail!("subtitle file is empty");
+    }
+    if bytes.len() > MAX_SUBTITLE_BYTES {
+        anyhow::bail!(
+            "subtitle file is larger than {} MB",
+            MAX_SUBTITLE_BYTES / 1024 / 1024
+        );
+    }
+
+    let text = decode_text(bytes).replace("\r\n", "\n").replace('\r', "\n");
+    let extension = file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_lowercase())
+        .unwrap_or_default();
+
+    let is_ass = match extension.as_str() {
+        "ass" | "ssa" => true,
+        "srt" | "vtt" => false,
+        // No usable extension: sniff the content
+        _ => text.trim_start().starts_with("[Script Info]"),
+    };
+
+    if is_ass {
+        validate_ass(&text)?;
+        return Ok(NormalizedSubtitle {
+            codec: "ass",
+            extension: "ass",
+            content: text,
+        });
+    }
+
+    if extension == "vtt" && !text.trim_start().starts_with("WEBVTT") {
+        anyhow::bail!("WebVTT file is missing the WEBVTT header");
+    }
+
+    let cues = parse_cues(&text);
+    if cues.is_empty() {
+        anyhow::bail!("no subtitle cues found");
+    }
+
+    Ok(NormalizedSubtitle {
+        codec: "subrip",
+        extension: "srt",
+        content: write_srt(&cues),
+    })
+}
+
+/// Serialize cues as SRT, numbered from 1 in start order
+pub fn write_srt(cues: &[Cue]) -> String {
+    let mut ordered: Vec<&Cue> = cues.iter().collect();
+    ordered.sort_by_key(|cue| (cue.start, cue.end));
+
+    let mut srt = String::new();
+    for (number, cue) in ordered.iter().enumerate() {
+        let _ = write!(
+            srt,
+            "{}\n{} --> {}\n{}\n\n",
+            number + 1,
+            srt_timestamp(cue.start),
+            srt_timestamp(cue.end),
+            cue.payload.trim_end()
+        );
+    }
+    srt
+}
+
+fn srt_timestamp(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+/// ASS/SSA files need the script header and at least one dialogue event
+fn validate_ass(text: &str) -> Result<()> {
+    if !text.contains("[Script Info]") {
+        anyhow::bail!("ASS/SSA file is missing the [Script Info] section");
+    }
+    if !text.contains("[Events]") {
+        anyhow::bail!("ASS/SSA file is missing the [Events] section");
+    }
+    if !text.lines().any(|line| line.trim_start().starts_with("Dialogue:")) {
+        anyhow::bail!("ASS/SSA file has no Dialogue events");
+    }
+    Ok(())
+}
+
+/// Decode UTF-8 (with or without BOM) or UTF-16 with BOM; anything else is read as Windows-1252,
+/// which most legacy subtitle files are
+fn decode_text(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        return String::from_utf8_lossy(rest).into_owned();
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFF, 0xFE]) {
+        let units: Vec<u16> = rest
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = rest
+            .chunks_exact(2)
+            .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+            .collect();
+        return String::from_utf16_lossy(&units);
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return text.to_string();
+    }
+
+    bytes.iter().map(|&byte| windows_1252_char(byte)).collect()
+}
+
+/// Windows-1252 differs from Latin-1 only in 0x80..=0x9F
+fn windows_1252_char(byte: u8) -> char {
+    const HIGH: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž',
+        '\u{8F}', '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}',
+        'ž', 'Ÿ',
+    ];
+    match byte {
+        0x80..=0x9F => HIGH[(byte - 0x80) as usize],
+        _ => byte as char,
+    }
+}
+
+/// Text subtitle formats that can be retimed, by file extension
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    Srt,
+    WebVtt,
+    Ass,
+}
+
+impl SubtitleFormat {
+    pub fn from_key(key: &str) -> Option<Self> {
+        match key.rsplit_once('.')?.1.to_lowercase().as_str() {
+            "srt" => Some(Self::Srt),
+            "vtt" => Some(Self::WebVtt),
+            "ass" | "ssa" => Some(Self::Ass),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Srt => "text/srt",
+            Self::WebVtt => "text/vtt",
+            Self::Ass => "text/x-ssa",
+        }
+    }
+}
+
+/// Linear retime of every timestamp: `t' = t * rate + offset_ms`, clamped at zero
+#[derive(Clone, Copy, Debug)]
+pub struct Retime {
+    pub offset_ms: i64,
+    pub rate: f64,
+}
+
+impl Retime {
+    fn apply(&self, ms: u64) -> u64 {
+        ((ms as f64 * self.rate).round() as i64 + self.offset_ms).max(0) as u64
+    }
+}
+
+/// Retime a subtitle file in place; everything but the timestamps (numbering, styles, cue
+/// settings, comments) is kept as it is
+pub fn retime_subtitle(content: &str, format: SubtitleFormat, retime: Retime) -> Result<String> {
+    let content = content.replace("\r\n", "\n");
+    let mut output = String::with_capacity(content.len());
+    let mut changed = 0;
+    // ASS lists the event fields in a Format line; Start and End are the 2nd and 3rd by default
+    let mut ass_fields = (1, 2, 10);
+
+    for line in content.lines() {
+        let retimed = match format {
+            SubtitleFormat::Srt | SubtitleFormat::WebVtt => {
+                retime_cue_timing(line, format == SubtitleFormat::Srt, retime)
+            }
+            SubtitleFormat::Ass => {
+                if let Some(fields) = line.trim_start().strip_prefix("Format:")
+                    && fields.contains("Start")
+                {
+                    let names: Vec<&str> = fields.split(',').map(str::trim).collect();
+                    if let (Some(start), Some(end)) = (
+                        names.iter().position(|n| *n == "Start"),
+                        names.iter().position(|n| *n == "End"),
+                    ) {
+                        ass_fields = (start, end, names.len());
+                    }
+                }
+                retime_ass_event(line, ass_fields, retime)
+            }
+        };
+
+        match retimed {
+            Some(line) => {
+                output.push_str(&line);
+                changed += 1;
+            }
+            None => output.push_str(line),
+        }
+        output.push('\n');
+    }
+
+    if changed == 0 {
+        anyhow::bail!("no timestamps found to retime");
+    }
+
+    Ok(output)
+}
+
+/// Retime an SRT/WebVTT timing line ("start --> end [settings]")
+fn retime_cue_timing(line: &str, srt: bool, retime: Retime) -> Option<String> {
+    let (start, rest) = line.split_once("-->")?;
+    let rest = rest.trim_start();
+    let (end, settings) = match rest.find(char::is_whitespace) {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, ""),
+    };
+    let start = parse_timestamp(start.trim())?;
+    let end = parse_timestamp(end)?;
+
+    let format = |ms: u64| {
+        if srt {
+            srt_timestamp(ms)
+        } else {
+            format_timestamp(ms)
+        }
+    };
+
+    Some(format!(
+        "{} --> {}{}",
+        format(retime.apply(start)),
+        format(retime.apply(end)),
+        settings
+    ))
+}
+
+/// Retime the Start/End fields of an ASS `Dialogue:` or `Comment:` event line
+fn retime_ass_event(line: &str, (start, end, count): (usize, usize, usize), retime: Retime) -> Option<String> {
+    let (kind, fields) = line.split_once(':')?;
+    if !matches!(kind.trim(), "Dialogue" | "Comment") {
+        return None;
+    }
+
+    // The last field (Text) may itself contain commas
+    let mut values: Vec<String> = fields.splitn(count, ',').map(str::to_string).collect();
+    for index in [start, end] {
+        let value = values.get_mut(index)?;
+        let ms = parse_ass_timestamp(value.trim())?;
+        // Keep the space after "Dialogue:" when Start is the first field
+        let indent = value.len() - value.trim_start().len();
+        value.replace_range(indent.., &format_ass_timestamp(retime.apply(ms)));
+    }
+
+    Some(format!("{}:{}", kind, values.join(",")))
+}
+
+/// Parse ASS `h:mm:ss.cc` into milliseconds
+fn parse_ass_timestamp(value: &str) -> Option<u64> {
+    let (clock, centis) = value.split_once('.')?;
+    let centis: u64 = centis.parse().ok()?;
+    let parts: Vec<u64> = clock
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let [h, m, s] = parts.as_slice() else {
+        return None;
+    };
+
+    Some((h * 3600 + m * 60 + s) * 1000 + centis * 10)
+}
+
+fn format_ass_timestamp(ms: u64) -> String {
+    // ASS has centisecond precision
+    let centis = (ms + 5) / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        centis / 360_000,
+        centis / 6000 % 60,
+        centis / 100 % 60,
+        centis % 100
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shift(offset_ms: i64) -> Retime {
+        Retime {
+            offset_ms,
+            rate: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_retime_srt_offset() {
+        let srt = "1\n00:00:01,000 --> 00:00:02,500\nHello\n";
+        let result = retime_subtitle(srt, SubtitleFormat::Srt, shift(1500)).unwrap();
+        assert_eq!(result, "1\n00:00:02,500 --> 00:00:04,000\nHello\n");
+    }
+
+    #[test]
+    fn test_retime_negative_offset_clamps_to_zero() {
+        let srt = "1\n00:00:01,000 --> 00:00:03,000\nHello\n";
+        let result = retime_subtitle(srt, SubtitleFormat::Srt, shift(-2000)).unwrap();
+        assert_eq!(result, "1\n00:00:00,000 --> 00:00:01,000\nHello\n");
+    }
+
+    #[test]
+    fn test_retime_fps_ratio() {
+        // Timed against 25 fps, played at 23.976 fps
+        let retime = Retime {
+            offset_ms: 0,
+            rate: 25.0 / 23.976,
+        };
+        let vtt = "WEBVTT\n\n00:00:10.000 --> 00:01:00.000 line:90%\nHello\n";
+        let result = retime_subtitle(vtt, SubtitleFormat::WebVtt, retime).unwrap();
+        assert_eq!(
+            result,
+            "WEBVTT\n\n00:00:10.427 --> 00:01:02.563 line:90%\nHello\n"
+        );
+    }
+
+    #[test]
+    fn test_retime_without_timestamps_fails() {
+        assert!(retime_subtitle("just text\n", SubtitleFormat::Srt, shift(1000)).is_err());
+    }
+
+    #[test]
+    fn test_retime_ass_default_columns() {
+        let ass = "[Events]\nDialogue: 0,0:00:01.00,0:00:02.50,Default,,0,0,0,,Hello, world\n";
+        let result = retime_subtitle(ass, SubtitleFormat::Ass, shift(500)).unwrap();
+        assert_eq!(
+            result,
+            "[Events]\nDialogue: 0,0:00:01.50,0:00:03.00,Default,,0,0,0,,Hello, world\n"
+        );
+    }
+
+    #[test]
+    fn test_retime_ass_follows_format_column_order() {
+        let ass = "[Events]\nFormat: Start, End, Style, Text\n\
+                   Dialogue: 0:00:01.00,0:00:02.00,Default,Hi, there\n\
+                   Comment: 0:00:05.00,0:00:06.00,Default,note\n";
+        let result = retime_subtitle(ass, SubtitleFormat::Ass, shift(-1500)).unwrap();
+        assert_eq!(
+            result,
+            "[Events]\nFormat: Start, End, Style, Text\n\
+             Dialogue: 0:00:00.00,0:00:00.50,Default,Hi, there\n\
+             Comment: 0:00:03.50,0:00:04.50,Default,note\n"
+        );
+    }
+
+    #[test]
+    fn test_ass_timestamp_round_trip() {
+        assert_eq!(parse_ass_timestamp("1:02:03.45"), Some(3_723_450));
+        assert_eq!(format_ass_timestamp(3_723_450), "1:02:03.45");
+        // Milliseconds round to the nearest centisecond
+        assert_eq!(format_ass_timestamp(1_234), "0:00:01.23");
+        assert_eq!(format_ass_timestamp(1_235), "0:00:01.24");
+        assert_eq!(parse_ass_timestamp("0:00:01"), None);
+    }
+
+    #[test]
+    fn test_normalize_utf16_srt() {
+        let text = "1\r\n00:00:01,000 --> 00:00:02,000\r\nHéllo\r\n";
+        let bytes: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let subtitle = normalize_subtitle(&bytes, "movie.srt").unwrap();
+        assert_eq!(subtitle.codec, "subrip");
+        assert_eq!(
+            subtitle.content,
+            "1\n00:00:01,000 --> 00:00:02,000\nHéllo\n\n"
+        );
+    }
+
+    #[test]
+    fn test_normalize_windows_1252() {
+        let bytes = b"1\n00:00:01,000 --> 00:00:02,000\n\x93Caf\xe9\x94\n";
+        let subtitle = normalize_subtitle(bytes, "movie.srt").unwrap();
+        assert_eq!(
+            subtitle.content,
+            "1\n00:00:01,000 --> 00:00:02,000\n“Café”\n\n"
+        );
+    }
+
+    #[test]
+    fn test_normalize_strips_utf8_bom() {
+        let bytes = b"\xEF\xBB\xBFWEBVTT\n\n00:01.000 --> 00:02.000\nHi\n";
+        let subtitle = normalize_subtitle(bytes, "movie.vtt").unwrap();
+        assert_eq!(subtitle.extension, "srt");
+        assert_eq!(subtitle.content, "1\n00:00:01,000 --> 00:00:02,000\nHi\n\n");
+    }
+
+    #[test]
+    fn test_normalize_sniffs_ass_without_extension() {
+        let ass =
+            "[Script Info]\nTitle: x\n\n[Events]\nDialogue: 0,0:00:01.00,0:00:02.00,,,0,0,0,,Hi\n";
+        let subtitle = normalize_subtitle(ass.as_bytes(), "upload").unwrap();
+        assert_eq!(subtitle.codec, "ass");
+        assert_eq!(subtitle.content, ass);
+    }
+
+    #[test]
+    fn test_normalize_rejects_vtt_without_header() {
+        let bytes = b"00:01.000 --> 00:02.000\nHi\n";
+        assert!(normalize_subtitle(bytes, "movie.vtt").is_err());
+    }
+}
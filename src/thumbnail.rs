@@ -0,0 +1,286 @@
+use crate::types::PosterImage;
+use anyhow::{Context, Result};
+use std::path::Path;
+use std::process::Stdio;
+use tokio::fs;
+use tokio::process::Command;
+use tracing::warn;
+
+/// Largest custom poster accepted
+pub const MAX_POSTER_BYTES: usize = 10 * 1024 * 1024;
+/// Width of generated thumbnails, as in the encode pipeline
+const THUMBNAIL_WIDTH: u32 = 480;
+/// Directory of the HLS output (and R2 prefix) holding the poster set
+pub const POSTERS_DIR: &str = "posters";
+/// Poster widths, from admin list rows up to full-width heroes. Sizes above the source width
+/// are skipped; the smallest is always produced.
+const POSTER_WIDTHS: [u32; 4] = [320, 640, 1280, 1920];
+
+/// An image format of the poster set
+pub struct PosterFormat {
+    pub extension: &'static str,
+    pub mime: &'static str,
+}
+
+const JPEG: PosterFormat = PosterFormat {
+    extension: "jpg",
+    mime: "image/jpeg",
+};
+const WEBP: PosterFormat = PosterFormat {
+    extension: "webp",
+    mime: "image/webp",
+};
+const AVIF: PosterFormat = PosterFormat {
+    extension: "avif",
+    mime: "image/avif",
+};
+const POSTER_FORMATS: [PosterFormat; 3] = [JPEG, WEBP, AVIF];
+
+/// MIME type of a poster from its key
+pub fn poster_mime(key: &str) -> Option<&'static str> {
+    let extension = key.rsplit_once('.')?.1;
+    POSTER_FORMATS
+        .iter()
+        .find(|format| format.extension == extension)
+        .map(|format| format.mime)
+}
+
+/// Render the poster set of the frame `seek` seconds into `input` into `dir`, as
+/// `poster_{width}.{ext}`. `source_width` is probed when unknown; `source_filter` (deinterlace
+/// and crop) runs first and `tone_map` is appended after scaling (empty for SDR). AVIF is best effort, as not every ffmpeg build has an AV1 encoder.
+/// Returned keys are file names relative to `dir`.
+pub async fn generate_posters(
+    input: &Path,
+    seek: f64,
+    source_width: Option<u32>,
+    source_filter: Option<&str>,
+    tone_map: &str,
+    dir: &Path,
+) -> Result<Vec<PosterImage>> {
+    fs::create_dir_all(dir).await?;
+
+    let source_width = match source_width {
+        Some(width) => width,
+        None => probe_width(input).await?,
+    };
+    let widths: Vec<u32> = POSTER_WIDTHS
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|(i, width)| *i == 0 || *width <= source_width)
+        .map(|(_, width)| width)
+        .collect();
+
+    let source_filter = source_filter
+        .map(|source_filter| format!("{},", source_filter))
+        .unwrap_or_default();
+    let filters = (source_filter.as_str(), tone_map);
+    encode_posters(input, seek, &widths, filters, dir, &[JPEG, WEBP]).await?;
+    if let Err(e) = encode_posters(input, seek, &widths, filters, dir, &[AVIF]).await {
+        warn!("AVIF posters skipped: {}", e);
+    }
+
+    Ok(collect_posters(dir).await)
+}
+
+/// Posters written to `dir` by `generate_posters`, with keys relative to `dir`
+pub async fn collect_posters(dir: &Path) -> Vec<PosterImage> {
+    let mut posters = Vec::new();
+    for format in &POSTER_FORMATS {
+        for width in POSTER_WIDTHS {
+            let name = format!("poster_{}.{}", width, format.extension);
+            if fs::try_exists(dir.join(&name)).await.unwrap_or(false) {
+                posters.push(PosterImage { width, key: name });
+            }
+        }
+    }
+    posters
+}
+
+/// One ffmpeg run decoding the frame once and writing every width in `formats`. `filters` are
+/// the chains placed before and after scaling.
+async fn encode_posters(
+    input: &Path,
+    seek: f64,
+    widths: &[u32],
+    (source_filter, tone_map): (&str, &str),
+    dir: &Path,
+    formats: &[PosterFormat],
+) -> Result<()> {
+    let outputs = widths.len() * formats.len();
+    let mut graph = format!("[0:v:0]{}split={}", source_filter, outputs);
+    for i in 0..outputs {
+        graph.push_str(&format!("[p{}]", i));
+    }
+    for (i, width) in widths.iter().cycle().take(outputs).enumerate() {
+        graph.push_str(&format!(";[p{}]scale={}:-2{},setsar=1[o{}]", i, width, tone_map, i));
+    }
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.kill_on_drop(true)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y");
+    if seek > 0.0 {
+        cmd.arg("-ss").arg(format!("{:.3}", seek));
+    }
+    cmd.arg("-i").arg(input).arg("-filter_complex").arg(&graph);
+
+    for (f, format) in formats.iter().enumerate() {
+        for (w, width) in widths.iter().enumerate() {
+            cmd.arg("-map")
+                .arg(format!("[o{}]", f * widths.len() + w))
+                .arg("-frames:v")
+                .arg("1");
+            match format.extension {
+                "webp" => cmd.arg("-c:v").arg("libwebp").arg("-quality").arg("80"),
+                "avif" => cmd
+                    .arg("-c:v")
+                    .arg("libaom-av1")
+                    .arg("-still-picture")
+                    .arg("1")
+                    .arg("-crf")
+                    .arg("30")
+                    .arg("-cpu-used")
+                    .arg("6")
+                    .arg("-pix_fmt")
+                    .arg("yuv420p"),
+                _ => cmd.arg("-q:v").arg("3"),
+            };
+            cmd.arg(dir.join(format!("poster_{}.{}", width, format.extension)));
+        }
+    }
+
+    let output = cmd.output().await.context("failed to run ffmpeg")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg poster encoding failed: {}", stderr);
+    }
+
+    Ok(())
+}
+
+/// Width of the first video stream (or image)
+async fn probe_width(input: &Path) -> Result<u32> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=width")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input)
+        .output()
+        .await
+        .context("failed to run ffprobe")?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .context("could not read the image width")
+}
+
+/// The media segment of an HLS rendition that holds a given timestamp
+pub struct SegmentAt {
+    /// Segment URI relative to the media playlist
+    pub uri: String,
+    /// Position of the timestamp inside the segment, in seconds
+    pub offset: f64,
+    /// `EXT-X-MAP` init segment of fMP4 renditions
+    pub init_uri: Option<String>,
+}
+
+/// Find the segment of a VOD media playlist covering `time`. Times past the end land on the last
+/// frame of the last segment.
+pub fn find_segment(playlist: &str, time: f64) -> Option<SegmentAt> {
+    let mut init_uri = None;
+    let mut segment_start = 0.0;
+    let mut pending_duration = None;
+    let mut last = None;
+
+    for line in playlist.lines().map(str::trim) {
+        if let Some(attributes) = line.strip_prefix("#EXT-X-MAP:") {
+            init_uri = attributes
+                .split_once("URI=\"")
+                .and_then(|(_, rest)| rest.split_once('"'))
+                .map(|(uri, _)| uri.to_string());
+        } else if let Some(info) = line.strip_prefix("#EXTINF:") {
+            pending_duration = info.split(',').next().and_then(|d| d.trim().parse::<f64>().ok());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            let Some(duration) = pending_duration.take() else {
+                continue;
+            };
+            if time < segment_start + duration {
+                return Some(SegmentAt {
+                    uri: line.to_string(),
+                    offset: (time - segment_start).max(0.0),
+                    init_uri,
+                });
+            }
+            last = Some((line.to_string(), duration));
+            segment_start += duration;
+        }
+    }
+
+    // Slightly before the end so the decoder still has a frame to return
+    last.map(|(uri, duration)| SegmentAt {
+        uri,
+        offset: (duration - 0.1).max(0.0),
+        init_uri,
+    })
+}
+
+/// Decode the frame `offset` seconds into `media` and write it as a JPEG thumbnail
+pub async fn grab_frame(media: &Path, offset: f64, output: &Path) -> Result<()> {
+    let frame_output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y")
+        .arg("-i")
+        .arg(media)
+        // Output seeking decodes up to the exact frame; segments are only a few seconds long
+        .arg("-ss")
+        .arg(format!("{:.3}", offset))
+        .arg("-map")
+        .arg("0:v:0")
+        .arg("-vf")
+        .arg(format!("scale={}:-2", THUMBNAIL_WIDTH))
+        .arg("-frames:v")
+        .arg("1")
+        .arg("-q:v")
+        .arg("2")
+        .arg(output)
+        .output()
+        .await
+        .context("failed to run ffmpeg")?;
+
+    if !frame_output.status.success() {
+        let stderr = String::from_utf8_lossy(&frame_output.stderr);
+        anyhow::bail!("ffmpeg frame grab failed: {}", stderr);
+    }
+    if !output.exists() {
+        anyhow::bail!("no frame at {:.3}s of the segment", offset);
+    }
+
+    Ok(())
+}
+
+/// Recognize an uploaded poster by its signature. Returns (extension, content type).
+pub fn detect_image(bytes: &[u8]) -> Option<(&'static str, &'static str)> {
+    if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some(("jpg", "image/jpeg"))
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some(("png", "image/png"))
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        Some(("webp", "image/webp"))
+    } else {
+        None
+    }
+}
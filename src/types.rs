@@ -2,9 +2,10 @@ use crate::config::Config;
 use aws_sdk_s3::Client as S3Client;
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
-use tokio::sync::{RwLock, Semaphore};
+use tokio::sync::{Notify, RwLock, Semaphore};
+use tokio_util::sync::CancellationToken;
 
 #[derive(Serialize)]
 pub struct ConfigInfo {
@@ -23,40 +24,132 @@ pub struct ProgressUpdate {
     pub result: Option<UploadResponse>,
     pub error: Option<String>,
     pub video_name: Option<String>,
+}
+
+/// A processing job persisted in the `jobs` table
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+    pub id: String,
+    pub video_id: Option<String>,
+    pub video_name: Option<String>,
+    pub tags: Vec<String>,
+    pub options: UploadOptions,
+    pub source_path: Option<String>,
+    pub stage: String,
+    pub status: String, // "initializing", "queued", "processing", "completed", "failed"
+    pub current_chunk: u32,
+    pub total_chunks: u32,
+    pub percentage: u32,
+    pub details: Option<String>,
+    pub error: Option<String>,
+    pub attempts: u32,
+    pub player_url: Option<String>,
     pub created_at: u64,
+    pub updated_at: u64,
+}
+
+/// Processing options chosen for a single upload; unset fields fall back to the config
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct UploadOptions {
+    /// Two-pass loudness normalization of the audio tracks (`video.audio.loudnorm.enabled`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalize_audio: Option<bool>,
+    /// Propose draft chapters from scene changes and silences (`video.auto_chapters.enabled`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_chapters: Option<bool>,
+    /// Detect and crop black bars (`video.auto_crop.enabled`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_crop: Option<bool>,
+    /// Crop to this rectangle instead of detecting one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crop: Option<CropRect>,
 }
 
-pub type ProgressMap = Arc<RwLock<HashMap<String, ProgressUpdate>>>;
+/// Crop rectangle in source pixels, in display orientation (rotation applied, pixel aspect
+/// ratio not)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CropRect {
+    pub width: u32,
+    pub height: u32,
+    pub x: u32,
+    pub y: u32,
+}
 
-#[derive(Clone, Debug)]
+impl CropRect {
+    /// Parse ffmpeg's `w:h:x:y` notation, as printed by cropdetect
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split(':').map(|part| part.trim().parse::<u32>().ok());
+        let rect = CropRect {
+            width: parts.next()??,
+            height: parts.next()??,
+            x: parts.next()??,
+            y: parts.next()??,
+        };
+        (parts.next().is_none() && rect.width > 0 && rect.height > 0).then_some(rect)
+    }
+
+    /// Whether the rectangle lies inside a `frame_width` x `frame_height` frame
+    pub fn fits(&self, frame_width: u32, frame_height: u32) -> bool {
+        self.x.checked_add(self.width).is_some_and(|right| right <= frame_width)
+            && self.y.checked_add(self.height).is_some_and(|bottom| bottom <= frame_height)
+    }
+
+    /// ffmpeg crop filter for the rectangle
+    pub fn filter(&self) -> String {
+        format!("crop={}:{}:{}:{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+impl Job {
+    /// Whether the job has reached a terminal state
+    pub fn is_finished(&self) -> bool {
+        self.status == "completed" || self.status == "failed"
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct VideoVariant {
     pub label: String,
+    /// Output frame size in display orientation (square pixels)
+    pub width: u32,
     pub height: u32,
     pub bitrate: u32, // in kbps
+    pub maxrate: u32, // in kbps
+    /// Constant quality encode capped at `maxrate`; `bitrate` is then only an estimate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub crf: Option<u32>,
+    /// Profile/level override for this rung (H.264 only)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
 }
 
 impl VideoVariant {
     /// Create a new variant with dynamically calculated bitrate based on resolution
     /// Uses bits-per-pixel (BPP) formula for optimal quality/size balance
-    pub fn new(label: &str, height: u32) -> Self {
+    pub fn new(label: &str, width: u32, height: u32, fps: f64) -> Self {
+        let bitrate = Self::calculate_bitrate(width, height, fps);
         Self {
             label: label.to_string(),
+            width,
             height,
-            bitrate: Self::calculate_bitrate(height),
+            bitrate,
+            maxrate: bitrate * 3 / 2,
+            crf: None,
+            profile: None,
+            level: None,
         }
     }
 
     /// Calculate optimal bitrate based on resolution using BPP (bits per pixel)
     /// BPP of 0.1 is good for H.264 with motion (live action)
-    /// Formula: bitrate = width * height * fps * bpp
-    pub fn calculate_bitrate(height: u32) -> u32 {
-        // Assume 16:9 aspect ratio and 24fps (common for movies)
-        let width = (height as f64 * 16.0 / 9.0).round() as u32;
-        let fps = 24.0;
-
-        // BPP values tuned for H.264 encoding quality
+    /// Formula: bitrate = width * height * 24 * bpp * sqrt(fps / 24)
+    pub fn calculate_bitrate(width: u32, height: u32, fps: f64) -> u32 {
+        // BPP values tuned for H.264 encoding quality, keyed on the short edge so
+        // vertical videos get the same treatment as their landscape counterparts.
         // Higher resolutions can use lower BPP due to better compression efficiency
-        let bpp = match height {
+        let bpp = match width.min(height) {
             0..=480 => 0.12,     // SD needs higher BPP for quality
             481..=720 => 0.10,   // HD sweet spot
             721..=1080 => 0.08,  // FHD - good compression
@@ -64,7 +157,10 @@ impl VideoVariant {
             _ => 0.06,           // 4K+ - very efficient
         };
 
-        let bitrate_bps = (width as f64) * (height as f64) * fps * bpp;
+        // Consecutive frames differ less at high frame rates, so bits grow slower than fps
+        let fps_factor = (fps / 24.0).clamp(0.5, 2.5).sqrt();
+
+        let bitrate_bps = (width as f64) * (height as f64) * 24.0 * bpp * fps_factor;
         let bitrate_kbps = (bitrate_bps / 1000.0).round() as u32;
 
         // Clamp to reasonable bounds
@@ -77,10 +173,10 @@ impl VideoVariant {
         format!("{}k", self.bitrate)
     }
 
-    /// Get max bitrate (1.5x target) for VBR headroom
+    /// Get max bitrate for VBR headroom (1.5x target unless the per-title ladder measured otherwise)
     #[inline]
     pub fn max_bitrate(&self) -> u32 {
-        self.bitrate * 3 / 2
+        self.maxrate
     }
 
     /// Get buffer size (2x target) for smooth streaming
@@ -90,9 +186,14 @@ impl VideoVariant {
     }
 
     /// Get bandwidth in bps for HLS manifest
+    /// CRF rungs have no target bitrate, so their cap is advertised instead
     #[inline]
     pub fn bandwidth(&self) -> u32 {
-        self.bitrate * 1000
+        if self.crf.is_some() {
+            self.maxrate * 1000
+        } else {
+            self.bitrate * 1000
+        }
     }
 }
 
@@ -101,7 +202,8 @@ pub struct AppState {
     pub config: Config,
     pub s3: S3Client,
     pub db_pool: SqlitePool,
-    pub progress: ProgressMap,
+    pub job_notify: Arc<Notify>,
+    pub job_cancellations: JobCancellationMap,
     pub ffmpeg_semaphore: Arc<Semaphore>,
     pub chunked_uploads: ChunkedUploadsMap,
     pub auth_rate_limiter: crate::rate_limit::AuthRateLimiter,
@@ -147,10 +249,59 @@ pub struct VideoDto {
     pub available_resolutions: Vec<String>,
     pub duration: u32,
     pub thumbnail_url: String,
+    /// `srcset` of the poster set by MIME type, e.g. "image/webp" -> "… 320w, … 640w"
+    pub thumbnail_srcset: BTreeMap<String, String>,
     pub sprites_url: Option<String>,
+    /// WebVTT thumbnails track (`#xywh=` fragments into the sprite sheets)
+    pub storyboard_url: Option<String>,
+    /// Sprite sheet layout; absent for videos encoded with the single 10x10 sprite
+    pub storyboard: Option<StoryboardLayout>,
+    /// Silent looping hover preview
+    pub preview_url: Option<PreviewUrls>,
     pub player_url: String,
     pub created_at: String,
     pub is_public: bool,
+    /// Source complexity measured by the per-title analysis (1.0 = default ladder)
+    pub complexity: Option<f64>,
+    /// Bitrate ladder the video was encoded with
+    pub ladder: Vec<VideoVariant>,
+    /// Black bars cropped from the source before scaling
+    pub crop: Option<CropRect>,
+    /// Field order ("tff" or "bff") an interlaced source was deinterlaced with
+    pub deinterlaced: Option<String>,
+}
+
+/// One image of a video's poster set
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PosterImage {
+    pub width: u32,
+    /// R2 key; the extension gives the format (jpg, webp or avif)
+    pub key: String,
+}
+
+/// Hover preview clips of a video; either may be missing if its encoder was unavailable
+#[derive(Serialize)]
+pub struct PreviewUrls {
+    /// Animated WebP for `<img>`
+    pub webp: Option<String>,
+    /// H.264 MP4 for a muted, looping `<video>`
+    pub mp4: Option<String>,
+}
+
+/// Layout of a video's storyboard: thumbnails every `interval` seconds, tiled row by row into
+/// `columns` x `rows` sheets. The last sheet may be partially filled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StoryboardLayout {
+    /// Seconds between two thumbnails
+    pub interval: f64,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub columns: u32,
+    pub rows: u32,
+    /// Thumbnails across all sheets
+    pub frames: u32,
+    /// Sheet file names, relative to the storyboard track
+    pub sheets: Vec<String>,
 }
 
 #[derive(Serialize)]
@@ -172,6 +323,8 @@ pub struct QueueItem {
     pub percentage: u32,
     pub details: Option<String>,
     pub status: String,
+    pub error: Option<String>,
+    pub attempts: u32,
     pub video_name: Option<String>,
     pub created_at: u64,
 }
@@ -195,6 +348,9 @@ pub struct ChunkedUpload {
 
 pub type ChunkedUploadsMap = Arc<RwLock<HashMap<String, ChunkedUpload>>>;
 
+// Job ID -> cancellation token for jobs the worker is currently running
+pub type JobCancellationMap = Arc<RwLock<HashMap<String, CancellationToken>>>;
+
 #[derive(Serialize)]
 pub struct ChunkUploadResponse {
     pub upload_id: String,
@@ -206,6 +362,11 @@ pub struct ChunkUploadResponse {
 pub struct FinalizeUploadRequest {
     pub name: String,
     pub tags: Option<String>,
+    pub normalize_audio: Option<bool>,
+    pub auto_chapters: Option<bool>,
+    pub auto_crop: Option<bool>,
+    /// Crop rectangle as `w:h:x:y`
+    pub crop: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -218,10 +379,48 @@ pub struct SubtitleTrack {
     pub codec: String,
     pub storage_key: String,
     pub idx_storage_key: Option<String>, // For VobSub subtitles (.idx file)
+    /// File before the last retime or replace, kept so the change can be reverted
+    pub previous_storage_key: Option<String>,
+    /// Codec of the previous file
+    pub previous_codec: Option<String>,
+    /// VobSub index of the previous file
+    pub previous_idx_storage_key: Option<String>,
     pub is_default: bool,
     pub is_forced: bool,
 }
 
+impl SubtitleTrack {
+    /// The file the track currently points at
+    pub fn current_version(&self) -> SubtitleVersion<'_> {
+        SubtitleVersion {
+            codec: &self.codec,
+            storage_key: &self.storage_key,
+            idx_storage_key: self.idx_storage_key.as_deref(),
+        }
+    }
+
+    /// The file kept for a revert. Versions saved before the codec was recorded keep the
+    /// current codec.
+    pub fn previous_version(&self) -> Option<SubtitleVersion<'_>> {
+        self.previous_storage_key
+            .as_deref()
+            .map(|storage_key| SubtitleVersion {
+                codec: self.previous_codec.as_deref().unwrap_or(&self.codec),
+                storage_key,
+                idx_storage_key: self.previous_idx_storage_key.as_deref(),
+            })
+    }
+}
+
+/// One file version of a subtitle track
+#[derive(Clone, Copy, Debug)]
+pub struct SubtitleVersion<'a> {
+    pub codec: &'a str,
+    pub storage_key: &'a str,
+    /// VobSub index of a bitmap track
+    pub idx_storage_key: Option<&'a str>,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Attachment {
     pub id: i64,
@@ -243,6 +442,71 @@ pub struct AudioTrack {
     pub sample_rate: Option<i32>,
     pub bit_rate: Option<i64>,
     pub is_default: bool,
+    /// R2 key of the track's main (stereo) HLS playlist
+    pub playlist_key: Option<String>,
+    /// URL of the main playlist for API clients, resolved per request
+    pub uri: Option<String>,
+    /// Encoded renditions of this track (stereo, plus 5.1 for multichannel sources)
+    pub renditions: Vec<AudioRendition>,
+    /// Loudness normalization result, when the track was normalized
+    pub loudness: Option<AudioLoudness>,
+}
+
+/// EBU R128 loudness of an audio track as measured by the first `loudnorm` pass, the configured
+/// targets the second pass normalized it to, and the loudness the second pass reported for the
+/// main rendition
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AudioLoudness {
+    /// Measured integrated loudness in LUFS
+    pub input_i: f64,
+    /// Measured true peak in dBTP
+    pub input_tp: f64,
+    /// Measured loudness range in LU
+    pub input_lra: f64,
+    /// Measured gating threshold in LUFS
+    pub input_thresh: f64,
+    /// Offset gain in LU applied after the dynamic stage
+    pub target_offset: f64,
+    /// Configured integrated loudness target in LUFS
+    pub target_i: f64,
+    /// Configured true peak limit in dBTP
+    pub target_tp: f64,
+    /// Configured loudness range target in LU
+    pub target_lra: f64,
+    /// Integrated loudness of the normalized output in LUFS
+    pub output_i: Option<f64>,
+    /// True peak of the normalized output in dBTP
+    pub output_tp: Option<f64>,
+    /// Loudness range of the normalized output in LU
+    pub output_lra: Option<f64>,
+}
+
+/// One encoded rendition of an audio track
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AudioRendition {
+    /// Output directory relative to the video root (e.g. "audio_eng_0", "audio_eng_0_51")
+    pub name: String,
+    /// FFmpeg encoder ("aac" or "eac3")
+    pub codec: String,
+    pub channels: i32,
+    /// Target bitrate in bps
+    pub bit_rate: i64,
+    /// R2 key of the rendition's HLS playlist, set once the video is stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub playlist_key: Option<String>,
+    /// URL of the playlist for API clients, resolved per request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+}
+
+impl AudioRendition {
+    /// RFC 6381 codec string for playlists and manifests
+    pub fn codec_string(&self) -> &'static str {
+        match self.codec.as_str() {
+            "eac3" => "ec-3",
+            _ => "mp4a.40.2",
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -273,6 +537,134 @@ pub struct AudioStreamInfo {
     pub is_default: bool,
 }
 
+/// Geometry and timing of the first video stream, as probed by ffprobe
+#[derive(Clone, Debug)]
+pub struct VideoMetadata {
+    /// Coded frame size
+    pub width: u32,
+    pub height: u32,
+    /// Clockwise display rotation in degrees (0, 90, 180 or 270)
+    pub rotation: u32,
+    pub sample_aspect_ratio: f64,
+    /// Display aspect ratio before rotation
+    pub display_aspect_ratio: f64,
+    pub frame_rate: f64,
+    pub duration: u32,
+    /// ffprobe color_transfer, e.g. "smpte2084" (PQ), "arib-std-b67" (HLG) or "bt709"
+    pub color_transfer: Option<String>,
+    /// ffprobe color_primaries, e.g. "bt2020"
+    pub color_primaries: Option<String>,
+    /// ffprobe color_space (matrix coefficients), e.g. "bt2020nc"
+    pub color_space: Option<String>,
+    /// ffprobe field_order tag: "progressive", "tt", "bb", "tb", "bt" or unset
+    pub field_order: Option<String>,
+    /// Field order to deinterlace with, set by the interlacing check; `None` for progressive
+    pub interlaced: Option<FieldOrder>,
+    /// Crop applied before scaling; the frame size fields already describe the cropped picture
+    pub crop: Option<CropRect>,
+}
+
+/// Which field of an interlaced frame is displayed first
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldOrder {
+    TopFirst,
+    BottomFirst,
+}
+
+impl FieldOrder {
+    /// Parity option value of FFmpeg's deinterlace filters
+    pub fn parity(&self) -> &'static str {
+        match self {
+            FieldOrder::TopFirst => "tff",
+            FieldOrder::BottomFirst => "bff",
+        }
+    }
+}
+
+/// HDR transfer function of a source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HdrFormat {
+    /// SMPTE ST 2084 (HDR10)
+    Pq,
+    /// ARIB STD-B67 Hybrid Log-Gamma
+    Hlg,
+}
+
+impl HdrFormat {
+    /// FFmpeg color_trc name
+    pub fn transfer(&self) -> &'static str {
+        match self {
+            HdrFormat::Pq => "smpte2084",
+            HdrFormat::Hlg => "arib-std-b67",
+        }
+    }
+
+    /// HLS VIDEO-RANGE attribute value
+    pub fn video_range(&self) -> &'static str {
+        match self {
+            HdrFormat::Pq => "PQ",
+            HdrFormat::Hlg => "HLG",
+        }
+    }
+
+    /// ISO/IEC 23091-2 TransferCharacteristics code point, signalled in DASH manifests
+    pub fn cicp_transfer(&self) -> u8 {
+        match self {
+            HdrFormat::Pq => 16,
+            HdrFormat::Hlg => 18,
+        }
+    }
+}
+
+impl VideoMetadata {
+    /// HDR format of the source, detected from its transfer characteristics
+    pub fn hdr_format(&self) -> Option<HdrFormat> {
+        match self.color_transfer.as_deref() {
+            Some("smpte2084") => Some(HdrFormat::Pq),
+            Some("arib-std-b67") => Some(HdrFormat::Hlg),
+            _ => None,
+        }
+    }
+
+    /// Frame size as the viewer sees it: pixel aspect ratio applied and rotation taken into account
+    pub fn display_size(&self) -> (u32, u32) {
+        let width = (self.height as f64 * self.display_aspect_ratio).round() as u32;
+        if self.rotation % 180 == 90 {
+            (self.height, width)
+        } else {
+            (width, self.height)
+        }
+    }
+
+    /// Crop the source to `crop`, updating the frame size and display aspect ratio to match
+    pub fn apply_crop(&mut self, crop: CropRect) {
+        let (width, height) = if self.rotation % 180 == 90 {
+            (crop.height, crop.width)
+        } else {
+            (crop.width, crop.height)
+        };
+        self.width = width;
+        self.height = height;
+        self.display_aspect_ratio = width as f64 * self.sample_aspect_ratio / height as f64;
+        self.crop = Some(crop);
+    }
+
+    /// Size of the decoded frames as filters see them: rotation applied, pixel aspect ratio not
+    pub fn frame_size(&self) -> (u32, u32) {
+        if self.rotation % 180 == 90 {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+
+    /// Ladder rungs are picked by the short edge, so a 1080x1920 phone video counts as 1080p
+    pub fn short_edge(&self) -> u32 {
+        let (width, height) = self.display_size();
+        width.min(height)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct AttachmentInfo {
     pub filename: String,
@@ -287,6 +679,8 @@ pub struct Chapter {
     pub start_time: f64,
     pub end_time: f64,
     pub title: String,
+    /// Proposed by chapter detection and not yet accepted; hidden from viewers
+    pub is_draft: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -294,6 +688,7 @@ pub struct ChapterInfo {
     pub start_time: f64,
     pub end_time: f64,
     pub title: String,
+    pub is_draft: bool,
 }
 
 #[derive(Serialize)]
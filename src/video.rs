@@ -1,16 +1,28 @@
+use crate::config::{
+    AudioConfig, AutoCropConfig, LadderRung, LoudnormConfig, SegmentFormat, SurroundCodec, VideoConfig,
+};
+use crate::dash::{DashAudio, DashVideo, write_dash_manifest};
+use crate::database::update_job_progress;
+use crate::storyboard::{STORYBOARD_DIR, sprite_filter};
+use crate::thumbnail::{POSTERS_DIR, generate_posters};
 use crate::types::{
-    AttachmentInfo, AudioStreamInfo, ChapterInfo, ProgressMap, ProgressUpdate, SubtitleStreamInfo,
-    VideoVariant,
+    AttachmentInfo, AudioLoudness, CropRect, FieldOrder, HdrFormat, AudioRendition, AudioStreamInfo, ChapterInfo, ProgressUpdate,
+    StoryboardLayout, SubtitleStreamInfo, VideoMetadata, VideoVariant,
 };
+use crate::webvtt::write_webvtt_playlist;
 use anyhow::{Context, Result};
-use futures::future::try_join_all;
-use std::path::PathBuf;
+use futures::future::{join, join_all, try_join, try_join_all};
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
 use tokio::{fs, process::Command};
 use tracing::{error, info, warn};
 
 /// Check if video has problematic data/timecode streams that might cause encoding issues
+#[allow(dead_code)]
 pub async fn has_data_streams(input: &PathBuf) -> Result<bool> {
     let output = Command::new("ffprobe")
         .arg("-v")
@@ -34,10 +46,8 @@ pub async fn has_data_streams(input: &PathBuf) -> Result<bool> {
     // Check if any stream has codec_type "data"
     if let Some(streams) = v["streams"].as_array() {
         for stream in streams {
-            if let Some(codec_type) = stream["codec_type"].as_str() {
-                if codec_type == "data" {
-                    return Ok(true);
-                }
+            if stream["codec_type"].as_str() == Some("data") {
+                return Ok(true);
             }
         }
     }
@@ -45,7 +55,7 @@ pub async fn has_data_streams(input: &PathBuf) -> Result<bool> {
     Ok(false)
 }
 
-pub async fn get_video_metadata(input: &PathBuf) -> Result<(u32, u32)> {
+pub async fn get_video_metadata(input: &PathBuf) -> Result<VideoMetadata> {
     // Using JSON output
     let output = Command::new("ffprobe")
         .arg("-v")
@@ -53,7 +63,7 @@ pub async fn get_video_metadata(input: &PathBuf) -> Result<(u32, u32)> {
         .arg("-select_streams")
         .arg("v:0")
         .arg("-show_entries")
-        .arg("stream=height:format=duration")
+        .arg("stream=width,height,sample_aspect_ratio,display_aspect_ratio,r_frame_rate,avg_frame_rate,color_transfer,color_primaries,color_space,field_order:stream_tags=rotate:stream_side_data=rotation:format=duration")
         .arg("-of")
         .arg("json")
         .arg(input)
@@ -67,8 +77,10 @@ pub async fn get_video_metadata(input: &PathBuf) -> Result<(u32, u32)> {
 
     let json_str = String::from_utf8(output.stdout)?;
     let v: serde_json::Value = serde_json::from_str(&json_str)?;
+    let stream = &v["streams"][0];
 
-    let height = v["streams"][0]["height"]
+    let width = stream["width"].as_u64().context("no width found")? as u32;
+    let height = stream["height"]
         .as_u64()
         .context("no height found")? as u32;
     let duration_str = v["format"]["duration"]
@@ -76,19 +88,355 @@ pub async fn get_video_metadata(input: &PathBuf) -> Result<(u32, u32)> {
         .context("no duration found")?;
     let duration: f64 = duration_str.parse()?;
 
-    Ok((height, duration.round() as u32))
+    // "0:1" / "N/A" mean unknown, which is treated as square pixels
+    let sample_aspect_ratio = stream["sample_aspect_ratio"]
+        .as_str()
+        .and_then(|sar| parse_ratio(sar, ':'))
+        .unwrap_or(1.0);
+    let display_aspect_ratio = stream["display_aspect_ratio"]
+        .as_str()
+        .and_then(|dar| parse_ratio(dar, ':'))
+        .unwrap_or(width as f64 * sample_aspect_ratio / height.max(1) as f64);
+
+    // avg_frame_rate is the better estimate for VFR phone footage; r_frame_rate is the fallback
+    let frame_rate = ["avg_frame_rate", "r_frame_rate"]
+        .iter()
+        .filter_map(|key| stream[*key].as_str().and_then(|r| parse_ratio(r, '/')))
+        .find(|fps| (1.0..=240.0).contains(fps))
+        .unwrap_or(24.0);
+
+    // Newer ffprobe reports the display matrix as side data, older versions as a rotate tag.
+    // The display matrix angle is counter-clockwise, the tag is clockwise.
+    let rotation = stream["side_data_list"]
+        .as_array()
+        .and_then(|list| list.iter().find_map(|sd| sd["rotation"].as_f64()))
+        .map(|degrees| -degrees)
+        .or_else(|| {
+            stream["tags"]["rotate"]
+                .as_str()
+                .and_then(|r| r.parse::<f64>().ok())
+        })
+        .unwrap_or(0.0);
+    let rotation = ((rotation.round() as i64).rem_euclid(360) as u32 + 45) / 90 * 90 % 360;
+
+    // "unknown"/"reserved" are as good as untagged
+    let color_value = |key: &str| {
+        stream[key]
+            .as_str()
+            .filter(|v| !v.is_empty() && *v != "unknown" && *v != "reserved")
+            .map(String::from)
+    };
+
+    Ok(VideoMetadata {
+        width,
+        height,
+        rotation,
+        sample_aspect_ratio,
+        display_aspect_ratio,
+        frame_rate,
+        duration: duration.round() as u32,
+        color_transfer: color_value("color_transfer"),
+        color_primaries: color_value("color_primaries"),
+        color_space: color_value("color_space"),
+        field_order: stream["field_order"]
+            .as_str()
+            .filter(|v| !v.is_empty() && *v != "unknown")
+            .map(String::from),
+        interlaced: None,
+        crop: None,
+    })
+}
+
+/// Frames sampled by the idet pass
+const IDET_FRAMES: u32 = 500;
+
+/// Outcome of the interlacing check of a source
+pub struct InterlaceDetection {
+    /// Field order to deinterlace with; `None` for progressive sources
+    pub field_order: Option<FieldOrder>,
+    /// How the decision was reached, for the job log
+    pub summary: String,
+}
+
+/// Decide whether a source is interlaced from its field_order tag and an `idet` pass over a
+/// sample of frames. The tag alone isn't trusted: progressive-segmented (PsF) material is often
+/// tagged interlaced, and some cameras tag interlaced footage as progressive.
+pub async fn detect_interlacing(input: &Path, metadata: &VideoMetadata) -> InterlaceDetection {
+    let tagged = match metadata.field_order.as_deref() {
+        Some("tt") | Some("tb") => Some(FieldOrder::TopFirst),
+        Some("bb") | Some("bt") => Some(FieldOrder::BottomFirst),
+        _ => None,
+    };
+    let tag = metadata.field_order.as_deref().unwrap_or("unset");
+
+    // Skip the intro, which is often a static slate that idet can't classify
+    let start = if metadata.duration > 60 {
+        metadata.duration as f64 * 0.1
+    } else {
+        0.0
+    };
+    let counts = match run_idet(input, start).await {
+        Ok(counts) => Some(counts),
+        Err(e) => {
+            warn!("idet pass failed, relying on the field_order tag: {}", e);
+            None
+        }
+    };
+
+    let field_order = match counts {
+        Some(counts) if counts.determined() > 0 => {
+            let ratio = (counts.tff + counts.bff) as f64 / counts.determined() as f64;
+            // A tagged source only needs some combing to confirm it, an untagged one a majority
+            let interlaced = if tagged.is_some() { ratio >= 0.05 } else { ratio >= 0.5 };
+            interlaced.then(|| {
+                if counts.tff > counts.bff {
+                    FieldOrder::TopFirst
+                } else if counts.bff > counts.tff {
+                    FieldOrder::BottomFirst
+                } else {
+                    tagged.unwrap_or(FieldOrder::TopFirst)
+                }
+            })
+        }
+        _ => tagged,
+    };
+
+    let idet = match counts {
+        Some(counts) => format!(
+            "idet TFF {} / BFF {} / progressive {} / undetermined {}",
+            counts.tff, counts.bff, counts.progressive, counts.undetermined
+        ),
+        None => "idet unavailable".to_string(),
+    };
+    let result = match field_order {
+        Some(order) => format!("interlaced ({}), deinterlacing", order.parity()),
+        None => "progressive".to_string(),
+    };
+
+    InterlaceDetection {
+        field_order,
+        summary: format!("field_order {}, {}: {}", tag, idet, result),
+    }
+}
+
+/// Multi-frame detection counts of ffmpeg's idet filter
+#[derive(Clone, Copy, Debug, Default)]
+struct IdetCounts {
+    tff: u32,
+    bff: u32,
+    progressive: u32,
+    undetermined: u32,
+}
+
+impl IdetCounts {
+    fn determined(&self) -> u32 {
+        self.tff + self.bff + self.progressive
+    }
+}
+
+async fn run_idet(input: &Path, start: f64) -> Result<IdetCounts> {
+    let output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-ss")
+        .arg(format!("{:.3}", start))
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg("0:v:0")
+        .arg("-an")
+        .arg("-sn")
+        .arg("-frames:v")
+        .arg(IDET_FRAMES.to_string())
+        .arg("-vf")
+        .arg("idet")
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await
+        .context("failed to run ffmpeg idet")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg idet failed: {}", stderr);
+    }
+
+    // "[Parsed_idet_0 @ 0x...] Multi frame detection: TFF: 412 BFF: 0 Progressive: 88 Undetermined: 0"
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let line = stderr
+        .lines()
+        .find(|line| line.contains("Multi frame detection:"))
+        .context("idet printed no statistics")?;
+    let count = |label: &str| -> u32 {
+        line.split_once(label)
+            .and_then(|(_, rest)| rest.split_whitespace().next())
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0)
+    };
+
+    Ok(IdetCounts {
+        tff: count("TFF:"),
+        bff: count("BFF:"),
+        progressive: count("Progressive:"),
+        undetermined: count("Undetermined:"),
+    })
+}
+
+/// CPU deinterlacer keeping the source frame rate (50i becomes 25p)
+pub fn deinterlace_filter(order: FieldOrder) -> String {
+    format!("bwdif=mode=send_frame:parity={}:deint=all", order.parity())
+}
+
+/// Frames each cropdetect sample looks at
+const CROPDETECT_FRAMES: u32 = 24;
+
+/// Find black bars burned into the picture: `cropdetect` runs at `samples` positions spread
+/// over the video and the union of the detected rectangles is kept, so a bright frame anywhere
+/// is never cut. Returns `None` when there is nothing worth cropping.
+pub async fn detect_crop(
+    input: &Path,
+    metadata: &VideoMetadata,
+    config: &AutoCropConfig,
+) -> Result<Option<CropRect>> {
+    let (frame_width, frame_height) = metadata.frame_size();
+    let duration = metadata.duration as f64;
+    // cropdetect scales fractional limits to the bit depth, so 10-bit sources work too
+    let mut filter = format!("cropdetect=limit={:.4}:round=2:reset=0", config.limit as f64 / 255.0);
+    if let Some(order) = metadata.interlaced {
+        filter = format!("{},{}", deinterlace_filter(order), filter);
+    }
+
+    let mut union: Option<(u32, u32, u32, u32)> = None;
+    let mut detected = 0;
+    for i in 0..config.samples {
+        let start = duration * (i as f64 + 0.5) / config.samples as f64;
+        // Fully black samples (fades, slates) detect no rectangle and are skipped
+        let Some(rect) = run_cropdetect(input, start, &filter).await? else {
+            continue;
+        };
+        detected += 1;
+        let (x1, y1) = (rect.x, rect.y);
+        let (x2, y2) = (rect.x + rect.width, rect.y + rect.height);
+        union = Some(match union {
+            Some((ux1, uy1, ux2, uy2)) => (ux1.min(x1), uy1.min(y1), ux2.max(x2), uy2.max(y2)),
+            None => (x1, y1, x2, y2),
+        });
+    }
+
+    let Some((x1, y1, x2, y2)) = union else {
+        info!("Crop detection: no picture found in {} samples", config.samples);
+        return Ok(None);
+    };
+
+    // Even offsets and sizes keep 4:2:0 chroma aligned; bars thinner than min_bar are kept
+    let axis = |start: u32, end: u32, size: u32| {
+        let start = start.min(size) & !1;
+        let length = (end.min(size).saturating_sub(start).div_ceil(2) * 2).min(size - start) & !1;
+        if size - length < config.min_bar {
+            (0, size)
+        } else {
+            (start, length)
+        }
+    };
+    let (x, width) = axis(x1, x2, frame_width);
+    let (y, height) = axis(y1, y2, frame_height);
+
+    info!(
+        "Crop detection: {}x{} picture at {},{} in a {}x{} frame ({} of {} samples)",
+        width, height, x, y, frame_width, frame_height, detected, config.samples
+    );
+
+    if width == frame_width && height == frame_height {
+        return Ok(None);
+    }
+    // Mostly dark videos can look like a small picture; that is not worth risking
+    if width * 2 < frame_width || height * 2 < frame_height {
+        warn!("Crop detection found a suspiciously small picture, not cropping");
+        return Ok(None);
+    }
+
+    Ok(Some(CropRect {
+        width,
+        height,
+        x,
+        y,
+    }))
+}
+
+/// Last rectangle cropdetect reports over a short window starting at `start`
+async fn run_cropdetect(input: &Path, start: f64, filter: &str) -> Result<Option<CropRect>> {
+    let output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-ss")
+        .arg(format!("{:.3}", start))
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg("0:v:0")
+        .arg("-an")
+        .arg("-sn")
+        .arg("-frames:v")
+        .arg(CROPDETECT_FRAMES.to_string())
+        .arg("-vf")
+        .arg(filter)
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await
+        .context("failed to run ffmpeg cropdetect")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg cropdetect failed: {}", stderr);
+    }
+
+    // "[Parsed_cropdetect_0 @ 0x...] x1:0 x2:1919 y1:140 y2:939 w:1920 h:800 x:0 y:140 ... crop=1920:800:0:140"
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Ok(stderr
+        .lines()
+        .filter_map(|line| line.split_once("crop="))
+        .filter_map(|(_, rest)| CropRect::parse(rest.split_whitespace().next()?))
+        .next_back())
 }
 
+/// Filters every output of the source starts with: deinterlacing, then the crop
+pub fn source_filter(metadata: &VideoMetadata) -> Option<String> {
+    let filters: Vec<String> = metadata
+        .interlaced
+        .map(deinterlace_filter)
+        .into_iter()
+        .chain(metadata.crop.map(|crop| crop.filter()))
+        .collect();
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
+/// Parse an ffprobe ratio such as "30000/1001" or "16:9"
+fn parse_ratio(value: &str, separator: char) -> Option<f64> {
+    let (num, den) = value.split_once(separator)?;
+    let num: f64 = num.trim().parse().ok()?;
+    let den: f64 = den.trim().parse().ok()?;
+    (num > 0.0 && den > 0.0).then(|| num / den)
+}
+
+#[allow(dead_code)]
 pub async fn get_video_height(input: &PathBuf) -> Result<u32> {
     // Keep for backward compatibility or individual usage
-    let (h, _) = get_video_metadata(input).await?;
-    Ok(h)
+    Ok(get_video_metadata(input).await?.height)
 }
 
+#[allow(dead_code)]
 pub async fn get_video_duration(input: &PathBuf) -> Result<u32> {
     // Keep for backward compatibility or individual usage
-    let (_, d) = get_video_metadata(input).await?;
-    Ok(d)
+    Ok(get_video_metadata(input).await?.duration)
 }
 
 // Get audio stream information from video file using ffprobe
@@ -279,6 +627,7 @@ pub async fn get_chapters(input: &PathBuf) -> Result<Vec<ChapterInfo>> {
                         start_time,
                         end_time,
                         title,
+                        is_draft: false,
                     })
                 })
                 .collect()
@@ -301,13 +650,18 @@ pub fn is_vobsub_subtitle(codec: &str) -> bool {
     matches!(codec.to_lowercase().as_str(), "dvd_subtitle" | "dvdsub")
 }
 
+/// Check if a subtitle codec is PGS (Blu-ray subtitle format)
+pub fn is_pgs_subtitle(codec: &str) -> bool {
+    matches!(codec.to_lowercase().as_str(), "hdmv_pgs_subtitle" | "pgssub")
+}
+
 /// Get the file extension for a subtitle codec
-#[allow(dead_code)]
 pub fn get_subtitle_extension(codec: &str) -> &'static str {
     if is_bitmap_subtitle(codec) {
-        match codec.to_lowercase().as_str() {
-            "hdmv_pgs_subtitle" | "pgssub" => "sup",
-            _ => "sub",
+        if is_pgs_subtitle(codec) {
+            "sup"
+        } else {
+            "sub"
         }
     } else {
         match codec.to_lowercase().as_str() {
@@ -316,7 +670,8 @@ pub fn get_subtitle_extension(codec: &str) -> &'static str {
             "webvtt" | "vtt" => "vtt",
             "mov_text" | "tx3g" => "srt",
             "ttml" | "dfxp" => "ttml",
-            "microdvd" => "sub",
+            // extract_subtitle converts MicroDVD to SRT
+            "microdvd" => "srt",
             _ => "ass",
         }
     }
@@ -427,11 +782,10 @@ pub struct VobSubExtractionResult {
 }
 
 /// Extract VobSub subtitle using mkvextract (produces both .idx and .sub files)
-#[allow(dead_code)]
 pub async fn extract_vobsub_subtitle(
     input: &PathBuf,
     subtitle_index: i32,
-    output_dir: &PathBuf,
+    output_dir: &Path,
     track_idx: usize,
 ) -> Result<VobSubExtractionResult> {
     let idx_filename = format!("track_{}.idx", track_idx);
@@ -540,20 +894,90 @@ pub async fn extract_all_attachments(input: &PathBuf, output_dir: &PathBuf) -> R
     Ok(())
 }
 
-pub fn get_variants_for_height(original_height: u32) -> Vec<VideoVariant> {
-    let all_variants = vec![
-        VideoVariant::new("480p", 480),
-        VideoVariant::new("720p", 720),
-        VideoVariant::new("1080p", 1080),
-        VideoVariant::new("1440p", 1440),
-        VideoVariant::new("2160p", 2160)
-    ];
+/// Built-in ladder used when `video.ladder` is empty: (label, short edge)
+const DEFAULT_LADDER: [(&str, u32); 5] = [
+    ("480p", 480),
+    ("720p", 720),
+    ("1080p", 1080),
+    ("1440p", 1440),
+    ("2160p", 2160),
+];
+
+pub fn get_variants_for_source(metadata: &VideoMetadata, config: &VideoConfig) -> Vec<VideoVariant> {
+    let (display_width, display_height) = metadata.display_size();
+    let short_edge = metadata.short_edge();
+
+    let rungs: Vec<LadderRung> = if config.ladder.is_empty() {
+        DEFAULT_LADDER
+            .iter()
+            .map(|(label, height)| LadderRung {
+                label: label.to_string(),
+                height: *height,
+                bitrate: None,
+                crf: None,
+                maxrate: None,
+                profile: None,
+                level: None,
+            })
+            .collect()
+    } else {
+        config.ladder.clone()
+    };
 
-    // Only include variants at or below the original resolution
-    all_variants
-        .into_iter()
-        .filter(|v| v.height <= original_height)
-        .collect()
+    // Output size at a given short edge, keeping the display aspect ratio
+    let size_for = |edge: u32| {
+        if display_width >= display_height {
+            (even_dimension(edge as f64 * display_width as f64 / display_height as f64), edge)
+        } else {
+            (edge, even_dimension(edge as f64 * display_height as f64 / display_width as f64))
+        }
+    };
+
+    let variant_for = |rung: &LadderRung, label: String, edge: u32| {
+        let (width, height) = size_for(edge);
+        let mut variant = VideoVariant::new(&label, width, height, metadata.frame_rate);
+        if let Some(bitrate) = rung.bitrate {
+            variant.bitrate = bitrate;
+        }
+        variant.maxrate = rung.maxrate.unwrap_or(variant.bitrate * 3 / 2);
+        variant.crf = rung.crf;
+        variant.profile = rung.profile.clone();
+        variant.level = rung.level.clone();
+        variant
+    };
+
+    // Only include variants at or below the original resolution (by short edge)
+    let variants: Vec<VideoVariant> = rungs
+        .iter()
+        .filter(|rung| rung.height <= short_edge)
+        .map(|rung| variant_for(rung, rung.label.clone(), rung.height))
+        .collect();
+
+    if !variants.is_empty() {
+        return variants;
+    }
+
+    // Sources smaller than the lowest rung get a single rendition at their own size,
+    // encoded with the lowest rung's settings
+    match rungs.first() {
+        Some(lowest) => {
+            let edge = even_dimension(short_edge as f64);
+            let mut variant = variant_for(lowest, format!("{}p", edge), edge);
+            if lowest.bitrate.is_some() || lowest.maxrate.is_some() {
+                // Scale configured rates down to the smaller frame
+                let scale = (edge as f64 / lowest.height as f64).powi(2);
+                variant.bitrate = ((variant.bitrate as f64 * scale).round() as u32).max(1);
+                variant.maxrate = ((variant.maxrate as f64 * scale).round() as u32).max(variant.bitrate);
+            }
+            vec![variant]
+        }
+        None => Vec::new(),
+    }
+}
+
+/// Round to the nearest even dimension (required for 4:2:0 chroma subsampling)
+pub fn even_dimension(value: f64) -> u32 {
+    ((value / 2.0).round() as u32 * 2).max(2)
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -577,15 +1001,218 @@ impl EncoderType {
         }
     }
 
-    /// Get the video codec name for this encoder type
-    fn video_codec(&self) -> &'static str {
+    /// Get the FFmpeg encoder name for this encoder type and output codec
+    fn video_codec(&self, codec: VideoCodec) -> &'static str {
+        match (self, codec) {
+            (EncoderType::Nvenc, VideoCodec::H264) => "h264_nvenc",
+            (EncoderType::Nvenc, VideoCodec::Hevc) => "hevc_nvenc",
+            (EncoderType::Nvenc, VideoCodec::Av1) => "av1_nvenc",
+            (EncoderType::Vaapi, VideoCodec::H264) => "h264_vaapi",
+            (EncoderType::Vaapi, VideoCodec::Hevc) => "hevc_vaapi",
+            (EncoderType::Vaapi, VideoCodec::Av1) => "av1_vaapi",
+            (EncoderType::Qsv, VideoCodec::H264) => "h264_qsv",
+            (EncoderType::Qsv, VideoCodec::Hevc) => "hevc_qsv",
+            (EncoderType::Qsv, VideoCodec::Av1) => "av1_qsv",
+            (EncoderType::Cpu, VideoCodec::H264) => "libx264",
+            (EncoderType::Cpu, VideoCodec::Hevc) => "libx265",
+            (EncoderType::Cpu, VideoCodec::Av1) => "libsvtav1",
+        }
+    }
+}
+
+/// Output video codec of a ladder. H.264 is always produced so legacy clients have a fallback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Av1,
+}
+
+impl VideoCodec {
+    /// Determine the codec produced by an FFmpeg encoder name (e.g. "hevc_nvenc", "libsvtav1")
+    pub fn from_encoder(encoder: &str) -> Option<Self> {
+        let encoder = encoder.to_lowercase();
+        if encoder.contains("264") {
+            Some(VideoCodec::H264)
+        } else if encoder.contains("265") || encoder.contains("hevc") {
+            Some(VideoCodec::Hevc)
+        } else if encoder.contains("av1") {
+            Some(VideoCodec::Av1)
+        } else {
+            None
+        }
+    }
+
+    /// Suffix appended to the variant label (and output directory) of this codec's ladder
+    fn label_suffix(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "",
+            VideoCodec::Hevc => "_hevc",
+            VideoCodec::Av1 => "_av1",
+        }
+    }
+
+    /// Bitrate relative to H.264 for comparable quality
+    fn bitrate_factor(&self) -> f64 {
+        match self {
+            VideoCodec::H264 => 1.0,
+            VideoCodec::Hevc => 0.7,
+            VideoCodec::Av1 => 0.6,
+        }
+    }
+
+    /// HEVC and AV1 are only widely playable from fMP4 segments; H.264 follows the
+    /// configured segment format
+    fn uses_fmp4(&self, format: SegmentFormat) -> bool {
+        format == SegmentFormat::Fmp4 || !matches!(self, VideoCodec::H264)
+    }
+
+    /// Sample entry type, used to group renditions of the same codec
+    fn codec_family(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "avc1",
+            VideoCodec::Hevc => "hvc1",
+            VideoCodec::Av1 => "av01",
+        }
+    }
+
+    /// RFC 6381 codec string used when the encoded output can't be probed
+    fn default_codec_string(&self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "avc1.640028",
+            VideoCodec::Hevc => "hvc1.1.6.L120.B0",
+            VideoCodec::Av1 => "av01.0.08M.08",
+        }
+    }
+
+    /// Derive this codec's rung from an H.264 ladder rung
+    fn variant_for(&self, base: &VideoVariant) -> VideoVariant {
+        VideoVariant {
+            label: format!("{}{}", base.label, self.label_suffix()),
+            width: base.width,
+            height: base.height,
+            bitrate: (base.bitrate as f64 * self.bitrate_factor()).round() as u32,
+            maxrate: (base.maxrate as f64 * self.bitrate_factor()).round() as u32,
+            crf: base.crf.map(|crf| self.crf_for(crf)),
+            // Configured profiles and levels are H.264 names
+            profile: base.profile.clone().filter(|_| *self == VideoCodec::H264),
+            level: base.level.clone().filter(|_| *self == VideoCodec::H264),
+        }
+    }
+
+    /// Translate an x264 CRF to this codec's encoder scale for similar quality
+    fn crf_for(&self, crf: u32) -> u32 {
         match self {
-            EncoderType::Nvenc => "h264_nvenc",
-            EncoderType::Vaapi => "h264_vaapi",
-            EncoderType::Qsv => "h264_qsv",
-            EncoderType::Cpu => "libx264",
+            VideoCodec::H264 => crf,
+            VideoCodec::Hevc => (crf + 4).min(51),
+            VideoCodec::Av1 => ((crf as f64 * 1.5).round() as u32).min(63),
+        }
+    }
+}
+
+/// Codecs to encode and the encoder backend for each, H.264 first
+fn output_codecs(config: &VideoConfig) -> Vec<(VideoCodec, EncoderType)> {
+    let mut outputs = vec![(VideoCodec::H264, EncoderType::from_string(&config.encoder))];
+
+    for encoder in &config.codecs {
+        match VideoCodec::from_encoder(encoder) {
+            Some(codec) if !outputs.iter().any(|(c, _)| *c == codec) => {
+                outputs.push((codec, EncoderType::from_string(encoder)));
+            }
+            Some(_) => {}
+            None => warn!("Ignoring unsupported output encoder: {}", encoder),
+        }
+    }
+
+    outputs
+}
+
+/// Build an RFC 6381 codec string (for the master playlist CODECS attribute) from ffprobe output
+fn codec_string(codec_name: &str, profile: &str, level: i64, pix_fmt: &str) -> Option<String> {
+    match codec_name {
+        "h264" => {
+            let profile_idc = match profile {
+                "Constrained Baseline" => "42E0",
+                "Baseline" => "4200",
+                "Main" => "4D40",
+                "High" => "6400",
+                "High 10" => "6E00",
+                _ => return None,
+            };
+            Some(format!("avc1.{}{:02X}", profile_idc, level))
+        }
+        "hevc" => {
+            let (profile_idc, compat) = match profile {
+                "Main" => (1, 6),
+                "Main 10" => (2, 4),
+                _ => return None,
+            };
+            Some(format!("hvc1.{}.{}.L{}.B0", profile_idc, compat, level))
+        }
+        "av1" => {
+            let profile_idc = match profile {
+                "Main" => 0,
+                "High" => 1,
+                "Professional" => 2,
+                _ => return None,
+            };
+            let bit_depth = if pix_fmt.contains("10") { 10 } else { 8 };
+            Some(format!("av01.{}.{:02}M.{:02}", profile_idc, level, bit_depth))
         }
+        _ => None,
+    }
+}
+
+/// Probe an encoded rendition (first segment or fMP4 init segment) for its codec string
+async fn probe_codec_string(media_path: &Path) -> Option<String> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=codec_name,profile,level,pix_fmt")
+        .arg("-of")
+        .arg("json")
+        .arg(media_path)
+        .output()
+        .await
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    let stream = json["streams"].as_array()?.first()?;
+
+    codec_string(
+        stream["codec_name"].as_str()?,
+        stream["profile"].as_str().unwrap_or_default(),
+        stream["level"].as_i64()?,
+        stream["pix_fmt"].as_str().unwrap_or_default(),
+    )
+}
+
+/// Start time in seconds of a media segment; MPEG-TS output doesn't start at zero
+async fn probe_start_time(media_path: &Path) -> Option<f64> {
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=start_time")
+        .arg("-of")
+        .arg("default=noprint_wrappers=1:nokey=1")
+        .arg(media_path)
+        .output()
+        .await
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
     }
+
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
 }
 
 /// Check if an FFmpeg error indicates hardware encoder failure that should fallback to CPU
@@ -643,496 +1270,1437 @@ fn get_language_display_name(lang_code: &str) -> String {
     }
 }
 
-pub async fn encode_to_hls(
-    input: &PathBuf,
-    out_dir: &PathBuf,
-    progress: &ProgressMap,
-    upload_id: &str,
-    semaphore: Arc<Semaphore>,
-    encoder: &str,
-    duration: u32,
-    audio_streams: &[AudioStreamInfo],
-) -> Result<()> {
-    fs::create_dir_all(out_dir).await?;
-
-    // Get original video height to determine appropriate variants
-    let (original_height, _) = get_video_metadata(input).await?;
-    let variants = get_variants_for_height(original_height);
-
-    if variants.is_empty() {
-        anyhow::bail!("No suitable variants for video height {}", original_height);
+/// Record encoding progress for a job, logging (but not propagating) database errors
+async fn report_progress(db_pool: &SqlitePool, upload_id: &str, update: ProgressUpdate) {
+    if let Err(e) = update_job_progress(db_pool, upload_id, &update).await {
+        error!("Failed to record progress for job {}: {}", upload_id, e);
     }
+}
 
-    let encoder_type = EncoderType::from_string(encoder);
-
-    // GOP size - use 48 for 24fps content (2 seconds), adjust for HLS segment alignment
-    let gop = 48;
-
-    let input = Arc::new(input.clone());
-    let out_dir = Arc::new(out_dir.clone());
-    let progress = Arc::new(progress.clone());
-    let upload_id = upload_id.to_string();
-    let audio_streams = Arc::new(audio_streams.to_vec());
-
-    let mut encode_tasks = Vec::new();
-    // Total tasks = video variants + audio streams
-    let total_variants = variants.len() as u32 + audio_streams.len() as u32;
-
-    for (index, variant) in variants.clone().iter().enumerate() {
-        let input = Arc::clone(&input);
-        let out_dir = Arc::clone(&out_dir);
-        let semaphore = Arc::clone(&semaphore);
-        let progress = Arc::clone(&progress);
-        let upload_id = upload_id.clone();
-        let variant = variant.clone();
-        let encoder_type = encoder_type.clone();
+/// Hardware decode setup. Frames stay on the GPU unless `system_frames` is set, in which case
+/// they're decoded to system memory so CPU filters (and ffmpeg's autorotate) can process them.
+fn push_hwaccel_args(cmd: &mut Command, encoder: &EncoderType, system_frames: bool) {
+    match encoder {
+        EncoderType::Nvenc => {
+            cmd.arg("-hwaccel").arg("cuda");
+            if !system_frames {
+                cmd.arg("-hwaccel_output_format").arg("cuda");
+            }
+        }
+        EncoderType::Vaapi => {
+            cmd.arg("-hwaccel").arg("vaapi");
+            if !system_frames {
+                cmd.arg("-hwaccel_output_format").arg("vaapi");
+            }
+            cmd.arg("-vaapi_device").arg("/dev/dri/renderD128");
+        }
+        EncoderType::Qsv => {
+            if system_frames {
+                // Software decode, frames are uploaded after the CPU filters
+                cmd.arg("-init_hw_device")
+                    .arg("qsv=hw")
+                    .arg("-filter_hw_device")
+                    .arg("hw");
+            } else {
+                cmd.arg("-hwaccel")
+                    .arg("qsv")
+                    .arg("-hwaccel_output_format")
+                    .arg("qsv");
+            }
+        }
+        EncoderType::Cpu => {}
+    }
+}
 
-        let task = tokio::task::spawn(async move {
-            let _permit = semaphore.acquire().await.unwrap();
+/// CPU filter chain tone mapping an HDR source to BT.709 SDR: linearize with zscale, map with
+/// Hable, then convert primaries, transfer and matrix to BT.709. The input color properties are
+/// passed explicitly because scaled frames don't always carry them. `None` for SDR sources.
+pub fn tone_map_filter(metadata: &VideoMetadata) -> Option<String> {
+    let hdr = metadata.hdr_format()?;
+    let primaries = metadata.color_primaries.as_deref().unwrap_or("bt2020");
+    let matrix = metadata.color_space.as_deref().unwrap_or("bt2020nc");
+
+    Some(format!(
+        "zscale=tin={}:pin={}:min={}:t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,\
+         tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p",
+        hdr.transfer(),
+        primaries,
+        matrix
+    ))
+}
 
-            let seg_dir = out_dir.join(&variant.label);
-            fs::create_dir_all(&seg_dir).await?;
-            let playlist_path = seg_dir.join("index.m3u8");
-            let segment_pattern = seg_dir.join("segment_%03d.ts");
+/// Whether the encoder's GPU filters can tone map this HDR format themselves (VAAPI's
+/// `tonemap_vaapi` and QSV's `vpp_qsv` only handle HDR10); otherwise the CPU chain is used
+fn hw_tone_mapping(encoder: &EncoderType, hdr: HdrFormat) -> bool {
+    matches!(encoder, EncoderType::Vaapi | EncoderType::Qsv) && hdr == HdrFormat::Pq
+}
 
-            info!(
-                "Encoding variant: {} at {}p with bitrate {}kbps (max: {}kbps)",
-                variant.label,
-                variant.height,
-                variant.bitrate,
-                variant.max_bitrate()
-            );
+/// Filter chain scaling to the rung's exact output size with square pixels, so anamorphic
+/// sources are stretched to their display aspect ratio. `tone_map` is the CPU tone mapping
+/// chain of an HDR source; on GPU frames the hardware tone mapper is used instead. On system
+/// frames `source_filter` runs first; on GPU frames interlaced sources use the GPU deinterlacer
+/// (cropped sources are always decoded to system frames).
+fn video_filter(
+    encoder: &EncoderType,
+    variant: &VideoVariant,
+    system_frames: bool,
+    tone_map: Option<&str>,
+    source_filter: Option<&str>,
+    interlaced: Option<FieldOrder>,
+) -> String {
+    let (width, height) = (variant.width, variant.height);
+    let mut cpu_scale = cpu_scale_filter(variant, source_filter);
+    if let Some(tone_map) = tone_map {
+        cpu_scale.push(',');
+        cpu_scale.push_str(tone_map);
+    }
 
-            // Update progress before starting this variant
-            let current_chunk = (index + 1) as u32;
-            let percentage = (((current_chunk as f32) / (total_variants as f32)) * 100.0) as u32;
-            let (existing_video_name, existing_created_at) = {
-                let progress_map = progress.read().await;
-                progress_map
-                    .get(&upload_id)
-                    .map(|p| (p.video_name.clone(), p.created_at))
-                    .unwrap_or((None, 0))
+    match (encoder, system_frames) {
+        (EncoderType::Cpu, _) | (EncoderType::Nvenc, true) => cpu_scale,
+        (EncoderType::Vaapi, true) => format!("{},format=nv12,hwupload", cpu_scale),
+        (EncoderType::Qsv, true) => format!("{},format=nv12,hwupload=extra_hw_frames=64", cpu_scale),
+        (EncoderType::Nvenc, false) => {
+            let deinterlace = interlaced
+                .map(|order| {
+                    format!("yadif_cuda=mode=send_frame:parity={}:deint=all,", order.parity())
+                })
+                .unwrap_or_default();
+            format!("{}scale_cuda={}:{},setsar=1", deinterlace, width, height)
+        }
+        (EncoderType::Vaapi, false) => {
+            // VAAPI takes the field order from the frames themselves
+            let deinterlace = if interlaced.is_some() {
+                "deinterlace_vaapi=rate=frame,"
+            } else {
+                ""
             };
-            let start_progress = ProgressUpdate {
-                stage: "FFmpeg processing".to_string(),
-                current_chunk,
-                total_chunks: total_variants,
-                percentage,
-                details: Some(format!(
-                    "Encoding variant: {} ({}p)",
-                    variant.label, variant.height
-                )),
-                status: "processing".to_string(),
-                result: None,
-                error: None,
-                video_name: existing_video_name.clone(),
-                created_at: existing_created_at,
+            let tone_map = if tone_map.is_some() {
+                ",tonemap_vaapi=format=nv12:p=bt709:t=bt709:m=bt709"
+            } else {
+                ""
             };
-            progress
-                .write()
-                .await
-                .insert(upload_id.clone(), start_progress);
+            format!(
+                "{}scale_vaapi=w={}:h={}{},setsar=1",
+                deinterlace, width, height, tone_map
+            )
+        }
+        (EncoderType::Qsv, false) => {
+            let deinterlace = if interlaced.is_some() { ":deinterlace=2" } else { "" };
+            let tone_map = if tone_map.is_some() { ":format=nv12:tonemap=1" } else { "" };
+            format!(
+                "vpp_qsv=w={}:h={}{}{},setsar=1",
+                width, height, deinterlace, tone_map
+            )
+        }
+    }
+}
 
-            // Try encoding with configured encoder, fallback to CPU if hardware fails
-            let mut current_encoder = encoder_type.clone();
-            let mut last_error: Option<String> = None;
+/// CPU scale to the rung's size with square pixels, after the deinterlace and crop filters
+fn cpu_scale_filter(variant: &VideoVariant, source_filter: Option<&str>) -> String {
+    let scale = format!("scale={}:{},setsar=1", variant.width, variant.height);
+    match source_filter {
+        Some(source_filter) => format!("{},{}", source_filter, scale),
+        None => scale,
+    }
+}
 
-            loop {
-                // Clean up any partial output from previous attempt
-                if last_error.is_some() {
-                    let _ = fs::remove_dir_all(&seg_dir).await;
-                    fs::create_dir_all(&seg_dir).await?;
-                }
+/// Filter chain of the 10-bit HDR rendition: scaled on the CPU without touching the transfer
+/// function, then handed to the encoder as 10-bit frames
+fn hdr_video_filter(
+    encoder: &EncoderType,
+    variant: &VideoVariant,
+    source_filter: Option<&str>,
+) -> String {
+    let cpu_scale = cpu_scale_filter(variant, source_filter);
+
+    match encoder {
+        EncoderType::Cpu => format!("{},format=yuv420p10le", cpu_scale),
+        EncoderType::Nvenc => format!("{},format=p010le", cpu_scale),
+        EncoderType::Vaapi => format!("{},format=p010,hwupload", cpu_scale),
+        EncoderType::Qsv => format!("{},format=p010,hwupload=extra_hw_frames=64", cpu_scale),
+    }
+}
 
-                let mut cmd = Command::new("ffmpeg");
-                cmd.stdout(std::process::Stdio::null())
-                    .stderr(std::process::Stdio::piped())
-                    .arg("-loglevel")
-                    .arg("error")
-                    .arg("-y");
-
-                // Hardware acceleration setup
-                match current_encoder {
-                    EncoderType::Nvenc => {
-                        cmd.arg("-hwaccel")
-                            .arg("cuda")
-                            .arg("-hwaccel_output_format")
-                            .arg("cuda");
+/// HLS muxer options writing `index.m3u8` plus MPEG-TS or fMP4 (CMAF) segments into `dir`.
+/// fMP4 output gets an `init.mp4` referenced from the playlist via EXT-X-MAP.
+fn push_hls_output_args(cmd: &mut Command, dir: &Path, fmp4: bool, segment_duration: u32) {
+    cmd.arg("-hls_time")
+        .arg(segment_duration.to_string())
+        .arg("-hls_list_size")
+        .arg("0")
+        .arg("-hls_playlist_type")
+        .arg("vod");
+
+    if fmp4 {
+        cmd.arg("-hls_segment_type")
+            .arg("fmp4")
+            .arg("-hls_fmp4_init_filename")
+            .arg("init.mp4")
+            .arg("-hls_flags")
+            .arg("independent_segments")
+            .arg("-hls_segment_filename")
+            .arg(dir.join("segment_%03d.m4s"));
+    } else {
+        cmd.arg("-hls_segment_type")
+            .arg("mpegts")
+            .arg("-hls_segment_filename")
+            .arg(dir.join("segment_%03d.ts"));
+    }
+
+    cmd.arg("-start_number").arg("0").arg(dir.join("index.m3u8"));
+}
+
+/// Encoder specific rate control, profile and preset options for one output codec.
+/// `preset` replaces the built-in preset of the encoder; the rung may override profile and level.
+fn push_encoder_settings(
+    cmd: &mut Command,
+    encoder: &EncoderType,
+    codec: VideoCodec,
+    variant: &VideoVariant,
+    preset: Option<&str>,
+    ten_bit: bool,
+) {
+    let constant_quality = variant.crf.is_some();
+    let vaapi_rc_mode = if constant_quality { "CQP" } else { "VBR" };
+
+    match codec {
+        VideoCodec::H264 => {
+            // Encoder specific settings - using "high" profile for better compression
+            // while maintaining browser compatibility (all modern browsers support High profile)
+            let profile = variant.profile.as_deref().unwrap_or("high");
+            match encoder {
+                EncoderType::Nvenc => {
+                    cmd.arg("-preset")
+                        .arg(preset.unwrap_or("p3"))
+                        .arg("-profile:v")
+                        .arg(profile)
+                        .arg("-level:v")
+                        .arg(variant.level.as_deref().unwrap_or("4.1"))
+                        .arg("-rc:v")
+                        .arg("vbr")
+                        .arg("-rc-lookahead")
+                        .arg("20")
+                        .arg("-bf")
+                        .arg("3")
+                        .arg("-spatial-aq")
+                        .arg("1")
+                        .arg("-temporal-aq")
+                        .arg("1")
+                        .arg("-aq-strength")
+                        .arg("8");
+                }
+                EncoderType::Vaapi => {
+                    // h264_vaapi only knows the constrained flavour of baseline
+                    let profile = if profile == "baseline" {
+                        "constrained_baseline"
+                    } else {
+                        profile
+                    };
+                    cmd.arg("-compression_level")
+                        .arg(preset.unwrap_or("20"))
+                        .arg("-rc_mode")
+                        .arg(vaapi_rc_mode)
+                        .arg("-profile:v")
+                        .arg(profile);
+                    if let Some(level) = &variant.level {
+                        cmd.arg("-level:v").arg(level);
                     }
-                    EncoderType::Vaapi => {
-                        cmd.arg("-hwaccel")
-                            .arg("vaapi")
-                            .arg("-hwaccel_output_format")
-                            .arg("vaapi")
-                            .arg("-vaapi_device")
-                            .arg("/dev/dri/renderD128");
+                }
+                EncoderType::Qsv => {
+                    cmd.arg("-preset")
+                        .arg(preset.unwrap_or("faster"))
+                        .arg("-profile:v")
+                        .arg(profile)
+                        .arg("-look_ahead")
+                        .arg("1")
+                        .arg("-look_ahead_depth")
+                        .arg("40");
+                    if let Some(level) = &variant.level {
+                        cmd.arg("-level:v").arg(level);
                     }
-                    EncoderType::Qsv => {
-                        cmd.arg("-hwaccel")
-                            .arg("qsv")
-                            .arg("-hwaccel_output_format")
-                            .arg("qsv");
+                }
+                EncoderType::Cpu => {
+                    cmd.arg("-preset")
+                        .arg(preset.unwrap_or("veryfast"))
+                        .arg("-profile:v")
+                        .arg(profile)
+                        .arg("-level:v")
+                        .arg(variant.level.as_deref().unwrap_or("4.0"));
+                }
+            }
+        }
+        VideoCodec::Hevc => {
+            // Main profile 8-bit (Main 10 for the HDR rendition), tagged hvc1 so Apple devices
+            // accept the fMP4 stream
+            let profile = if ten_bit { "main10" } else { "main" };
+            match encoder {
+                EncoderType::Nvenc => {
+                    cmd.arg("-preset")
+                        .arg(preset.unwrap_or("p4"))
+                        .arg("-profile:v")
+                        .arg(profile)
+                        .arg("-rc:v")
+                        .arg("vbr")
+                        .arg("-rc-lookahead")
+                        .arg("20")
+                        .arg("-spatial-aq")
+                        .arg("1")
+                        .arg("-temporal-aq")
+                        .arg("1");
+                }
+                EncoderType::Vaapi => {
+                    if let Some(preset) = preset {
+                        cmd.arg("-compression_level").arg(preset);
                     }
-                    EncoderType::Cpu => {}
+                    cmd.arg("-rc_mode")
+                        .arg(vaapi_rc_mode)
+                        .arg("-profile:v")
+                        .arg(profile);
+                }
+                EncoderType::Qsv => {
+                    cmd.arg("-preset")
+                        .arg(preset.unwrap_or("faster"))
+                        .arg("-profile:v")
+                        .arg(profile)
+                        .arg("-look_ahead_depth")
+                        .arg("40");
+                }
+                EncoderType::Cpu => {
+                    // x265 ignores -sc_threshold, so disable scene cuts here to keep GOPs aligned
+                    cmd.arg("-preset")
+                        .arg(preset.unwrap_or("fast"))
+                        .arg("-profile:v")
+                        .arg(profile)
+                        .arg("-x265-params")
+                        .arg("log-level=error:scenecut=0:open-gop=0");
+                }
+            }
+            cmd.arg("-tag:v").arg("hvc1");
+        }
+        VideoCodec::Av1 => match encoder {
+            EncoderType::Nvenc => {
+                cmd.arg("-preset")
+                    .arg(preset.unwrap_or("p4"))
+                    .arg("-rc:v")
+                    .arg("vbr")
+                    .arg("-rc-lookahead")
+                    .arg("20")
+                    .arg("-spatial-aq")
+                    .arg("1");
+            }
+            EncoderType::Vaapi => {
+                if let Some(preset) = preset {
+                    cmd.arg("-compression_level").arg(preset);
                 }
+                cmd.arg("-rc_mode").arg(vaapi_rc_mode);
+            }
+            EncoderType::Qsv => {
+                cmd.arg("-preset").arg(preset.unwrap_or("faster"));
+            }
+            EncoderType::Cpu => {
+                // Preset 8 keeps SVT-AV1 near real time; scene detection off for aligned GOPs
+                cmd.arg("-preset")
+                    .arg(preset.unwrap_or("8"))
+                    .arg("-svtav1-params")
+                    .arg("scd=0");
+            }
+        },
+    }
+}
 
-                cmd.arg("-i").arg(input.as_ref());
+/// Constant quality options for a CRF rung (the value is already on the encoder's scale)
+fn push_quality_args(cmd: &mut Command, encoder: &EncoderType, crf: u32) {
+    match encoder {
+        EncoderType::Nvenc => {
+            cmd.arg("-cq").arg(crf.to_string()).arg("-b:v").arg("0");
+        }
+        EncoderType::Vaapi => {
+            cmd.arg("-qp").arg(crf.to_string());
+        }
+        EncoderType::Qsv => {
+            cmd.arg("-global_quality").arg(crf.to_string());
+        }
+        EncoderType::Cpu => {
+            cmd.arg("-crf").arg(crf.to_string());
+        }
+    }
+}
 
-                // Explicitly map only the first video stream to ignore data streams (timecode, etc.)
-                cmd.arg("-map").arg("0:v:0");
+/// Directory suffix of an audio track; always includes the track index to ensure uniqueness
+/// (handles multiple tracks with the same language)
+fn audio_label(audio: &AudioStreamInfo, idx: usize) -> String {
+    if let Some(lang) = &audio.language {
+        format!("{}_{}", lang, idx)
+    } else {
+        format!("track_{}", idx)
+    }
+}
 
-                // Scaling filter
-                let scale_filter = match current_encoder {
-                    EncoderType::Nvenc => format!("scale_cuda=-2:{}", variant.height),
-                    EncoderType::Vaapi => format!("scale_vaapi=-2:{}", variant.height),
-                    EncoderType::Qsv => format!("vpp_qsv=w=-2:h={}", variant.height),
-                    EncoderType::Cpu => format!("scale=-2:{}", variant.height),
-                };
+/// Directory of a text subtitle track's WebVTT rendition, unique like the audio directories
+fn subtitle_label(subtitle: &SubtitleStreamInfo, idx: usize) -> String {
+    match &subtitle.language {
+        Some(lang) => format!("subs_{}_{}", lang, idx),
+        None => format!("subs_track_{}", idx),
+    }
+}
 
-                cmd.arg("-c:v").arg(current_encoder.video_codec());
-
-                // Encoder specific settings - using "high" profile for better compression
-                // while maintaining browser compatibility (all modern browsers support High profile)
-                match current_encoder {
-                    EncoderType::Nvenc => {
-                        cmd.arg("-preset")
-                            .arg("p3")
-                            .arg("-profile:v")
-                            .arg("high")  // High profile for better quality
-                            .arg("-level:v")
-                            .arg("4.1")
-                            .arg("-rc:v")
-                            .arg("vbr")
-                            .arg("-rc-lookahead")
-                            .arg("20")
-                            .arg("-bf")
-                            .arg("3")
-                            .arg("-spatial-aq")
-                            .arg("1")
-                            .arg("-temporal-aq")
-                            .arg("1")
-                            .arg("-aq-strength")
-                            .arg("8");
-                    }
-                    EncoderType::Vaapi => {
-                        cmd.arg("-compression_level")
-                            .arg("20")
-                            .arg("-rc_mode")
-                            .arg("VBR")
-                            .arg("-profile:v")
-                            .arg("high");  // High profile for better quality
-                    }
-                    EncoderType::Qsv => {
-                        cmd.arg("-preset")
-                            .arg("faster")
-                            .arg("-profile:v")
-                            .arg("high")  // High profile for better quality
-                            .arg("-look_ahead")
-                            .arg("1")
-                            .arg("-look_ahead_depth")
-                            .arg("40");
-                    }
-                    EncoderType::Cpu => {
-                        cmd.arg("-preset")
-                            .arg("veryfast")
-                            .arg("-profile:v")
-                            .arg("high")  // High profile for better quality
-                            .arg("-level:v")
-                            .arg("4.0");
-                    }
-                }
+/// Renditions produced for an audio track: stereo AAC (mono stays mono), plus a 5.1 rendition
+/// when the source has at least six channels and surround output is enabled
+pub fn audio_renditions(audio: &AudioStreamInfo, idx: usize, config: &AudioConfig) -> Vec<AudioRendition> {
+    let label = audio_label(audio, idx);
+    let source_channels = audio.channels.unwrap_or(2);
+
+    let mut renditions = vec![AudioRendition {
+        name: format!("audio_{}", label),
+        codec: "aac".to_string(),
+        channels: source_channels.clamp(1, 2),
+        bit_rate: config.bitrate as i64 * 1000,
+        playlist_key: None,
+        uri: None,
+    }];
+
+    if config.surround && source_channels >= 6 {
+        renditions.push(AudioRendition {
+            name: format!("audio_{}_51", label),
+            codec: match config.surround_codec {
+                SurroundCodec::Aac => "aac",
+                SurroundCodec::Eac3 => "eac3",
+            }
+            .to_string(),
+            channels: 6,
+            bit_rate: config.surround_bitrate as i64 * 1000,
+            playlist_key: None,
+            uri: None,
+        });
+    }
 
-                cmd.arg("-b:v")
-                    .arg(variant.bitrate_str())
-                    .arg("-maxrate")
-                    .arg(format!("{}k", variant.max_bitrate()))
-                    .arg("-bufsize")
-                    .arg(format!("{}k", variant.bufsize()))
-                    .arg("-vf")
-                    .arg(&scale_filter);
-
-                // Force yuv420p pixel format for web compatibility
-                // This ensures browsers can play the video (no 10-bit, no yuv444p)
-                match current_encoder {
-                    EncoderType::Nvenc => {
-                        // For NVENC, specify format after hwdownload
-                        cmd.arg("-pix_fmt").arg("yuv420p");
-                    }
-                    EncoderType::Vaapi | EncoderType::Qsv => {
-                        // Hardware encoders: force 8-bit 4:2:0
-                        cmd.arg("-pix_fmt").arg("yuv420p");
-                    }
-                    EncoderType::Cpu => {
-                        // CPU encoder: explicitly set yuv420p
-                        cmd.arg("-pix_fmt").arg("yuv420p");
-                    }
-                }
+    renditions
+}
 
-                cmd.arg("-g")
-                    .arg(gop.to_string())
-                    .arg("-keyint_min")
-                    .arg(gop.to_string())
-                    .arg("-sc_threshold")
-                    .arg("0")
-                    .arg("-force_key_frames")
-                    .arg("expr:gte(t,n_forced*4)");
-
-                // Don't include audio in video variants - audio is encoded separately
-                cmd.arg("-an");
-
-                // Don't include subtitles in HLS output - they are extracted separately
-                cmd.arg("-sn");
-
-                cmd.arg("-hls_time")
-                    .arg("4")
-                    .arg("-hls_list_size")
-                    .arg("0")
-                    .arg("-hls_playlist_type")
-                    .arg("vod")
-                    .arg("-hls_segment_type")
-                    .arg("mpegts")
-                    .arg("-start_number")
-                    .arg("0")
-                    .arg("-hls_segment_filename")
-                    .arg(&segment_pattern)
-                    .arg(&playlist_path);
-
-                // Log the FFmpeg command for debugging
-                info!(
-                    "Running FFmpeg command for variant {}: ffmpeg {}",
-                    variant.label,
-                    cmd.as_std()
-                        .get_args()
-                        .map(|arg| format!("{:?}", arg))
-                        .collect::<Vec<_>>()
-                        .join(" ")
-                );
+/// First `loudnorm` pass: measure the EBU R128 loudness of an audio track
+async fn measure_loudness(
+    input: &Path,
+    audio_idx: usize,
+    config: &LoudnormConfig,
+) -> Result<AudioLoudness> {
+    let output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:a:{}", audio_idx))
+        .arg("-vn")
+        .arg("-sn")
+        .arg("-af")
+        .arg(format!(
+            "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+            config.target, config.true_peak, config.loudness_range
+        ))
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .output()
+        .await
+        .context("failed to run ffmpeg loudness measurement")?;
 
-                let output = cmd.output().await.context("failed to run ffmpeg")?;
-
-                if output.status.success() {
-                    // Verify that segments were actually created
-                    if let Ok(mut entries) = fs::read_dir(&seg_dir).await {
-                        let mut segment_count = 0;
-                        let mut total_size = 0u64;
-                        while let Ok(Some(entry)) = entries.next_entry().await {
-                            if let Ok(metadata) = entry.metadata().await {
-                                let path = entry.path();
-                                if path.extension().and_then(|s| s.to_str()) == Some("ts") {
-                                    segment_count += 1;
-                                    total_size += metadata.len();
-                                    if metadata.len() == 0 {
-                                        error!("Empty segment file detected: {:?}", path);
-                                    }
-                                }
-                            }
-                        }
-                        info!(
-                            "Variant {} created {} segments, total size: {} bytes",
-                            variant.label, segment_count, total_size
-                        );
-                        if segment_count == 0 {
-                            error!("No segments were created for variant {}", variant.label);
-                        }
-                    }
-                    break;
-                }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        anyhow::bail!("ffmpeg loudness measurement failed: {}", stderr);
+    }
 
-                let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    let stats = loudnorm_stats(&stderr)?;
+    Ok(AudioLoudness {
+        input_i: loudnorm_value(&stats, "input_i")?,
+        input_tp: loudnorm_value(&stats, "input_tp")?,
+        input_lra: loudnorm_value(&stats, "input_lra")?,
+        input_thresh: loudnorm_value(&stats, "input_thresh")?,
+        target_offset: loudnorm_value(&stats, "target_offset")?,
+        target_i: config.target,
+        target_tp: config.true_peak,
+        target_lra: config.loudness_range,
+        output_i: None,
+        output_tp: None,
+        output_lra: None,
+    })
+}
 
-                // Check if this is a hardware encoder error and we can fallback
-                if current_encoder != EncoderType::Cpu && is_hardware_encoder_error(&stderr) {
-                    warn!(
-                        "Hardware encoder {:?} failed for variant {}, falling back to CPU: {}",
-                        current_encoder,
-                        variant.label,
-                        stderr.lines().next().unwrap_or(&stderr)
-                    );
-                    current_encoder = EncoderType::Cpu;
-                    last_error = Some(stderr);
-
-                    // Update progress to indicate fallback
-                    let fallback_progress = ProgressUpdate {
-                        stage: "FFmpeg processing".to_string(),
-                        current_chunk,
-                        total_chunks: total_variants,
-                        percentage,
-                        details: Some(format!(
-                            "Encoding variant: {} ({}p) - using CPU fallback",
-                            variant.label, variant.height
-                        )),
-                        status: "processing".to_string(),
-                        result: None,
-                        error: None,
-                        video_name: existing_video_name.clone(),
-                        created_at: existing_created_at,
-                    };
-                    progress
-                        .write()
-                        .await
-                        .insert(upload_id.clone(), fallback_progress);
+/// Statistics `loudnorm` prints as a JSON object at the end of the log with `print_format=json`
+fn loudnorm_stats(stderr: &str) -> Result<serde_json::Value> {
+    let json = stderr
+        .rfind('{')
+        .and_then(|start| stderr[start..].find('}').map(|end| &stderr[start..=start + end]))
+        .context("loudnorm did not print its statistics")?;
+    Ok(serde_json::from_str(json)?)
+}
+
+/// One value of the `loudnorm` statistics. Values are printed as strings; silence measures
+/// as "-inf" and fails to parse.
+fn loudnorm_value(stats: &serde_json::Value, key: &str) -> Result<f64> {
+    stats[key]
+        .as_str()
+        .and_then(|v| v.trim().parse::<f64>().ok())
+        .filter(|v| v.is_finite())
+        .with_context(|| format!("loudnorm statistics have no usable {}", key))
+}
+
+/// Second `loudnorm` pass: normalize to the targets using the first pass' measurement, and
+/// print the loudness of the result
+fn loudnorm_filter(loudness: &AudioLoudness) -> String {
+    format!(
+        "loudnorm=I={}:TP={}:LRA={}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true:print_format=json",
+        loudness.target_i,
+        loudness.target_tp,
+        loudness.target_lra,
+        loudness.input_i,
+        loudness.input_tp,
+        loudness.input_lra,
+        loudness.input_thresh,
+        loudness.target_offset
+    )
+}
+
+/// Convert the text subtitle stream `idx` to WebVTT and segment it for HLS inside `dir`
+async fn encode_subtitle_rendition(
+    input: &Path,
+    idx: usize,
+    dir: &Path,
+    duration: u32,
+    segment_duration: u32,
+    start_time: f64,
+) -> Result<usize> {
+    fs::create_dir_all(dir).await?;
+    let full_path = dir.join("full.vtt");
+
+    let output = Command::new("ffmpeg")
+        .kill_on_drop(true)
+        .arg("-v")
+        .arg("error")
+        .arg("-y")
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:s:{}", idx))
+        .arg("-c:s")
+        .arg("webvtt")
+        .arg(&full_path)
+        .output()
+        .await
+        .context("failed to run ffmpeg for WebVTT conversion")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("ffmpeg WebVTT conversion failed: {}", stderr);
+    }
+
+    let cues = write_webvtt_playlist(&full_path, dir, duration, segment_duration, start_time).await?;
+    let _ = fs::remove_file(&full_path).await;
+
+    Ok(cues)
+}
+
+/// An EXT-X-MEDIA entry of the subtitle group
+struct SubtitleMedia {
+    language: String,
+    name: String,
+    is_default: bool,
+    is_forced: bool,
+    label: String,
+}
+
+/// An EXT-X-MEDIA entry of the master playlist
+struct AudioMedia {
+    language: String,
+    name: String,
+    is_default: bool,
+    rendition: AudioRendition,
+}
+
+/// Inputs shared by the video encode tasks of one upload
+struct RenditionContext {
+    input: PathBuf,
+    out_dir: PathBuf,
+    db_pool: SqlitePool,
+    upload_id: String,
+    segment_format: SegmentFormat,
+    segment_duration: u32,
+    gop: u32,
+    presets: HashMap<String, String>,
+    rotated: bool,
+    /// HDR format of the source; SDR renditions of an HDR source are tone mapped
+    hdr: Option<HdrFormat>,
+    /// CPU tone mapping chain for HDR sources
+    tone_map: Option<String>,
+    /// Field order of interlaced sources, which are deinterlaced before scaling
+    interlaced: Option<FieldOrder>,
+    /// Black bars cropped before scaling
+    crop: Option<CropRect>,
+    /// Deinterlace and crop chain applied to system frames
+    source_filter: Option<String>,
+    total_tasks: u32,
+}
+
+impl RenditionContext {
+    /// Whether frames have to be decoded to system memory for CPU filters: rotated and cropped
+    /// sources, and HDR sources whose tone mapping the encoder's GPU filters can't do
+    fn system_frames(&self, encoder: &EncoderType, rendition: &Rendition) -> bool {
+        self.rotated
+            || self.crop.is_some()
+            || rendition.hdr.is_some()
+            || self.hdr.is_some_and(|hdr| !hw_tone_mapping(encoder, hdr))
+    }
+
+    /// Filter chain of one rendition for the encoder
+    fn filter(&self, encoder: &EncoderType, rendition: &Rendition) -> String {
+        if rendition.hdr.is_some() {
+            return hdr_video_filter(encoder, &rendition.variant, self.source_filter.as_deref());
+        }
+        video_filter(
+            encoder,
+            &rendition.variant,
+            self.system_frames(encoder, rendition),
+            self.tone_map.as_deref(),
+            self.source_filter.as_deref(),
+            self.interlaced,
+        )
+    }
+
+    /// Report encoding progress as of the task at `index`
+    async fn report(&self, index: usize, details: String) {
+        let current_chunk = (index + 1) as u32;
+        let percentage = (((current_chunk as f32) / (self.total_tasks as f32)) * 100.0) as u32;
+        let update = ProgressUpdate {
+            stage: "FFmpeg processing".to_string(),
+            current_chunk,
+            total_chunks: self.total_tasks,
+            percentage,
+            details: Some(details),
+            status: "processing".to_string(),
+            result: None,
+            error: None,
+            video_name: None,
+        };
+        report_progress(&self.db_pool, &self.upload_id, update).await;
+    }
+}
+
+/// One video output: a ladder rung in one codec, plus its position in the progress count
+#[derive(Clone)]
+struct Rendition {
+    index: usize,
+    codec: VideoCodec,
+    variant: VideoVariant,
+    /// Set for the 10-bit HDR rendition, which keeps the source's transfer function
+    hdr: Option<HdrFormat>,
+}
+
+/// Encoder, rate control and keyframe options of one video output
+fn push_video_output_args(
+    cmd: &mut Command,
+    ctx: &RenditionContext,
+    encoder: &EncoderType,
+    rendition: &Rendition,
+) {
+    let Rendition {
+        codec,
+        variant,
+        hdr,
+        ..
+    } = rendition;
+    let codec = *codec;
+    let encoder_name = encoder.video_codec(codec);
+    cmd.arg("-c:v").arg(encoder_name);
+    push_encoder_settings(
+        cmd,
+        encoder,
+        codec,
+        variant,
+        ctx.presets.get(encoder_name).map(String::as_str),
+        hdr.is_some(),
+    );
+
+    match variant.crf {
+        Some(crf) => push_quality_args(cmd, encoder, crf),
+        None => {
+            cmd.arg("-b:v").arg(variant.bitrate_str());
+        }
+    }
+
+    // SVT-AV1 only accepts a bitrate cap in CRF mode, so leave it uncapped VBR;
+    // VAAPI's constant-QP mode has no cap at all
+    let uncapped = match encoder {
+        EncoderType::Cpu => codec == VideoCodec::Av1 && variant.crf.is_none(),
+        EncoderType::Vaapi => variant.crf.is_some(),
+        _ => false,
+    };
+    if !uncapped {
+        cmd.arg("-maxrate")
+            .arg(format!("{}k", variant.max_bitrate()))
+            .arg("-bufsize")
+            .arg(format!("{}k", variant.bufsize()));
+    }
 
-                    continue;
+    match hdr {
+        Some(hdr) => {
+            // 10-bit output tagged with the source's HDR transfer; hardware frames are already
+            // 10-bit surfaces
+            match encoder {
+                EncoderType::Cpu => {
+                    cmd.arg("-pix_fmt").arg("yuv420p10le");
+                }
+                EncoderType::Nvenc => {
+                    cmd.arg("-pix_fmt").arg("p010le");
                 }
+                EncoderType::Vaapi | EncoderType::Qsv => {}
+            }
+            cmd.arg("-color_primaries")
+                .arg("bt2020")
+                .arg("-color_trc")
+                .arg(hdr.transfer())
+                .arg("-colorspace")
+                .arg("bt2020nc");
+        }
+        None => {
+            // Force yuv420p pixel format for web compatibility
+            // This ensures browsers can play the video (no 10-bit, no yuv444p)
+            cmd.arg("-pix_fmt").arg("yuv420p");
+
+            // Tone-mapped output must not inherit the source's HDR color tags
+            if ctx.hdr.is_some() {
+                cmd.arg("-color_primaries")
+                    .arg("bt709")
+                    .arg("-color_trc")
+                    .arg("bt709")
+                    .arg("-colorspace")
+                    .arg("bt709");
+            }
+        }
+    }
 
-                // Non-recoverable error - log full stderr for debugging
-                error!("FFmpeg failed for variant {}: {}", variant.label, stderr);
-                error!("Full FFmpeg stderr output:\n{}", stderr);
-                anyhow::bail!(
-                    "ffmpeg exited with status: {} for variant {}. Error: {}",
-                    output.status,
-                    variant.label,
-                    stderr.lines().take(5).collect::<Vec<_>>().join("; ")
-                );
+    cmd.arg("-g")
+        .arg(ctx.gop.to_string())
+        .arg("-keyint_min")
+        .arg(ctx.gop.to_string())
+        .arg("-sc_threshold")
+        .arg("0")
+        .arg("-force_key_frames")
+        .arg(format!("expr:gte(t,n_forced*{})", ctx.segment_duration));
+}
+
+/// Log the FFmpeg command for debugging
+fn log_command(what: &str, cmd: &Command) {
+    info!(
+        "Running FFmpeg command for {}: ffmpeg {}",
+        what,
+        cmd.as_std()
+            .get_args()
+            .map(|arg| format!("{:?}", arg))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+}
+
+/// Verify that segments were actually created for a rendition
+async fn check_segments(seg_dir: &Path, label: &str) {
+    let Ok(mut entries) = fs::read_dir(seg_dir).await else {
+        return;
+    };
+
+    let mut segment_count = 0;
+    let mut total_size = 0u64;
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Ok(metadata) = entry.metadata().await {
+            let path = entry.path();
+            if matches!(
+                path.extension().and_then(|s| s.to_str()),
+                Some("ts") | Some("m4s")
+            ) {
+                segment_count += 1;
+                total_size += metadata.len();
+                if metadata.len() == 0 {
+                    error!("Empty segment file detected: {:?}", path);
+                }
             }
+        }
+    }
+    info!(
+        "Variant {} created {} segments, total size: {} bytes",
+        label, segment_count, total_size
+    );
+    if segment_count == 0 {
+        error!("No segments were created for variant {}", label);
+    }
+}
 
-            // Update progress for this variant
-            let current_chunk = (index + 1) as u32;
-            let percentage = (((current_chunk as f32) / (total_variants as f32)) * 100.0) as u32;
-            let updated_progress = ProgressUpdate {
-                stage: "FFmpeg processing".to_string(),
-                current_chunk,
-                total_chunks: total_variants,
-                percentage,
-                details: Some(format!("Encoded variant: {}", variant.label)),
-                status: "processing".to_string(),
-                result: None,
-                error: None,
-                video_name: existing_video_name,
-                created_at: existing_created_at,
-            };
-            progress
-                .write()
-                .await
-                .insert(upload_id.clone(), updated_progress);
+/// Encode one rendition in its own ffmpeg process, falling back to CPU if the hardware encoder fails
+async fn encode_rendition(
+    ctx: &RenditionContext,
+    rendition: &Rendition,
+    encoder_type: &EncoderType,
+) -> Result<()> {
+    let Rendition {
+        index,
+        codec,
+        variant,
+        ..
+    } = rendition;
+
+    let seg_dir = ctx.out_dir.join(&variant.label);
+    fs::create_dir_all(&seg_dir).await?;
+
+    info!(
+        "Encoding {:?} variant: {} at {}x{} with bitrate {}kbps (max: {}kbps)",
+        codec,
+        variant.label,
+        variant.width,
+        variant.height,
+        variant.bitrate,
+        variant.max_bitrate()
+    );
+
+    // Update progress before starting this variant
+    ctx.report(
+        *index,
+        format!(
+            "Encoding variant: {} ({}x{})",
+            variant.label, variant.width, variant.height
+        ),
+    )
+    .await;
+
+    // Try encoding with configured encoder, fallback to CPU if hardware fails
+    let mut current_encoder = encoder_type.clone();
+    let mut last_error: Option<String> = None;
+
+    loop {
+        // Clean up any partial output from previous attempt
+        if last_error.is_some() {
+            let _ = fs::remove_dir_all(&seg_dir).await;
+            fs::create_dir_all(&seg_dir).await?;
+        }
+
+        let mut cmd = Command::new("ffmpeg");
+        cmd.kill_on_drop(true)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .arg("-loglevel")
+            .arg("error")
+            .arg("-y");
+
+        // Hardware acceleration setup
+        push_hwaccel_args(
+            &mut cmd,
+            &current_encoder,
+            ctx.system_frames(&current_encoder, rendition),
+        );
+
+        cmd.arg("-i").arg(&ctx.input);
+
+        // Explicitly map only the first video stream to ignore data streams (timecode, etc.)
+        cmd.arg("-map").arg("0:v:0");
+
+        // Scaling (and tone mapping) filter
+        cmd.arg("-vf").arg(ctx.filter(&current_encoder, rendition));
+
+        push_video_output_args(&mut cmd, ctx, &current_encoder, rendition);
+
+        // Don't include audio in video variants - audio is encoded separately
+        cmd.arg("-an");
+
+        // Don't include subtitles in HLS output - they are extracted separately
+        cmd.arg("-sn");
+
+        push_hls_output_args(
+            &mut cmd,
+            &seg_dir,
+            codec.uses_fmp4(ctx.segment_format),
+            ctx.segment_duration,
+        );
+
+        log_command(&format!("variant {}", variant.label), &cmd);
+
+        let output = cmd.output().await.context("failed to run ffmpeg")?;
+
+        if output.status.success() {
+            check_segments(&seg_dir, &variant.label).await;
+            break;
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+        // Check if this is a hardware encoder error and we can fallback
+        if current_encoder != EncoderType::Cpu && is_hardware_encoder_error(&stderr) {
+            warn!(
+                "Hardware encoder {:?} failed for variant {}, falling back to CPU: {}",
+                current_encoder,
+                variant.label,
+                stderr.lines().next().unwrap_or(&stderr)
+            );
+            current_encoder = EncoderType::Cpu;
+            last_error = Some(stderr);
+
+            // Update progress to indicate fallback
+            ctx.report(
+                *index,
+                format!(
+                    "Encoding variant: {} ({}x{}) - using CPU fallback",
+                    variant.label, variant.width, variant.height
+                ),
+            )
+            .await;
+
+            continue;
+        }
+
+        // Non-recoverable error - log full stderr for debugging
+        error!("FFmpeg failed for variant {}: {}", variant.label, stderr);
+        error!("Full FFmpeg stderr output:\n{}", stderr);
+        anyhow::bail!(
+            "ffmpeg exited with status: {} for variant {}. Error: {}",
+            output.status,
+            variant.label,
+            stderr.lines().take(5).collect::<Vec<_>>().join("; ")
+        );
+    }
+
+    // Update progress for this variant
+    ctx.report(*index, format!("Encoded variant: {}", variant.label))
+        .await;
+
+    Ok(())
+}
+
+/// Encode several renditions from a single decode of the source. The decoded frames are fanned
+/// out with `split` (on the GPU for the hwaccel paths), each branch is scaled and encoded to its
+/// own playlist. If the hardware encoder fails, the renditions are re-encoded one by one so each
+/// rung gets its own CPU fallback.
+async fn encode_renditions_single_decode(
+    ctx: &RenditionContext,
+    renditions: &[Rendition],
+    encoder_type: &EncoderType,
+) -> Result<()> {
+    let Some(last) = renditions.last() else {
+        return Ok(());
+    };
+    let labels = renditions
+        .iter()
+        .map(|r| r.variant.label.as_str())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    info!(
+        "Encoding {} variants with {:?} from a single decode: {}",
+        renditions.len(),
+        encoder_type,
+        labels
+    );
+    ctx.report(
+        renditions[0].index,
+        format!("Encoding variants in one pass: {}", labels),
+    )
+    .await;
+
+    let mut cmd = Command::new("ffmpeg");
+    cmd.kill_on_drop(true)
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::piped())
+        .arg("-loglevel")
+        .arg("error")
+        .arg("-y");
+
+    // The HDR rendition is never part of a single-decode group, so any member decides
+    push_hwaccel_args(
+        &mut cmd,
+        encoder_type,
+        ctx.system_frames(encoder_type, &renditions[0]),
+    );
+
+    cmd.arg("-i").arg(&ctx.input);
+
+    // [0:v:0]split=N[s0][s1]...;[s0]scale...[v0];[s1]scale...[v1]
+    let mut graph = format!("[0:v:0]split={}", renditions.len());
+    for i in 0..renditions.len() {
+        graph.push_str(&format!("[s{}]", i));
+    }
+    for (i, rendition) in renditions.iter().enumerate() {
+        graph.push_str(&format!(
+            ";[s{}]{}[v{}]",
+            i,
+            ctx.filter(encoder_type, rendition),
+            i
+        ));
+    }
+    cmd.arg("-filter_complex").arg(&graph);
+
+    for (i, rendition) in renditions.iter().enumerate() {
+        let seg_dir = ctx.out_dir.join(&rendition.variant.label);
+        fs::create_dir_all(&seg_dir).await?;
+
+        cmd.arg("-map").arg(format!("[v{}]", i));
+        push_video_output_args(&mut cmd, ctx, encoder_type, rendition);
+        push_hls_output_args(
+            &mut cmd,
+            &seg_dir,
+            rendition.codec.uses_fmp4(ctx.segment_format),
+            ctx.segment_duration,
+        );
+    }
+
+    log_command(&format!("variants {}", labels), &cmd);
+
+    let output = cmd.output().await.context("failed to run ffmpeg")?;
+
+    if output.status.success() {
+        for rendition in renditions {
+            check_segments(&ctx.out_dir.join(&rendition.variant.label), &rendition.variant.label)
+                .await;
+        }
+        ctx.report(last.index, format!("Encoded variants: {}", labels))
+            .await;
+        return Ok(());
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+
+    if *encoder_type != EncoderType::Cpu && is_hardware_encoder_error(&stderr) {
+        warn!(
+            "Hardware encoder {:?} failed for single-decode variants {}, encoding them one by one: {}",
+            encoder_type,
+            labels,
+            stderr.lines().next().unwrap_or(&stderr)
+        );
+
+        for rendition in renditions {
+            // Drop partial output of the failed run
+            let _ = fs::remove_dir_all(ctx.out_dir.join(&rendition.variant.label)).await;
+            encode_rendition(ctx, rendition, encoder_type).await?;
+        }
+        return Ok(());
+    }
+
+    error!("FFmpeg failed for variants {}: {}", labels, stderr);
+    error!("Full FFmpeg stderr output:\n{}", stderr);
+    anyhow::bail!(
+        "ffmpeg exited with status: {} for variants {}. Error: {}",
+        output.status,
+        labels,
+        stderr.lines().take(5).collect::<Vec<_>>().join("; ")
+    );
+}
+
+/// Moments stitched into the hover preview
+const PREVIEW_CLIPS: u32 = 5;
+/// Length of each preview moment in seconds
+const PREVIEW_CLIP_SECONDS: f64 = 1.5;
+/// Frame rate and widths of the preview; the WebP stays small enough for gallery grids
+const PREVIEW_FPS: u32 = 12;
+const PREVIEW_WEBP_WIDTH: u32 = 320;
+const PREVIEW_MP4_WIDTH: u32 = 480;
+
+/// (start, length) in seconds of the evenly spaced preview moments, skipping the very start and
+/// end. Videos too short for separate moments are previewed from the start.
+fn preview_moments(duration: u32) -> Vec<(f64, f64)> {
+    let duration = duration as f64;
+    let total = PREVIEW_CLIPS as f64 * PREVIEW_CLIP_SECONDS;
+    if duration < total * 2.0 {
+        return vec![(0.0, duration.clamp(1.0, total))];
+    }
+
+    (1..=PREVIEW_CLIPS)
+        .map(|i| {
+            let center = duration * i as f64 / (PREVIEW_CLIPS + 1) as f64;
+            (center - PREVIEW_CLIP_SECONDS / 2.0, PREVIEW_CLIP_SECONDS)
+        })
+        .collect()
+}
+
+/// Size of the MP4 preview: `PREVIEW_MP4_WIDTH` wide at the source's display aspect ratio, so
+/// anamorphic and rotated sources keep their shape once the pixels are made square
+fn preview_size(metadata: &VideoMetadata) -> (u32, u32) {
+    let (display_width, display_height) = metadata.display_size();
+    let height = PREVIEW_MP4_WIDTH as f64 * display_height as f64 / display_width.max(1) as f64;
+    (PREVIEW_MP4_WIDTH, even_dimension(height))
+}
+
+/// Filter graph concatenating one input per moment and splitting it into the WebP and MP4
+/// outputs (`[webp]` and `[mp4]`). `source_filter` is prepended to each input's chain.
+fn preview_filter(
+    moments: usize,
+    (width, height): (u32, u32),
+    source_filter: &str,
+    tone_map: &str,
+) -> String {
+    let mut graph = String::new();
+    for i in 0..moments {
+        graph.push_str(&format!(
+            "[{}:v:0]{}fps={},scale={}:{}{},setsar=1,format=yuv420p[m{}];",
+            i, source_filter, PREVIEW_FPS, width, height, tone_map, i
+        ));
+    }
+    for i in 0..moments {
+        graph.push_str(&format!("[m{}]", i));
+    }
+    graph.push_str(&format!(
+        "concat=n={}:v=1:a=0,split=2[mp4][full];[full]scale={}:-2[webp]",
+        moments, PREVIEW_WEBP_WIDTH
+    ));
+    graph
+}
 
-            Ok::<_, anyhow::Error>(())
+#[allow(clippy::too_many_arguments)]
+pub async fn encode_to_hls(
+    input: &Path,
+    out_dir: &PathBuf,
+    db_pool: &SqlitePool,
+    upload_id: &str,
+    semaphore: Arc<Semaphore>,
+    config: &VideoConfig,
+    variants: &[VideoVariant],
+    metadata: &VideoMetadata,
+    audio_streams: &[AudioStreamInfo],
+    subtitle_streams: &[SubtitleStreamInfo],
+    storyboard: &StoryboardLayout,
+    normalize_audio: bool,
+    cancel: &CancellationToken,
+) -> Result<Vec<Option<AudioLoudness>>> {
+    fs::create_dir_all(out_dir).await?;
+
+    if variants.is_empty() {
+        anyhow::bail!("No suitable variants for this video");
+    }
+
+    let outputs = output_codecs(config);
+    let segment_format = config.segment_format;
+
+    let duration = metadata.duration;
+    let frame_rate = metadata.frame_rate;
+    // Keyframe interval at the source frame rate; segment boundaries are forced on top of it
+    let gop = ((frame_rate * config.gop_seconds as f64).round() as u32).max(1);
+    // Rotated sources are decoded to system memory so ffmpeg's autorotate can transpose them
+    let rotated = metadata.rotation != 0;
+    let hdr = metadata.hdr_format();
+
+    let input = Arc::new(input.to_path_buf());
+    let out_dir = Arc::new(out_dir.clone());
+    let db_pool = db_pool.clone();
+    let upload_id = upload_id.to_string();
+    let audio_streams = Arc::new(audio_streams.to_vec());
+
+    // One rendition per ladder rung per output codec
+    let mut renditions: Vec<(VideoCodec, EncoderType, VideoVariant, Option<HdrFormat>)> = outputs
+        .iter()
+        .flat_map(|(codec, encoder_type)| {
+            variants
+                .iter()
+                .map(|variant| (*codec, encoder_type.clone(), codec.variant_for(variant), None))
+        })
+        .collect();
+
+    // HDR sources can keep a 10-bit HEVC rendition of the top rung, encoded with the configured
+    // HEVC encoder (libx265 otherwise)
+    if let Some(hdr) = hdr
+        && config.hdr_rendition
+        && let Some(top) = variants.last()
+    {
+        let encoder_type = outputs
+            .iter()
+            .find(|(codec, _)| *codec == VideoCodec::Hevc)
+            .map(|(_, encoder_type)| encoder_type.clone())
+            .unwrap_or(EncoderType::Cpu);
+        let base = VideoCodec::Hevc.variant_for(top);
+        // 10-bit HDR needs roughly a quarter more bits than 8-bit SDR at the same quality
+        let variant = VideoVariant {
+            label: format!("{}_hdr", top.label),
+            bitrate: (base.bitrate as f64 * 1.25).round() as u32,
+            maxrate: (base.maxrate as f64 * 1.25).round() as u32,
+            ..base
+        };
+        renditions.push((VideoCodec::Hevc, encoder_type, variant, Some(hdr)));
+    }
+
+    let mut encode_tasks = Vec::new();
+    // Total tasks = video renditions + audio renditions
+    let audio_rendition_count: usize = audio_streams
+        .iter()
+        .enumerate()
+        .map(|(idx, audio)| audio_renditions(audio, idx, &config.audio).len())
+        .sum();
+    let total_variants = (renditions.len() + audio_rendition_count) as u32;
+
+    let context = Arc::new(RenditionContext {
+        input: input.as_ref().clone(),
+        out_dir: out_dir.as_ref().clone(),
+        db_pool: db_pool.clone(),
+        upload_id: upload_id.clone(),
+        segment_format,
+        segment_duration: config.segment_duration,
+        gop,
+        presets: config.presets.clone(),
+        rotated,
+        hdr,
+        tone_map: tone_map_filter(metadata),
+        interlaced: metadata.interlaced,
+        crop: metadata.crop,
+        source_filter: source_filter(metadata),
+        total_tasks: total_variants,
+    });
+
+    let jobs = renditions
+        .iter()
+        .cloned()
+        .enumerate()
+        .map(|(index, (codec, encoder_type, variant, hdr))| {
+            (
+                Rendition {
+                    index,
+                    codec,
+                    variant,
+                    hdr,
+                },
+                encoder_type,
+            )
         });
 
-        encode_tasks.push(task);
+    if config.single_decode {
+        // One ffmpeg process per encoder backend; with a single backend every rung of every
+        // codec shares one decode of the source. The HDR rendition needs untouched 10-bit
+        // frames, so it is always encoded on its own.
+        let mut groups: Vec<(EncoderType, Vec<Rendition>)> = Vec::new();
+        for (rendition, encoder_type) in jobs {
+            if rendition.hdr.is_some() {
+                groups.push((encoder_type, vec![rendition]));
+                continue;
+            }
+            match groups
+                .iter_mut()
+                .find(|(e, group)| *e == encoder_type && group[0].hdr.is_none())
+            {
+                Some((_, group)) => group.push(rendition),
+                None => groups.push((encoder_type, vec![rendition])),
+            }
+        }
+
+        for (encoder_type, group) in groups {
+            let context = Arc::clone(&context);
+            let semaphore = Arc::clone(&semaphore);
+
+            let task = tokio::task::spawn(async move {
+                let _permit = semaphore.acquire().await.unwrap();
+                encode_renditions_single_decode(&context, &group, &encoder_type).await
+            });
+
+            encode_tasks.push(task);
+        }
+    } else {
+        for (rendition, encoder_type) in jobs {
+            let context = Arc::clone(&context);
+            let semaphore = Arc::clone(&semaphore);
+
+            let task = tokio::task::spawn(async move {
+                let _permit = semaphore.acquire().await.unwrap();
+                encode_rendition(&context, &rendition, &encoder_type).await
+            });
+
+            encode_tasks.push(task);
+        }
     }
 
-    // Encode each audio stream as a separate HLS audio playlist
-    let num_video_variants = variants.len();
+    // Encode each audio track as one HLS audio playlist per rendition. A track's renditions are
+    // encoded by one task so the loudness measurement is shared between them.
+    let mut audio_tasks = Vec::new();
+    let mut audio_task_index = renditions.len();
     for (audio_idx, audio_stream) in audio_streams.iter().enumerate() {
+        let track_renditions = audio_renditions(audio_stream, audio_idx, &config.audio);
+        let first_task_index = audio_task_index;
+        audio_task_index += track_renditions.len();
+
         let input = Arc::clone(&input);
         let out_dir = Arc::clone(&out_dir);
         let semaphore = Arc::clone(&semaphore);
-        let progress = Arc::clone(&progress);
+        let db_pool = db_pool.clone();
         let upload_id = upload_id.clone();
         let audio_stream = audio_stream.clone();
+        let loudnorm = normalize_audio.then(|| config.audio.loudnorm.clone());
+        let sample_rate = config.audio.sample_rate;
+        let segment_duration = config.segment_duration;
 
         let task = tokio::task::spawn(async move {
-            let _permit = semaphore.acquire().await.unwrap();
-
-            // Create audio directory with language/index identifier
-            // Always include track index to ensure uniqueness (handles multiple tracks with same language)
-            let audio_label = if let Some(lang) = &audio_stream.language {
-                format!("{}_{}", lang, audio_idx)
-            } else {
-                format!("track_{}", audio_idx)
+            // First loudnorm pass: measure the track once for all of its renditions
+            let mut loudness = match &loudnorm {
+                Some(loudnorm) => {
+                    let _permit = semaphore.acquire().await.unwrap();
+                    match measure_loudness(&input, audio_idx, loudnorm).await {
+                        Ok(loudness) => {
+                            info!(
+                                "Audio track {} loudness: {:.1} LUFS, {:.1} dBTP, LRA {:.1} LU (target {:.1} LUFS)",
+                                audio_idx,
+                                loudness.input_i,
+                                loudness.input_tp,
+                                loudness.input_lra,
+                                loudness.target_i
+                            );
+                            Some(loudness)
+                        }
+                        Err(e) => {
+                            warn!(
+                                "Loudness measurement failed for audio track {}, encoding without normalization: {}",
+                                audio_idx, e
+                            );
+                            None
+                        }
+                    }
+                }
+                None => None,
             };
-            let audio_dir = out_dir.join(format!("audio_{}", audio_label));
-            fs::create_dir_all(&audio_dir).await?;
-            let playlist_path = audio_dir.join("index.m3u8");
-            let segment_pattern = audio_dir.join("segment_%03d.ts");
-
-            info!(
-                "Encoding audio track {}: {} (codec: {}, channels: {:?})",
-                audio_idx, audio_label, audio_stream.codec_name, audio_stream.channels
-            );
 
-            // Update progress
-            let current_chunk = (num_video_variants + audio_idx + 1) as u32;
-            let percentage = ((current_chunk as f32 / total_variants as f32) * 100.0) as u32;
-            let (existing_video_name, existing_created_at) = {
-                let progress_map = progress.read().await;
-                progress_map
-                    .get(&upload_id)
-                    .map(|p| (p.video_name.clone(), p.created_at))
-                    .unwrap_or((None, 0))
-            };
-            let audio_progress = ProgressUpdate {
-                stage: "FFmpeg processing".to_string(),
-                current_chunk,
-                total_chunks: total_variants,
-                percentage,
-                details: Some(format!("Encoding audio track: {}", audio_label)),
-                status: "processing".to_string(),
-                result: None,
-                error: None,
-                video_name: existing_video_name.clone(),
-                created_at: existing_created_at,
-            };
-            progress
-                .write()
-                .await
-                .insert(upload_id.clone(), audio_progress);
-
-            // Encode audio to HLS
-            let mut cmd = Command::new("ffmpeg");
-            cmd.stdout(std::process::Stdio::null())
-                .stderr(std::process::Stdio::piped())
-                .arg("-loglevel")
-                .arg("error")
-                .arg("-y")
-                .arg("-i")
-                .arg(input.as_ref())
-                .arg("-map")
-                .arg(format!("0:a:{}", audio_idx))
-                .arg("-vn")
-                .arg("-c:a")
-                .arg("aac")
-                .arg("-b:a")
-                .arg("128k")
-                .arg("-ac")
-                .arg(if audio_stream.channels.unwrap_or(2) <= 2 {
-                    audio_stream.channels.unwrap_or(2).to_string()
-                } else {
-                    "2".to_string()
-                })
-                .arg("-hls_time")
-                .arg("4")
-                .arg("-hls_list_size")
-                .arg("0")
-                .arg("-hls_playlist_type")
-                .arg("vod")
-                .arg("-hls_segment_type")
-                .arg("mpegts")
-                .arg("-start_number")
-                .arg("0")
-                .arg("-hls_segment_filename")
-                .arg(&segment_pattern)
-                .arg(&playlist_path);
-
-            let output = cmd
-                .output()
-                .await
-                .context("failed to run ffmpeg for audio")?;
+            for (offset, rendition) in track_renditions.iter().enumerate() {
+                let _permit = semaphore.acquire().await.unwrap();
 
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                error!(
-                    "FFmpeg audio encoding failed for track {}: {}",
-                    audio_idx, stderr
-                );
-                anyhow::bail!(
-                    "ffmpeg audio encoding failed for track {}: {}",
+                let audio_dir = out_dir.join(&rendition.name);
+                fs::create_dir_all(&audio_dir).await?;
+
+                info!(
+                    "Encoding audio track {}: {} (source codec: {}, channels: {:?}) as {} {}ch",
                     audio_idx,
-                    stderr
+                    rendition.name,
+                    audio_stream.codec_name,
+                    audio_stream.channels,
+                    rendition.codec,
+                    rendition.channels
                 );
-            }
 
-            info!("Audio track {} encoded successfully", audio_label);
+                // Update progress
+                let current_chunk = (first_task_index + offset + 1) as u32;
+                let percentage = ((current_chunk as f32 / total_variants as f32) * 100.0) as u32;
+                let audio_progress = ProgressUpdate {
+                    stage: "FFmpeg processing".to_string(),
+                    current_chunk,
+                    total_chunks: total_variants,
+                    percentage,
+                    details: Some(format!("Encoding audio track: {}", rendition.name)),
+                    status: "processing".to_string(),
+                    result: None,
+                    error: None,
+                    video_name: None,
+                };
+                report_progress(&db_pool, &upload_id, audio_progress).await;
 
-            Ok::<_, anyhow::Error>(())
+                // Encode audio to HLS. loudnorm prints its output statistics at the info level.
+                let mut cmd = Command::new("ffmpeg");
+                cmd.kill_on_drop(true)
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::piped())
+                    .arg("-hide_banner")
+                    .arg("-nostats")
+                    .arg("-loglevel")
+                    .arg(if loudness.is_some() { "info" } else { "error" })
+                    .arg("-y")
+                    .arg("-i")
+                    .arg(input.as_ref())
+                    .arg("-map")
+                    .arg(format!("0:a:{}", audio_idx))
+                    .arg("-vn");
+                // Second loudnorm pass: linear normalization with the measured values
+                if let Some(loudness) = &loudness {
+                    cmd.arg("-af").arg(loudnorm_filter(loudness));
+                }
+                cmd.arg("-c:a")
+                    .arg(&rendition.codec)
+                    .arg("-b:a")
+                    .arg(rendition.bit_rate.to_string())
+                    .arg("-ac")
+                    .arg(rendition.channels.to_string());
+                // loudnorm resamples to 192 kHz internally, so normalized output always sets a rate
+                match (sample_rate, &loudness) {
+                    (Some(sample_rate), _) => {
+                        cmd.arg("-ar").arg(sample_rate.to_string());
+                    }
+                    (None, Some(_)) => {
+                        cmd.arg("-ar").arg("48000");
+                    }
+                    (None, None) => {}
+                }
+                push_hls_output_args(
+                    &mut cmd,
+                    &audio_dir,
+                    segment_format == SegmentFormat::Fmp4,
+                    segment_duration,
+                );
+
+                let output = cmd
+                    .output()
+                    .await
+                    .context("failed to run ffmpeg for audio")?;
+
+                if !output.status.success() {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    error!(
+                        "FFmpeg audio encoding failed for {}: {}",
+                        rendition.name, stderr
+                    );
+                    anyhow::bail!(
+                        "ffmpeg audio encoding failed for {}: {}",
+                        rendition.name,
+                        stderr
+                    );
+                }
+
+                info!("Audio rendition {} encoded successfully", rendition.name);
+
+                // Keep what the second pass achieved on the main rendition
+                if offset == 0
+                    && let Some(loudness) = loudness.as_mut()
+                {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    let output_stats = loudnorm_stats(&stderr).and_then(|stats| {
+                        Ok((
+                            loudnorm_value(&stats, "output_i")?,
+                            loudnorm_value(&stats, "output_tp")?,
+                            loudnorm_value(&stats, "output_lra")?,
+                        ))
+                    });
+                    match output_stats {
+                        Ok((output_i, output_tp, output_lra)) => {
+                            info!(
+                                "Audio track {} normalized to {:.1} LUFS, {:.1} dBTP, LRA {:.1} LU",
+                                audio_idx, output_i, output_tp, output_lra
+                            );
+                            loudness.output_i = Some(output_i);
+                            loudness.output_tp = Some(output_tp);
+                            loudness.output_lra = Some(output_lra);
+                        }
+                        Err(e) => {
+                            warn!("No output loudness for audio track {}: {}", audio_idx, e);
+                        }
+                    }
+                }
+            }
+
+            Ok::<_, anyhow::Error>(loudness)
         });
 
-        encode_tasks.push(task);
+        audio_tasks.push(task);
     }
 
+    // Thumbnails of HDR sources are tone mapped like the SDR renditions
+    let thumbnail_tone_map = context
+        .tone_map
+        .as_ref()
+        .map(|tone_map| format!(",{}", tone_map))
+        .unwrap_or_default();
+    // ...and deinterlaced and cropped like every rendition
+    let still_source = context
+        .source_filter
+        .as_ref()
+        .map(|source_filter| format!("{},", source_filter))
+        .unwrap_or_default();
+
     // Generate thumbnail (single frame at 10% of video)
     let input_thumbnail = Arc::clone(&input);
+    let thumbnail_filter = format!("{}scale=480:-1{}", still_source, thumbnail_tone_map);
     let out_dir_thumbnail = Arc::clone(&out_dir);
     let thumbnail_task = tokio::task::spawn(async move {
         let thumbnail_path = out_dir_thumbnail.join("thumbnail.jpg");
@@ -1141,6 +2709,7 @@ pub async fn encode_to_hls(
         let seek_time = (duration as f64 * 0.1).max(1.0);
 
         let thumbnail_output = Command::new("ffmpeg")
+            .kill_on_drop(true)
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::piped())
             .arg("-loglevel")
@@ -1153,7 +2722,7 @@ pub async fn encode_to_hls(
             .arg("-map")
             .arg("0:v:0")
             .arg("-vf")
-            .arg("scale=480:-1")
+            .arg(&thumbnail_filter)
             .arg("-frames:v")
             .arg("1")
             .arg("-q:v")
@@ -1173,23 +2742,121 @@ pub async fn encode_to_hls(
 
     encode_tasks.push(thumbnail_task);
 
-    // Generate sprites (preview thumbnails grid)
-    let input_thumb = Arc::clone(&input);
-    let out_dir_thumb = Arc::clone(&out_dir);
-    let thumb_task = tokio::task::spawn(async move {
-        let sprite_path = out_dir_thumb.join("sprites.jpg");
-        info!("Generating thumbnail sprite: {:?}", sprite_path);
+    // Poster set (several widths as JPEG, WebP and AVIF) of the same frame
+    let input_posters = Arc::clone(&input);
+    let posters_tone_map = thumbnail_tone_map.clone();
+    let posters_source = context.source_filter.clone();
+    let posters_dir = out_dir.join(POSTERS_DIR);
+    let (display_width, _) = metadata.display_size();
+    let posters_task = tokio::task::spawn(async move {
+        let seek_time = (duration as f64 * 0.1).max(1.0);
+        info!("Generating posters: {:?}", posters_dir);
+
+        // Like the thumbnail, posters are not worth failing the encode over
+        if let Err(e) = generate_posters(
+            input_posters.as_ref(),
+            seek_time,
+            Some(display_width),
+            posters_source.as_deref(),
+            &posters_tone_map,
+            &posters_dir,
+        )
+        .await
+        {
+            error!("Poster generation failed: {}", e);
+        }
 
-        let target_frames = 100.0;
-        let fps = if duration > 0 {
-            (target_frames / duration as f64).max(0.01)
-        } else {
-            1.0
-        };
+        Ok::<_, anyhow::Error>(())
+    });
 
-        let vf_filter = format!("fps={:.4},scale=160:-1,tile=10x10", fps);
+    encode_tasks.push(posters_task);
+
+    // Generate the hover preview: a short, silent loop of evenly spaced moments
+    let input_preview = Arc::clone(&input);
+    let preview_moments = preview_moments(duration);
+    let preview_filter = preview_filter(
+        preview_moments.len(),
+        preview_size(metadata),
+        &still_source,
+        &thumbnail_tone_map,
+    );
+    let out_dir_preview = Arc::clone(&out_dir);
+    let preview_task = tokio::task::spawn(async move {
+        let webp_path = out_dir_preview.join("preview.webp");
+        let mp4_path = out_dir_preview.join("preview.mp4");
+        info!("Generating preview: {:?}, {:?}", webp_path, mp4_path);
+
+        let mut cmd = Command::new("ffmpeg");
+        cmd.kill_on_drop(true)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .arg("-loglevel")
+            .arg("error")
+            .arg("-y");
+        // Input seeking keeps each moment cheap, even deep into long videos
+        for (start, length) in &preview_moments {
+            cmd.arg("-ss")
+                .arg(format!("{:.3}", start))
+                .arg("-t")
+                .arg(format!("{:.3}", length))
+                .arg("-i")
+                .arg(input_preview.as_ref());
+        }
+        cmd.arg("-filter_complex")
+            .arg(&preview_filter)
+            .arg("-map")
+            .arg("[webp]")
+            .arg("-c:v")
+            .arg("libwebp")
+            .arg("-loop")
+            .arg("0")
+            .arg("-quality")
+            .arg("60")
+            .arg("-an")
+            .arg(&webp_path)
+            .arg("-map")
+            .arg("[mp4]")
+            .arg("-c:v")
+            .arg("libx264")
+            .arg("-preset")
+            .arg("veryfast")
+            .arg("-crf")
+            .arg("28")
+            .arg("-an")
+            .arg("-movflags")
+            .arg("+faststart")
+            .arg(&mp4_path);
+
+        let preview_output = cmd.output().await.context("failed to generate preview")?;
+
+        if !preview_output.status.success() {
+            let stderr = String::from_utf8_lossy(&preview_output.stderr);
+            error!("Preview generation failed: {}", stderr);
+            // A half-written pair is worse than none
+            let _ = fs::remove_file(&webp_path).await;
+            let _ = fs::remove_file(&mp4_path).await;
+        }
+
+        Ok::<_, anyhow::Error>(())
+    });
+
+    encode_tasks.push(preview_task);
+
+    // Generate the storyboard sprite sheets; the thumbnails track is written once they exist
+    let input_thumb = Arc::clone(&input);
+    let sprite_filter = format!(
+        "{}{}",
+        still_source,
+        sprite_filter(storyboard, &thumbnail_tone_map)
+    );
+    let storyboard_dir = out_dir.join(STORYBOARD_DIR);
+    let thumb_task = tokio::task::spawn(async move {
+        fs::create_dir_all(&storyboard_dir).await?;
+        let sprite_pattern = storyboard_dir.join("sprite_%03d.jpg");
+        info!("Generating storyboard sprites: {:?}", sprite_pattern);
 
         let thumb_output = Command::new("ffmpeg")
+            .kill_on_drop(true)
             .stdout(std::process::Stdio::null())
             .stderr(std::process::Stdio::piped())
             .arg("-loglevel")
@@ -1200,19 +2867,19 @@ pub async fn encode_to_hls(
             .arg("-map")
             .arg("0:v:0")
             .arg("-vf")
-            .arg(&vf_filter)
-            .arg("-frames:v")
-            .arg("1")
+            .arg(&sprite_filter)
+            .arg("-start_number")
+            .arg("0")
             .arg("-q:v")
             .arg("5")
-            .arg(&sprite_path)
+            .arg(&sprite_pattern)
             .output()
             .await
-            .context("failed to generate thumbnail sprite")?;
+            .context("failed to generate storyboard sprites")?;
 
         if !thumb_output.status.success() {
             let stderr = String::from_utf8_lossy(&thumb_output.stderr);
-            error!("Thumbnail sprite generation failed: {}", stderr);
+            error!("Storyboard sprite generation failed: {}", stderr);
         }
 
         Ok::<_, anyhow::Error>(())
@@ -1220,89 +2887,520 @@ pub async fn encode_to_hls(
 
     encode_tasks.push(thumb_task);
 
-    // Wait for all encoding and thumbnail tasks to complete
-    let results: Result<Vec<_>, _> = try_join_all(
-        encode_tasks
+    // Wait for all encoding and thumbnail tasks to complete. The handles are only borrowed so
+    // they can still be aborted and awaited if the job is cancelled.
+    let all_tasks = try_join(
+        try_join_all(
+            encode_tasks
+                .iter_mut()
+                .map(|handle| async move { handle.await.context("task panicked")? }),
+        ),
+        try_join_all(
+            audio_tasks
+                .iter_mut()
+                .map(|handle| async move { handle.await.context("task panicked")? }),
+        ),
+    );
+
+    let results = tokio::select! {
+        results = all_tasks => Some(results),
+        _ = cancel.cancelled() => None,
+    };
+
+    let Some(results) = results else {
+        // Aborting a task drops its pending `output()` future, and kill_on_drop kills the ffmpeg
+        // child. Awaiting the aborted tasks makes sure that has happened before the caller
+        // cleans up the output directory. Finished handles were already consumed above.
+        let pending: Vec<_> = encode_tasks
             .into_iter()
-            .map(|handle| async move { handle.await.context("task panicked")? }),
-    )
-    .await;
+            .filter(|t| !t.is_finished())
+            .map(|t| {
+                t.abort();
+                async move {
+                    let _ = t.await;
+                }
+            })
+            .collect();
+        let pending_audio: Vec<_> = audio_tasks
+            .into_iter()
+            .filter(|t| !t.is_finished())
+            .map(|t| {
+                t.abort();
+                async move {
+                    let _ = t.await;
+                }
+            })
+            .collect();
+        let stopped = pending.len() + pending_audio.len();
+        join(join_all(pending), join_all(pending_audio)).await;
+        info!("Encoding cancelled for job {}, stopped {} ffmpeg tasks", upload_id, stopped);
+        anyhow::bail!("Encoding cancelled");
+    };
 
-    results?;
+    let (_, loudness) = results?;
 
-    // Create master playlist with audio track support
-    let master_playlist_path = out_dir.join("index.m3u8");
-    let mut master_content = String::from("#EXTM3U\n#EXT-X-VERSION:3\n\n");
+    // Text subtitles become segmented WebVTT renditions. Cue times are mapped onto the video
+    // timeline through X-TIMESTAMP-MAP, so MPEG-TS output needs the first segment's start time.
+    let mut subtitle_media = Vec::new();
+    let text_subtitles: Vec<(usize, &SubtitleStreamInfo)> = subtitle_streams
+        .iter()
+        .enumerate()
+        .filter(|(_, sub)| !is_bitmap_subtitle(&sub.codec_name))
+        .collect();
+    let start_time = match renditions.first() {
+        Some((codec, _, variant, _)) if !text_subtitles.is_empty() && !codec.uses_fmp4(segment_format) => {
+            probe_start_time(&out_dir.join(&variant.label).join("segment_000.ts"))
+                .await
+                .unwrap_or(0.0)
+        }
+        _ => 0.0,
+    };
+
+    for (idx, sub) in text_subtitles {
+        let label = subtitle_label(sub, idx);
+        let sub_dir = out_dir.join(&label);
+        let result = tokio::select! {
+            result = encode_subtitle_rendition(
+                &input,
+                idx,
+                &sub_dir,
+                duration,
+                config.segment_duration,
+                start_time,
+            ) => result,
+            _ = cancel.cancelled() => anyhow::bail!("Encoding cancelled"),
+        };
 
-    let variants_ref = get_variants_for_height(get_video_height(input.as_ref()).await?);
+        match result {
+            Ok(cues) => {
+                info!("Created WebVTT rendition {} ({} cues)", label, cues);
+            }
+            Err(e) => {
+                warn!("Skipping WebVTT rendition of subtitle track {}: {}", idx, e);
+                let _ = fs::remove_dir_all(&sub_dir).await;
+                continue;
+            }
+        }
 
-    // Add audio tracks as EXT-X-MEDIA entries
-    if !audio_streams.is_empty() {
-        for (idx, audio) in audio_streams.iter().enumerate() {
-            // Use same labeling logic as encoding to ensure consistency
-            let audio_label = if let Some(lang) = &audio.language {
-                format!("{}_{}", lang, idx)
+        let language = sub.language.as_deref().unwrap_or("und").to_string();
+        let name = sub.title.clone().unwrap_or_else(|| {
+            if language == "und" {
+                format!("Subtitles {}", idx + 1)
+            } else if sub.is_forced {
+                format!("{} (Forced)", get_language_display_name(&language))
             } else {
-                format!("track_{}", idx)
-            };
-            let language = audio.language.as_deref().unwrap_or("und");
-            let name = audio
-                .title
-                .clone()
-                .unwrap_or_else(|| {
-                    // For undefined/unknown languages, include track number to differentiate
-                    if language == "und" {
-                        format!("Audio Track {} ({})", idx + 1, audio.codec_name)
-                    } else {
-                        get_language_display_name(language)
-                    }
+                get_language_display_name(&language)
+            }
+        });
+        subtitle_media.push(SubtitleMedia {
+            language,
+            name,
+            is_default: sub.is_default,
+            is_forced: sub.is_forced,
+            label,
+        });
+    }
+
+    // At most one subtitle rendition may be the group's default
+    if let Some(first_default) = subtitle_media.iter().position(|media| media.is_default) {
+        for media in subtitle_media.iter_mut().skip(first_default + 1) {
+            media.is_default = false;
+        }
+    }
+
+    // Create master playlist with audio track support
+    let master_playlist_path = out_dir.join("index.m3u8");
+    // EXT-X-MAP in the fMP4 media playlists needs protocol version 7
+    let version = if outputs
+        .iter()
+        .any(|(codec, _)| codec.uses_fmp4(segment_format))
+    {
+        7
+    } else {
+        3
+    };
+    let mut master_content = format!("#EXTM3U\n#EXT-X-VERSION:{}\n\n", version);
+
+    // With CMAF packaging every rendition can be shared with a DASH manifest
+    let write_dash = segment_format == SegmentFormat::Fmp4;
+    let mut dash_videos = Vec::new();
+    let mut dash_audios = Vec::new();
+
+    // Audio groups: "audio" holds the stereo rendition of every track. If any track also has a
+    // 5.1 rendition, "audio-surround" holds those (falling back to stereo for the other tracks)
+    // and every video variant is listed a second time against it.
+    let mut stereo_group: Vec<AudioMedia> = Vec::new();
+    let mut surround_group: Vec<AudioMedia> = Vec::new();
+
+    for (idx, audio) in audio_streams.iter().enumerate() {
+        let language = audio.language.as_deref().unwrap_or("und").to_string();
+        let name = audio
+            .title
+            .clone()
+            .unwrap_or_else(|| {
+                // For undefined/unknown languages, include track number to differentiate
+                if language == "und" {
+                    format!("Audio Track {} ({})", idx + 1, audio.codec_name)
+                } else {
+                    get_language_display_name(&language)
+                }
+            });
+        let is_default = audio.is_default || idx == 0;
+
+        let mut track_renditions = audio_renditions(audio, idx, &config.audio).into_iter();
+        let Some(stereo) = track_renditions.next() else {
+            continue;
+        };
+        let surround = track_renditions.next();
+
+        if write_dash {
+            for (rendition, default) in
+                std::iter::once((&stereo, is_default)).chain(surround.iter().map(|r| (r, false)))
+            {
+                dash_audios.push(DashAudio {
+                    dir: rendition.name.clone(),
+                    language: language.clone(),
+                    codecs: rendition.codec_string().to_string(),
+                    bandwidth: rendition.bit_rate as u32,
+                    channels: rendition.channels as u32,
+                    is_default: default,
                 });
-            let is_default = if audio.is_default || idx == 0 {
-                "YES"
-            } else {
-                "NO"
-            };
-            let autoselect = if audio.is_default || idx == 0 {
-                "YES"
-            } else {
-                "NO"
-            };
+            }
+        }
 
-            master_content.push_str(&format!(
-                "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",LANGUAGE=\"{}\",NAME=\"{}\",DEFAULT={},AUTOSELECT={},URI=\"audio_{}/index.m3u8\"\n",
-                language,
-                name,
+        surround_group.push(match surround {
+            Some(surround) => AudioMedia {
+                language: language.clone(),
+                name: format!("{} (5.1)", name),
                 is_default,
-                autoselect,
-                audio_label
+                rendition: surround,
+            },
+            None => AudioMedia {
+                language: language.clone(),
+                name: name.clone(),
+                is_default,
+                rendition: stereo.clone(),
+            },
+        });
+        stereo_group.push(AudioMedia {
+            language,
+            name,
+            is_default,
+            rendition: stereo,
+        });
+    }
+
+    let has_surround = surround_group.iter().any(|media| media.rendition.channels > 2);
+    let mut audio_groups = Vec::new();
+    if !stereo_group.is_empty() {
+        audio_groups.push(("audio", stereo_group));
+    }
+    if has_surround {
+        audio_groups.push(("audio-surround", surround_group));
+    }
+
+    // Add audio renditions as EXT-X-MEDIA entries
+    for (group_id, group) in &audio_groups {
+        for media in group {
+            let default = if media.is_default { "YES" } else { "NO" };
+            master_content.push_str(&format!(
+                "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"{}\",LANGUAGE=\"{}\",NAME=\"{}\",DEFAULT={},AUTOSELECT={},CHANNELS=\"{}\",URI=\"{}/index.m3u8\"\n",
+                group_id,
+                media.language,
+                media.name,
+                default,
+                default,
+                media.rendition.channels,
+                media.rendition.name
             ));
         }
         master_content.push('\n');
     }
 
-    // Add video stream variants with audio group reference
-    for variant in &variants_ref {
-        let audio_group = if !audio_streams.is_empty() {
-            ",AUDIO=\"audio\""
+    // Subtitle renditions; DEFAULT or FORCED renditions must be AUTOSELECT, and so are tracks
+    // with a known language so clients can match them to the user's preferences
+    for media in &subtitle_media {
+        let yes_no = |flag: bool| if flag { "YES" } else { "NO" };
+        master_content.push_str(&format!(
+            "#EXT-X-MEDIA:TYPE=SUBTITLES,GROUP-ID=\"subs\",LANGUAGE=\"{}\",NAME=\"{}\",DEFAULT={},AUTOSELECT={},FORCED={},URI=\"{}/index.m3u8\"\n",
+            media.language,
+            media.name,
+            yes_no(media.is_default),
+            yes_no(media.is_default || media.is_forced || media.language != "und"),
+            yes_no(media.is_forced),
+            media.label
+        ));
+    }
+    if !subtitle_media.is_empty() {
+        master_content.push('\n');
+    }
+    let subtitle_group = if subtitle_media.is_empty() {
+        ""
+    } else {
+        ",SUBTITLES=\"subs\""
+    };
+
+    // Add video stream variants with audio group reference. H.264 renditions come first so
+    // legacy clients start on a stream they can decode; CODECS lets others pick HEVC/AV1.
+    let mut video_entries = Vec::new();
+    for (codec, _, variant, hdr) in &renditions {
+        let first_media = if codec.uses_fmp4(segment_format) {
+            out_dir.join(&variant.label).join("init.mp4")
         } else {
-            ""
+            out_dir.join(&variant.label).join("segment_000.ts")
         };
+        let video_codecs = match probe_codec_string(&first_media).await {
+            Some(codec_string) => codec_string,
+            None => {
+                warn!(
+                    "Could not probe codec of variant {}, using default codec string",
+                    variant.label
+                );
+                match hdr {
+                    Some(_) => "hvc1.2.4.L150.B0".to_string(),
+                    None => codec.default_codec_string().to_string(),
+                }
+            }
+        };
+        if write_dash {
+            dash_videos.push(DashVideo {
+                dir: variant.label.clone(),
+                // HDR and SDR renditions must not share an AdaptationSet
+                codec_family: if hdr.is_some() {
+                    "hvc1-hdr"
+                } else {
+                    codec.codec_family()
+                },
+                codecs: video_codecs.clone(),
+                bandwidth: variant.bandwidth(),
+                width: variant.width,
+                height: variant.height,
+                frame_rate,
+                transfer_characteristics: hdr.map(|hdr| hdr.cicp_transfer()),
+            });
+        }
+        video_entries.push((variant, video_codecs, hdr));
+    }
 
-        let stream_inf = format!(
-            "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{}{}\n",
-            variant.bandwidth(),
-            (((variant.height as f32) * 16.0) / 9.0) as u32,
-            variant.height,
-            audio_group
-        );
+    // Without audio the variants are listed once, otherwise once per audio group
+    let variant_groups: Vec<Option<&(&str, Vec<AudioMedia>)>> = if audio_groups.is_empty() {
+        vec![None]
+    } else {
+        audio_groups.iter().map(Some).collect()
+    };
 
-        master_content.push_str(&stream_inf);
-        master_content.push_str(&format!("{}/index.m3u8\n", variant.label));
+    for group in variant_groups {
+        for (variant, video_codecs, hdr) in &video_entries {
+            let mut codecs = video_codecs.clone();
+            let mut bandwidth = variant.bandwidth() as u64;
+            let mut audio_group = String::new();
+
+            if let Some((group_id, members)) = group {
+                // CODECS lists every audio codec of the group; BANDWIDTH covers the largest rendition
+                let mut audio_codecs: Vec<&str> = Vec::new();
+                for media in members {
+                    if !audio_codecs.contains(&media.rendition.codec_string()) {
+                        audio_codecs.push(media.rendition.codec_string());
+                    }
+                }
+                for audio_codec in audio_codecs {
+                    codecs.push(',');
+                    codecs.push_str(audio_codec);
+                }
+                bandwidth += members
+                    .iter()
+                    .map(|media| media.rendition.bit_rate as u64)
+                    .max()
+                    .unwrap_or(0);
+                audio_group = format!(",AUDIO=\"{}\"", group_id);
+            }
+
+            // VIDEO-RANGE defaults to SDR, so only the HDR rendition needs it
+            let video_range = hdr
+                .map(|hdr| format!(",VIDEO-RANGE={}", hdr.video_range()))
+                .unwrap_or_default();
+
+            let stream_inf = format!(
+                "#EXT-X-STREAM-INF:BANDWIDTH={},RESOLUTION={}x{},FRAME-RATE={:.3},CODECS=\"{}\"{}{}{}\n",
+                bandwidth,
+                variant.width,
+                variant.height,
+                frame_rate,
+                codecs,
+                video_range,
+                audio_group,
+                subtitle_group
+            );
+
+            master_content.push_str(&stream_inf);
+            master_content.push_str(&format!("{}/index.m3u8\n", variant.label));
+        }
     }
 
     fs::write(&master_playlist_path, master_content)
         .await
         .context("failed to write master playlist")?;
 
-    Ok(())
+    if write_dash {
+        write_dash_manifest(out_dir.as_ref(), duration, &dash_videos, &dash_audios).await?;
+    }
+
+    Ok(loudness)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(width: u32, height: u32, sample_aspect_ratio: f64, rotation: u32) -> VideoMetadata {
+        VideoMetadata {
+            width,
+            height,
+            rotation,
+            sample_aspect_ratio,
+            display_aspect_ratio: width as f64 * sample_aspect_ratio / height as f64,
+            frame_rate: 30.0,
+            duration: 60,
+            color_transfer: None,
+            color_primaries: None,
+            color_space: None,
+            field_order: None,
+            interlaced: None,
+            crop: None,
+        }
+    }
+
+    fn config(yaml: &str) -> VideoConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    fn sizes(variants: &[VideoVariant]) -> Vec<(&str, u32, u32)> {
+        variants
+            .iter()
+            .map(|v| (v.label.as_str(), v.width, v.height))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_ladder_landscape() {
+        let variants =
+            get_variants_for_source(&source(1920, 1080, 1.0, 0), &config("encoder: libx264"));
+        assert_eq!(
+            sizes(&variants),
+            vec![
+                ("480p", 854, 480),
+                ("720p", 1280, 720),
+                ("1080p", 1920, 1080)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_ladder_portrait() {
+        let expected = vec![
+            ("480p", 480, 854),
+            ("720p", 720, 1280),
+            ("1080p", 1080, 1920),
+        ];
+        let config = config("encoder: libx264");
+
+        let variants = get_variants_for_source(&source(1080, 1920, 1.0, 0), &config);
+        assert_eq!(sizes(&variants), expected);
+        // A landscape frame with a rotation tag displays as portrait
+        let variants = get_variants_for_source(&source(1920, 1080, 1.0, 90), &config);
+        assert_eq!(sizes(&variants), expected);
+    }
+
+    #[test]
+    fn test_default_ladder_anamorphic() {
+        // 16:9 DVD: 720x480 stored, 853x480 displayed
+        let variants = get_variants_for_source(
+            &source(720, 480, 32.0 / 27.0, 0),
+            &config("encoder: libx264"),
+        );
+        assert_eq!(sizes(&variants), vec![("480p", 854, 480)]);
+    }
+
+    #[test]
+    fn test_odd_source_keeps_rungs_below_short_edge() {
+        let variants =
+            get_variants_for_source(&source(1281, 719, 1.0, 0), &config("encoder: libx264"));
+        assert_eq!(sizes(&variants), vec![("480p", 856, 480)]);
+    }
+
+    #[test]
+    fn test_small_source_gets_single_even_rendition() {
+        let variants =
+            get_variants_for_source(&source(425, 239, 1.0, 0), &config("encoder: libx264"));
+        assert_eq!(sizes(&variants), vec![("240p", 426, 240)]);
+        // The BPP bitrate is clamped to its 500 kbps floor
+        assert_eq!(variants[0].bitrate, 500);
+        assert_eq!(variants[0].maxrate, 750);
+    }
+
+    #[test]
+    fn test_small_source_scales_configured_rates() {
+        let config = config(
+            "encoder: libx264\nladder:\n  - label: 720p\n    height: 720\n    bitrate: 3000\n",
+        );
+        let variants = get_variants_for_source(&source(640, 360, 1.0, 0), &config);
+        assert_eq!(sizes(&variants), vec![("360p", 640, 360)]);
+        assert_eq!(variants[0].bitrate, 750);
+        assert_eq!(variants[0].maxrate, 1125);
+    }
+
+    #[test]
+    fn test_preview_moments_short_sources() {
+        assert_eq!(preview_moments(0), vec![(0.0, 1.0)]);
+        assert_eq!(preview_moments(3), vec![(0.0, 3.0)]);
+        // Too short for five separate moments: one clip of the full preview length
+        assert_eq!(preview_moments(14), vec![(0.0, 7.5)]);
+    }
+
+    #[test]
+    fn test_preview_moments_evenly_spaced() {
+        assert_eq!(
+            preview_moments(60),
+            vec![
+                (9.25, 1.5),
+                (19.25, 1.5),
+                (29.25, 1.5),
+                (39.25, 1.5),
+                (49.25, 1.5)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_preview_moments_stay_inside_the_source() {
+        for duration in [15, 16, 31, 599, 7200] {
+            let moments = preview_moments(duration);
+            assert_eq!(moments.len(), PREVIEW_CLIPS as usize);
+            assert!(moments[0].0 > 0.0);
+            let (last_start, last_length) = moments[moments.len() - 1];
+            assert!(
+                last_start + last_length < duration as f64,
+                "duration {}",
+                duration
+            );
+            for pair in moments.windows(2) {
+                assert!(pair[0].0 + pair[0].1 <= pair[1].0, "duration {}", duration);
+            }
+        }
+    }
+
+    #[test]
+    fn test_preview_size_follows_display_aspect() {
+        assert_eq!(preview_size(&source(1920, 1080, 1.0, 0)), (480, 270));
+        // 16:9 DVD stored as 720x480
+        assert_eq!(preview_size(&source(720, 480, 32.0 / 27.0, 0)), (480, 270));
+        // Phone footage stored landscape with a rotation tag
+        assert_eq!(preview_size(&source(1920, 1080, 1.0, 90)), (480, 854));
+    }
+
+    #[test]
+    fn test_bpp_bitrate_is_clamped() {
+        assert_eq!(VideoVariant::calculate_bitrate(7680, 4320, 60.0), 20000);
+        assert_eq!(VideoVariant::calculate_bitrate(320, 180, 24.0), 500);
+    }
 }
@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::path::Path;
+use tokio::fs;
+
+/// MPEG-TS clock rate used by X-TIMESTAMP-MAP
+const MPEGTS_CLOCK: f64 = 90000.0;
+
+/// One cue of a WebVTT or SRT file; times are in milliseconds
+pub struct Cue {
+    pub start: u64,
+    pub end: u64,
+    /// Cue settings after the timing (position, align, ...)
+    pub settings: String,
+    pub payload: String,
+}
+
+/// Split the WebVTT file `source` into segments of `segment_duration` seconds inside `dir` and
+/// write a VOD media playlist (`index.m3u8`) for them. Cues spanning a segment boundary are
+/// repeated in both segments, as HLS clients expect. `start_time` is the presentation time (in
+/// seconds) of the first video sample, so cues line up with the video segments.
+pub async fn write_webvtt_playlist(
+    source: &Path,
+    dir: &Path,
+    duration: u32,
+    segment_duration: u32,
+    start_time: f64,
+) -> Result<usize> {
+    let content = fs::read_to_string(source)
+        .await
+        .with_context(|| format!("failed to read {:?}", source))?;
+    let cues = parse_cues(&content);
+
+    let segment_ms = segment_duration.max(1) as u64 * 1000;
+    let duration_ms = (duration as u64 * 1000).max(cues.iter().map(|c| c.end).max().unwrap_or(0));
+    let segment_count = duration_ms.div_ceil(segment_ms).max(1);
+    let header = format!(
+        "WEBVTT\nX-TIMESTAMP-MAP=MPEGTS:{},LOCAL:00:00:00.000\n\n",
+        (start_time * MPEGTS_CLOCK).round() as u64
+    );
+
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+        segment_duration.max(1)
+    );
+
+    for index in 0..segment_count {
+        let window_start = index * segment_ms;
+        let window_end = ((index + 1) * segment_ms).min(duration_ms);
+
+        let mut segment = header.clone();
+        for cue in cues
+            .iter()
+            .filter(|c| c.start < window_end && c.end > window_start)
+        {
+            let _ = write!(
+                segment,
+                "{} --> {}{}\n{}\n\n",
+                format_timestamp(cue.start),
+                format_timestamp(cue.end),
+                cue.settings,
+                cue.payload
+            );
+        }
+
+        let name = format!("segment_{:03}.vtt", index);
+        fs::write(dir.join(&name), segment)
+            .await
+            .with_context(|| format!("failed to write {}", name))?;
+
+        let _ = writeln!(
+            playlist,
+            "#EXTINF:{:.3},\n{}",
+            (window_end - window_start) as f64 / 1000.0,
+            name
+        );
+    }
+
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    fs::write(dir.join("index.m3u8"), playlist)
+        .await
+        .context("failed to write subtitle playlist")?;
+
+    Ok(cues.len())
+}
+
+/// Parse the cues of a WebVTT file, skipping the header and NOTE/STYLE/REGION blocks. SRT
+/// files parse the same way: their counters are read as cue identifiers.
+pub fn parse_cues(content: &str) -> Vec<Cue> {
+    let content = content.replace("\r\n", "\n");
+    let mut cues = Vec::new();
+
+    for block in content.split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| line.trim().is_empty());
+        let Some(mut timing) = lines.next() else {
+            continue;
+        };
+        // Cues may start with an identifier line
+        if !timing.contains("-->") {
+            match lines.next() {
+                Some(line) if line.contains("-->") => timing = line,
+                _ => continue,
+            }
+        }
+
+        let Some((start, rest)) = timing.split_once("-->") else {
+            continue;
+        };
+        let rest = rest.trim_start();
+        let (end, settings) = match rest.find(char::is_whitespace) {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, ""),
+        };
+        let (Some(start), Some(end)) = (parse_timestamp(start.trim()), parse_timestamp(end)) else {
+            continue;
+        };
+
+        let payload = lines.collect::<Vec<_>>().join("\n");
+        if payload.trim().is_empty() || end <= start {
+            continue;
+        }
+
+        cues.push(Cue {
+            start,
+            end,
+            settings: settings.trim_end().to_string(),
+            payload,
+        });
+    }
+
+    cues
+}
+
+/// Parse `hh:mm:ss.mmm` or `mm:ss.mmm` (SRT's `hh:mm:ss,mmm` too) into milliseconds
+pub fn parse_timestamp(value: &str) -> Option<u64> {
+    let (clock, fraction) = value.split_once(['.', ','])?;
+    if fraction.is_empty() || fraction.len() > 3 {
+        return None;
+    }
+    // Some SRT writers emit fewer than three fraction digits
+    let millis = fraction.parse::<u64>().ok()? * 10u64.pow(3 - fraction.len() as u32);
+    let parts: Vec<u64> = clock
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+
+    let seconds = match parts.as_slice() {
+        [h, m, s] => h * 3600 + m * 60 + s,
+        [m, s] => m * 60 + s,
+        _ => return None,
+    };
+
+    Some(seconds * 1000 + millis)
+}
+
+pub fn format_timestamp(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}